@@ -0,0 +1,178 @@
+use std::collections::HashSet;
+
+use crate::csvql::query::ast;
+use crate::csvql::query::value;
+use crate::csvql::query::error;
+
+// The running state of an aggregate over the rows of a group
+#[derive(Debug, Clone)]
+pub enum Accumulator {
+  Count(i64),
+  CountDistinct(HashSet<value::Value>),
+  Sum(Option<value::Value>),
+  Min(Option<value::Value>),
+  Max(Option<value::Value>),
+  Avg(f64, i64),
+}
+
+impl Accumulator {
+  pub fn new(agg: &ast::Aggregate) -> Result<Accumulator, error::Error> {
+    if agg.arg.is_none() && agg.func != ast::Function::Count {
+      return Err(error::QueryError::new(&format!("Only count accepts '*': {}", agg)).into());
+    }
+    if agg.distinct && agg.func != ast::Function::Count {
+      return Err(error::QueryError::new(&format!("Only count accepts 'distinct': {}", agg)).into());
+    }
+    Ok(match agg.func {
+      ast::Function::Count => if agg.distinct {
+        Self::CountDistinct(HashSet::new())
+      }else{
+        Self::Count(0)
+      },
+      ast::Function::Sum => Self::Sum(None),
+      ast::Function::Min => Self::Min(None),
+      ast::Function::Max => Self::Max(None),
+      ast::Function::Avg => Self::Avg(0.0, 0),
+    })
+  }
+  
+  // The type of the value produced by an aggregate over a column of the
+  // specified type. Sums of integers are promoted to decimals when they
+  // overflow, and fields of columns which are not numeric may be any kind
+  // of number, so their sum is a float, which can represent each.
+  pub fn result_type(agg: &ast::Aggregate, typ: value::Type) -> value::Type {
+    match agg.func {
+      ast::Function::Count => value::Type::Integer,
+      ast::Function::Avg => value::Type::Float,
+      ast::Function::Sum => match typ {
+        value::Type::Integer | value::Type::Decimal => value::Type::Decimal,
+        _ => value::Type::Float,
+      },
+      ast::Function::Min | ast::Function::Max => typ,
+    }
+  }
+  
+  // Update this accumulator with a value. For count(*) the value is
+  // irrelevant and every row is counted; otherwise nulls are ignored.
+  pub fn update(&mut self, val: value::Value, all: bool) -> Result<(), error::Error> {
+    if val.is_null() && !all {
+      return Ok(());
+    }
+    match self {
+      Self::Count(n) => *n += 1,
+      Self::CountDistinct(set) => {
+        set.insert(val);
+      },
+      Self::Sum(sum) => {
+        let val = numeric(&val)?;
+        *sum = Some(match sum {
+          Some(sum) => match sum.add(&val) {
+            Some(sum) => sum,
+            None => return Err(error::FrameError::new(&format!("Sum overflows: {} + {}", sum, val)).into()),
+          },
+          None => val,
+        });
+      },
+      Self::Min(min) => match min {
+        Some(min) if *min <= val => {},
+        _ => *min = Some(val),
+      },
+      Self::Max(max) => match max {
+        Some(max) if *max >= val => {},
+        _ => *max = Some(val),
+      },
+      Self::Avg(sum, n) => {
+        *sum += numeric(&val)?.to_f64().unwrap_or_default();
+        *n += 1;
+      },
+    };
+    Ok(())
+  }
+  
+  // The final value of this accumulator
+  pub fn value(&self) -> value::Value {
+    match self {
+      Self::Count(n) => value::Value::Integer(*n),
+      Self::CountDistinct(set) => value::Value::Integer(set.len() as i64),
+      Self::Sum(sum) | Self::Min(sum) | Self::Max(sum) => match sum {
+        Some(sum) => sum.clone(),
+        None => value::Value::Null,
+      },
+      Self::Avg(sum, n) => if *n > 0 {
+        value::Value::Float(sum / *n as f64)
+      }else{
+        value::Value::Null
+      },
+    }
+  }
+}
+
+fn numeric(val: &value::Value) -> Result<value::Value, error::Error> {
+  match val.numeric() {
+    Some(val) => Ok(val),
+    None => Err(error::FrameError::new(&format!("Expected a numeric value: {:?}", val.to_string())).into()),
+  }
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+  use crate::csvql::query::parser;
+  
+  fn aggregate(text: &str) -> ast::Aggregate {
+    parser::parse_aggregates(text).unwrap().remove(0)
+  }
+  
+  // Aggregate fields of the specified type, checking that the result can
+  // be represented by the declared result type
+  fn eval(text: &str, typ: value::Type, fields: &[&str]) -> Result<String, error::Error> {
+    let agg = aggregate(text);
+    let mut acc = Accumulator::new(&agg)?;
+    for field in fields {
+      acc.update(typ.value(field)?, agg.arg.is_none())?;
+    }
+    let res = acc.value().to_string();
+    Accumulator::result_type(&agg, typ).value(&res)?;
+    Ok(res)
+  }
+  
+  #[test]
+  fn count() {
+    assert_eq!(eval("count(*)", value::Type::String, &["a", "", "b"]).unwrap(), "3");
+    assert_eq!(eval("count(a.x)", value::Type::String, &["a", "", "b"]).unwrap(), "2");
+    assert_eq!(eval("count(distinct a.x)", value::Type::String, &["a", "", "b", "a"]).unwrap(), "2");
+    assert_eq!(eval("count(a.x)", value::Type::String, &[]).unwrap(), "0");
+  }
+  
+  #[test]
+  fn sum() {
+    assert_eq!(eval("sum(a.x)", value::Type::Integer, &["1", "", "2"]).unwrap(), "3");
+    assert_eq!(eval("sum(a.x)", value::Type::Decimal, &["1.50", "2.25"]).unwrap(), "3.75");
+    assert_eq!(eval("sum(a.x)", value::Type::Integer, &["9223372036854775807", "1"]).unwrap(), "9223372036854775808");
+    assert_eq!(eval("sum(a.x)", value::Type::Integer, &[]).unwrap(), "");
+  }
+  
+  #[test]
+  fn sum_untyped() {
+    assert_eq!(eval("sum(a.x)", value::Type::String, &["1", "2.5"]).unwrap(), "3.5");
+    assert_eq!(eval("sum(a.x)", value::Type::String, &["1", "1e3"]).unwrap(), "1001");
+    assert!(eval("sum(a.x)", value::Type::String, &["1", "inf"]).is_err());
+    assert!(eval("sum(a.x)", value::Type::String, &["1", "NaN"]).is_err());
+    assert!(eval("sum(a.x)", value::Type::String, &["1", "x"]).is_err());
+  }
+  
+  #[test]
+  fn min_max_avg() {
+    assert_eq!(eval("min(a.x)", value::Type::Integer, &["3", "", "10", "2"]).unwrap(), "2");
+    assert_eq!(eval("max(a.x)", value::Type::Integer, &["3", "", "10", "2"]).unwrap(), "10");
+    assert_eq!(eval("max(a.x)", value::Type::String, &["3", "10", "2"]).unwrap(), "3");
+    assert_eq!(eval("avg(a.x)", value::Type::Integer, &["1", "", "2"]).unwrap(), "1.5");
+    assert_eq!(eval("avg(a.x)", value::Type::Integer, &[]).unwrap(), "");
+  }
+  
+  #[test]
+  fn invalid() {
+    assert!(Accumulator::new(&aggregate("sum(*)")).is_err());
+    assert!(Accumulator::new(&aggregate("sum(distinct a.x)")).is_err());
+  }
+}
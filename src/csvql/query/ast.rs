@@ -0,0 +1,510 @@
+use std::fmt;
+use std::cmp;
+
+use crate::csvql::query::schema;
+use crate::csvql::query::value;
+use crate::csvql::query::error;
+
+// An aggregate function
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Function {
+  Count,
+  Sum,
+  Min,
+  Max,
+  Avg,
+}
+
+impl fmt::Display for Function {
+  fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+    match self {
+      Self::Count => write!(f, "count"),
+      Self::Sum => write!(f, "sum"),
+      Self::Min => write!(f, "min"),
+      Self::Max => write!(f, "max"),
+      Self::Avg => write!(f, "avg"),
+    }
+  }
+}
+
+// An aggregate computed over a group of rows; a missing argument
+// represents '*', which counts every row.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Aggregate {
+  pub func: Function,
+  pub arg: Option<schema::QName>,
+  pub distinct: bool,
+}
+
+impl Aggregate {
+  // The name of the column this aggregate produces
+  pub fn qname(&self) -> schema::QName {
+    schema::QName::new_unscoped(&self.to_string())
+  }
+}
+
+impl fmt::Display for Aggregate {
+  fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+    write!(f, "{}(", self.func)?;
+    if self.distinct {
+      write!(f, "distinct ")?;
+    }
+    match &self.arg {
+      Some(arg) => write!(f, "{})", arg),
+      None => write!(f, "*)"),
+    }
+  }
+}
+
+// A pattern which matches columns by name: every column, optionally of
+// one input, or those whose unqualified name matches a regular expression
+#[derive(Debug, Clone, PartialEq)]
+pub enum Pattern {
+  All(Option<String>),
+  Regex(Option<String>, String),
+}
+
+#[allow(clippy::needless_lifetimes)]
+impl Pattern {
+  pub fn scope<'a>(&'a self) -> Option<&'a str> {
+    match self {
+      Self::All(scope) | Self::Regex(scope, _) => scope.as_deref(),
+    }
+  }
+}
+
+impl fmt::Display for Pattern {
+  fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+    if let Some(scope) = self.scope() {
+      write!(f, "{}.", scope)?;
+    }
+    match self {
+      Self::All(_) => write!(f, "*"),
+      Self::Regex(_, regex) => write!(f, "/{}/", regex.replace('/', "\\/")),
+    }
+  }
+}
+
+// A selection of the columns which match a pattern, except those which
+// are excluded by name or by pattern, as in: * EXCEPT (a.secret, /^internal_/)
+#[derive(Debug, Clone, PartialEq)]
+pub struct Wildcard {
+  pub pattern: Pattern,
+  pub except: Vec<Exclusion>,
+}
+
+impl Wildcard {
+  // Whether this selects every column
+  pub fn is_all(&self) -> bool {
+    self.pattern == Pattern::All(None) && self.except.is_empty()
+  }
+}
+
+impl fmt::Display for Wildcard {
+  fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+    write!(f, "{}", self.pattern)?;
+    if !self.except.is_empty() {
+      write!(f, " EXCEPT ({})", self.except.iter().map(|e| { e.to_string() }).collect::<Vec<String>>().join(", "))?;
+    }
+    Ok(())
+  }
+}
+
+// Columns excluded from a wildcard
+#[derive(Debug, Clone, PartialEq)]
+pub enum Exclusion {
+  Name(schema::QName),
+  Pattern(Pattern),
+}
+
+impl fmt::Display for Exclusion {
+  fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+    match self {
+      Self::Name(qname) => write!(f, "{}", qname),
+      Self::Pattern(pattern) => write!(f, "{}", pattern),
+    }
+  }
+}
+
+// A column selected by a query
+#[derive(Debug, Clone, PartialEq)]
+pub enum Column {
+  All(Wildcard),
+  Name(schema::QName),
+  Aggregate(Aggregate, Option<String>), // an aggregate, optionally named by an alias
+  Expr(Projection),
+}
+
+impl fmt::Display for Column {
+  fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+    match self {
+      Self::All(wildcard) => write!(f, "{}", wildcard),
+      Self::Name(qname) => write!(f, "{}", qname),
+      Self::Aggregate(agg, Some(alias)) => write!(f, "{} AS {}", agg, alias),
+      Self::Aggregate(agg, None) => write!(f, "{}", agg),
+      Self::Expr(proj) => write!(f, "{}", proj),
+    }
+  }
+}
+
+// An expression which computes a column, optionally named by an alias
+#[derive(Debug, Clone, PartialEq)]
+pub struct Projection {
+  pub expr: Expr,
+  pub alias: Option<String>,
+}
+
+impl Projection {
+  // The name of the column this projection produces: its alias, the
+  // column it selects, or otherwise the text of its expression
+  pub fn qname(&self) -> schema::QName {
+    match (&self.alias, &self.expr) {
+      (Some(alias), _) => schema::QName::new_unscoped(alias),
+      (None, Expr::Column(qname)) => qname.clone(),
+      (None, expr) => schema::QName::new_unscoped(&expr.to_string()),
+    }
+  }
+}
+
+impl fmt::Display for Projection {
+  fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+    match &self.alias {
+      Some(alias) => write!(f, "{} AS {}", self.expr, alias),
+      None => write!(f, "{}", self.expr),
+    }
+  }
+}
+
+// The kind of a join, which determines the rows it produces: inner joins
+// produce only matching pairs of rows; left, right and full joins also
+// produce unmatched rows from one or both sides; semi and anti joins
+// produce the left rows which do or do not have a match, respectively.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum JoinKind {
+  Inner,
+  Left,
+  Right,
+  Full,
+  Semi,
+  Anti,
+}
+
+impl JoinKind {
+  pub fn parse(text: &str) -> Result<JoinKind, error::Error> {
+    match text.trim().to_lowercase().as_ref() {
+      "inner" => Ok(Self::Inner),
+      "left" => Ok(Self::Left),
+      "right" => Ok(Self::Right),
+      "full" | "outer" => Ok(Self::Full),
+      "semi" => Ok(Self::Semi),
+      "anti" => Ok(Self::Anti),
+      _ => Err(error::ParseError::new(&format!("Invalid join type, expected one of inner, left, right, full, semi or anti: {}", text)).into()),
+    }
+  }
+  
+  // Whether left rows without a match are produced
+  pub fn keeps_left(&self) -> bool {
+    matches!(self, Self::Left | Self::Full)
+  }
+  
+  // Whether right rows without a match are produced
+  pub fn keeps_right(&self) -> bool {
+    matches!(self, Self::Right | Self::Full)
+  }
+  
+  // Whether this join only filters the left rows, in which case it
+  // produces only the columns of the left side
+  pub fn filters(&self) -> bool {
+    matches!(self, Self::Semi | Self::Anti)
+  }
+}
+
+impl fmt::Display for JoinKind {
+  fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+    match self {
+      Self::Inner => write!(f, "INNER"),
+      Self::Left => write!(f, "LEFT"),
+      Self::Right => write!(f, "RIGHT"),
+      Self::Full => write!(f, "FULL"),
+      Self::Semi => write!(f, "SEMI"),
+      Self::Anti => write!(f, "ANTI"),
+    }
+  }
+}
+
+// An as-of join, which pairs each left row with the right row whose value
+// in the last joining column is the greatest at or below the left value,
+// optionally by no more than a tolerance
+#[derive(Debug, Clone, PartialEq)]
+pub struct AsOf {
+  pub tolerance: Option<value::Tolerance>,
+}
+
+// A join against another source, on one or more pairs of columns which
+// must be equal. A join with USING merges each pair into a single column.
+// An as-of join relates its last pair by inequality instead, left >= right.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Join {
+  pub kind: JoinKind,
+  pub source: String,
+  pub on: Vec<(schema::QName, schema::QName)>,
+  pub using: bool,
+  pub normalize: Vec<value::Normalizer>,
+  pub asof: Option<AsOf>,
+}
+
+impl fmt::Display for Join {
+  fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+    let asof = if self.asof.is_some() { " ASOF" } else { "" };
+    if self.using {
+      write!(f, "{}{} JOIN {} USING ({})", self.kind, asof, self.source, self.on.iter().map(|e| { e.1.name() }).collect::<Vec<&str>>().join(", "))?;
+    }else{
+      write!(f, "{}{} JOIN {} ON {}", self.kind, asof, self.source, self.on.iter().enumerate().map(|(n, (a, b))| {
+        if self.asof.is_some() && n + 1 == self.on.len() { format!("{} >= {}", a, b) } else { format!("{} = {}", a, b) }
+      }).collect::<Vec<String>>().join(" AND "))?;
+    }
+    if let Some(AsOf{tolerance: Some(tolerance)}) = &self.asof {
+      write!(f, " TOLERANCE '{}'", tolerance)?;
+    }
+    if !self.normalize.is_empty() {
+      write!(f, " NORMALIZE ({})", self.normalize.iter().map(|e| { e.to_string() }).collect::<Vec<String>>().join(", "))?;
+    }
+    Ok(())
+  }
+}
+
+// A sort direction
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Direction {
+  Asc,
+  Desc,
+}
+
+// The position of nulls in a sort order
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Nulls {
+  First,
+  Last,
+}
+
+// A sort key. Unless specified otherwise, nulls sort as if they were
+// smaller than any other value: first when ascending, last when descending.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Order {
+  pub on: schema::QName,
+  pub direction: Direction,
+  pub nulls: Option<Nulls>,
+}
+
+#[allow(clippy::redundant_field_names)]
+impl Order {
+  pub fn new(on: schema::QName) -> Order {
+    Order{
+      on: on,
+      direction: Direction::Asc,
+      nulls: None,
+    }
+  }
+  
+  pub fn nulls(&self) -> Nulls {
+    match (self.nulls, self.direction) {
+      (Some(nulls), _) => nulls,
+      (None, Direction::Asc) => Nulls::First,
+      (None, Direction::Desc) => Nulls::Last,
+    }
+  }
+  
+  // Compare two values according to this sort key
+  pub fn compare(&self, a: &value::Value, b: &value::Value) -> cmp::Ordering {
+    let nulls = match self.nulls() {
+      Nulls::First => cmp::Ordering::Less,
+      Nulls::Last => cmp::Ordering::Greater,
+    };
+    match (a.is_null(), b.is_null()) {
+      (true, true) => cmp::Ordering::Equal,
+      (true, false) => nulls,
+      (false, true) => nulls.reverse(),
+      (false, false) => match self.direction {
+        Direction::Asc => a.cmp(b),
+        Direction::Desc => a.cmp(b).reverse(),
+      },
+    }
+  }
+  
+  // Compare two tuples of values according to a sequence of sort keys
+  pub fn compare_all(order: &[Order], a: &[value::Value], b: &[value::Value]) -> cmp::Ordering {
+    for (o, (a, b)) in order.iter().zip(a.iter().zip(b)) {
+      let cmp = o.compare(a, b);
+      if cmp != cmp::Ordering::Equal {
+        return cmp;
+      }
+    }
+    cmp::Ordering::Equal
+  }
+}
+
+impl fmt::Display for Order {
+  fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+    write!(f, "{}", self.on)?;
+    if self.direction == Direction::Desc {
+      write!(f, " DESC")?;
+    }
+    match self.nulls {
+      Some(Nulls::First) => write!(f, " NULLS FIRST"),
+      Some(Nulls::Last) => write!(f, " NULLS LAST"),
+      None => Ok(()),
+    }
+  }
+}
+
+// A literal value
+#[derive(Debug, Clone, PartialEq)]
+pub enum Literal {
+  String(String),
+  Number(String),
+}
+
+impl fmt::Display for Literal {
+  fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+    match self {
+      Self::String(v) => write!(f, "'{}'", v.replace('\'', "''")),
+      Self::Number(v) => write!(f, "{}", v),
+    }
+  }
+}
+
+// A comparison operator
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Operator {
+  Eq,
+  Ne,
+  Lt,
+  Le,
+  Gt,
+  Ge,
+}
+
+impl fmt::Display for Operator {
+  fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+    match self {
+      Self::Eq => write!(f, "="),
+      Self::Ne => write!(f, "!="),
+      Self::Lt => write!(f, "<"),
+      Self::Le => write!(f, "<="),
+      Self::Gt => write!(f, ">"),
+      Self::Ge => write!(f, ">="),
+    }
+  }
+}
+
+// An arithmetic operator
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Arith {
+  Add,
+  Sub,
+  Mul,
+  Div,
+  Rem,
+}
+
+impl fmt::Display for Arith {
+  fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+    match self {
+      Self::Add => write!(f, "+"),
+      Self::Sub => write!(f, "-"),
+      Self::Mul => write!(f, "*"),
+      Self::Div => write!(f, "/"),
+      Self::Rem => write!(f, "%"),
+    }
+  }
+}
+
+// An expression
+#[derive(Debug, Clone, PartialEq)]
+pub enum Expr {
+  Column(schema::QName),
+  Literal(Literal),
+  Arith(Box<Expr>, Arith, Box<Expr>),
+  Neg(Box<Expr>),
+  Call(String, Vec<Expr>),
+  Compare(Box<Expr>, Operator, Box<Expr>),
+  IsEmpty(Box<Expr>),
+  In(Box<Expr>, Vec<Expr>),
+  Like(Box<Expr>, Box<Expr>),
+  And(Box<Expr>, Box<Expr>),
+  Or(Box<Expr>, Box<Expr>),
+  Not(Box<Expr>),
+}
+
+impl Expr {
+  // Format an operand of arithmetic; nested arithmetic is parenthesized,
+  // so precedence is explicit
+  fn nested(&self) -> String {
+    match self {
+      Self::Arith(..) | Self::Neg(_) => format!("({})", self),
+      expr => expr.to_string(),
+    }
+  }
+}
+
+impl fmt::Display for Expr {
+  fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+    match self {
+      Self::Column(qname) => write!(f, "{}", qname),
+      Self::Literal(lit) => write!(f, "{}", lit),
+      Self::Arith(lhs, op, rhs) => write!(f, "{} {} {}", lhs.nested(), op, rhs.nested()),
+      Self::Neg(expr) => write!(f, "-{}", expr.nested()),
+      Self::Call(name, args) => {
+        let args: Vec<String> = args.iter().map(|e| { e.to_string() }).collect();
+        write!(f, "{}({})", name, args.join(", "))
+      },
+      Self::Compare(lhs, op, rhs) => write!(f, "{} {} {}", lhs, op, rhs),
+      Self::IsEmpty(expr) => write!(f, "{} IS EMPTY", expr),
+      Self::In(expr, set) => {
+        let set: Vec<String> = set.iter().map(|e| { e.to_string() }).collect();
+        write!(f, "{} IN ({})", expr, set.join(", "))
+      },
+      Self::Like(expr, pattern) => write!(f, "{} LIKE {}", expr, pattern),
+      Self::And(lhs, rhs) => write!(f, "({} AND {})", lhs, rhs),
+      Self::Or(lhs, rhs) => write!(f, "({} OR {})", lhs, rhs),
+      Self::Not(expr) => write!(f, "NOT {}", expr),
+    }
+  }
+}
+
+// A select query
+#[derive(Debug, Clone, PartialEq)]
+pub struct Select {
+  pub columns: Vec<Column>,
+  pub from: String,
+  pub joins: Vec<Join>,
+  pub filter: Option<Expr>,
+  pub group: Vec<schema::QName>,
+  pub order: Vec<Order>,
+  pub limit: Option<usize>,
+}
+
+impl fmt::Display for Select {
+  fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+    let cols: Vec<String> = self.columns.iter().map(|e| { e.to_string() }).collect();
+    write!(f, "SELECT {} FROM {}", cols.join(", "), self.from)?;
+    for join in &self.joins {
+      write!(f, " {}", join)?;
+    }
+    if let Some(filter) = &self.filter {
+      write!(f, " WHERE {}", filter)?;
+    }
+    if !self.group.is_empty() {
+      let keys: Vec<String> = self.group.iter().map(|e| { e.to_string() }).collect();
+      write!(f, " GROUP BY {}", keys.join(", "))?;
+    }
+    if !self.order.is_empty() {
+      let keys: Vec<String> = self.order.iter().map(|e| { e.to_string() }).collect();
+      write!(f, " ORDER BY {}", keys.join(", "))?;
+    }
+    if let Some(limit) = self.limit {
+      write!(f, " LIMIT {}", limit)?;
+    }
+    Ok(())
+  }
+}
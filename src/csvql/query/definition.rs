@@ -0,0 +1,308 @@
+use std::fs;
+use std::path;
+
+use csv;
+use serde::Deserialize;
+
+use crate::csvql::query::schema;
+use crate::csvql::query::value;
+use crate::csvql::query::error;
+
+// A scalar in a definition file, which is interpreted according to the
+// type of the column it belongs to
+#[derive(Debug, Clone, Deserialize)]
+#[serde(untagged)]
+enum Scalar {
+  String(String),
+  Integer(i64),
+  Float(f64),
+  Boolean(bool),
+}
+
+impl Scalar {
+  fn text(&self) -> String {
+    match self {
+      Self::String(v) => v.to_owned(),
+      Self::Integer(v) => v.to_string(),
+      Self::Float(v) => v.to_string(),
+      Self::Boolean(v) => v.to_string(),
+    }
+  }
+}
+
+#[derive(Debug, Clone, Deserialize)]
+#[serde(deny_unknown_fields)]
+struct ColumnFile {
+  name: String,
+  header: Option<String>,
+  #[serde(rename="type")]
+  typ: Option<String>,
+  #[serde(default)]
+  required: bool,
+  default: Option<Scalar>,
+}
+
+#[derive(Debug, Clone, Deserialize)]
+#[serde(deny_unknown_fields)]
+struct DefinitionFile {
+  columns: Vec<ColumnFile>,
+}
+
+// A column declared by a definition
+#[derive(Debug, Clone)]
+pub struct Column {
+  name: String,
+  header: Option<String>,
+  typ: Option<value::Type>,
+  required: bool,
+  default: Option<String>,
+}
+
+// A schema definition, which declares the columns of an input in order:
+// their names, which override the input's header, and optionally their
+// types, whether they are required, and default values to substitute
+// for empty fields.
+#[derive(Debug, Clone)]
+pub struct Definition {
+  columns: Vec<Column>,
+}
+
+#[allow(clippy::needless_lifetimes, clippy::redundant_field_names)]
+impl Definition {
+  // Load a definition from a TOML or JSON file, depending on its extension
+  pub fn load(path: &str) -> Result<Definition, error::Error> {
+    let text = fs::read_to_string(path)?;
+    let ext = path::Path::new(path).extension().and_then(|e| { e.to_str() }).map(|e| { e.to_lowercase() });
+    match Self::parse(&text, ext.as_deref() == Some("json")) {
+      Ok(def) => Ok(def),
+      Err(err) => Err(error::ParseError::new(&format!("Invalid schema definition: {}: {}", path, err)).into()),
+    }
+  }
+  
+  // Parse a definition from the text of a JSON file, or otherwise a TOML file
+  pub fn parse(text: &str, json: bool) -> Result<Definition, error::Error> {
+    let file: DefinitionFile = if json {
+      match serde_json::from_str(text) {
+        Ok(file) => file,
+        Err(err) => return Err(error::ParseError::new(&err.to_string()).into()),
+      }
+    }else{
+      match toml::from_str(text) {
+        Ok(file) => file,
+        Err(err) => return Err(error::ParseError::new(&err.to_string()).into()),
+      }
+    };
+    Self::new(file)
+  }
+  
+  fn new(file: DefinitionFile) -> Result<Definition, error::Error> {
+    let mut columns: Vec<Column> = Vec::new();
+    for col in file.columns {
+      let typ = match &col.typ {
+        Some(typ) => Some(value::Type::parse(typ)?),
+        None => None,
+      };
+      let default = col.default.map(|e| { e.text() });
+      if let (Some(typ), Some(default)) = (typ, &default) {
+        if let Err(err) = typ.value(default) {
+          return Err(error::ParseError::new(&format!("Invalid default for column {}: {}", col.name, err)).into());
+        }
+      }
+      columns.push(Column{
+        name: col.name,
+        header: col.header,
+        typ: typ,
+        required: col.required,
+        default: default,
+      });
+    }
+    Ok(Definition{
+      columns: columns,
+    })
+  }
+  
+  // Produce the schema for an input with the provided header, verifying
+  // that the header is consistent with this definition.
+  pub fn schema(&self, scope: &str, hdrs: &csv::StringRecord) -> Result<schema::Schema, error::Error> {
+    self.check_width(scope, hdrs.len())?;
+    for (col, hdr) in self.columns.iter().zip(hdrs.iter()) {
+      if let Some(expect) = &col.header {
+        if expect != hdr {
+          return Err(error::FrameError::new(&format!("{}: schema expects header {:?} for column {} but the input has {:?}", scope, expect, col.name, hdr)).into());
+        }
+      }
+    }
+    Ok(self.declared(scope))
+  }
+  
+  // Produce the schema for an input without a header, whose rows have
+  // the provided number of fields.
+  pub fn schema_headerless(&self, scope: &str, width: usize) -> Result<schema::Schema, error::Error> {
+    self.check_width(scope, width)?;
+    Ok(self.declared(scope))
+  }
+  
+  fn check_width(&self, scope: &str, width: usize) -> Result<(), error::Error> {
+    if width != self.columns.len() {
+      return Err(error::FrameError::new(&format!("{}: schema declares {} columns but the input has {}", scope, self.columns.len(), width)).into());
+    }
+    Ok(())
+  }
+  
+  fn declared(&self, scope: &str) -> schema::Schema {
+    let mut schema = schema::Schema::new(scope, self.columns.iter().map(|e| { e.name.as_ref() }));
+    for (i, col) in self.columns.iter().enumerate() {
+      if let Some(typ) = col.typ {
+        schema.set_column_type(i, typ);
+      }
+    }
+    schema
+  }
+  
+  // The header each declared column is read from: its expected header if
+  // there is one, and otherwise its name
+  pub fn headers<'a>(&'a self) -> Vec<&'a str> {
+    self.columns.iter().map(|e| { e.header.as_deref().unwrap_or(&e.name) }).collect()
+  }
+  
+  // Determine whether the column at the specified index has a declared type
+  pub fn is_typed(&self, index: usize) -> bool {
+    match self.columns.get(index) {
+      Some(col) => col.typ.is_some(),
+      None => false,
+    }
+  }
+  
+  // Validate a row against this definition, substituting defaults for
+  // empty fields. The row is checked against the types in the provided
+  // schema, which may have been inferred or overridden.
+  pub fn validate(&self, schema: &schema::Schema, row: csv::StringRecord) -> Result<csv::StringRecord, error::Error> {
+    let line = match row.position() {
+      Some(pos) => pos.line(),
+      None => 0,
+    };
+    let fail = |index: usize, msg: &str| -> error::Error {
+      let col = match schema.columns().get(index) {
+        Some(col) => col.to_string(),
+        None => index.to_string(),
+      };
+      error::FrameError::new(&format!("Line {}: column {}: {}", line, col, msg)).into()
+    };
+    
+    let mut res: Option<Vec<String>> = None; // only copied if a default is substituted
+    for (i, col) in self.columns.iter().enumerate() {
+      let field = match row.get(i) {
+        Some(field) => field,
+        None => return Err(fail(i, "missing field")),
+      };
+      let field = if field.is_empty() {
+        if let Some(default) = &col.default {
+          let res = res.get_or_insert_with(|| { row.iter().map(|e| { e.to_owned() }).collect() });
+          res[i] = default.to_owned();
+          default
+        }else if col.required {
+          return Err(fail(i, "value is required"));
+        }else{
+          field
+        }
+      }else{
+        field
+      };
+      if let Err(err) = schema.column_type(i).value(field) {
+        return Err(fail(i, &err.to_string()));
+      }
+    }
+    
+    match res {
+      Some(res) => {
+        let mut rec = csv::StringRecord::from(res);
+        rec.set_position(row.position().cloned());
+        Ok(rec)
+      },
+      None => Ok(row),
+    }
+  }
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+  
+  const ORDERS: &str = r#"
+    [[columns]]
+    name = "id"
+    type = "integer"
+    required = true
+    
+    [[columns]]
+    name = "customer"
+    header = "Customer Name"
+    
+    [[columns]]
+    name = "qty"
+    type = "int"
+    default = 1
+    
+    [[columns]]
+    name = "paid"
+    type = "bool"
+    default = false
+  "#;
+  
+  fn error(text: &str, json: bool) -> String {
+    Definition::parse(text, json).unwrap_err().to_string()
+  }
+  
+  fn validate(def: &Definition, row: &[&str]) -> Result<Vec<String>, error::Error> {
+    let schema = def.schema_headerless("a", def.columns.len())?;
+    let row = def.validate(&schema, csv::StringRecord::from(row.to_vec()))?;
+    Ok(row.iter().map(|e| { e.to_owned() }).collect())
+  }
+  
+  #[test]
+  fn parse_toml() {
+    let def = Definition::parse(ORDERS, false).unwrap();
+    assert_eq!(def.headers(), vec!["id", "Customer Name", "qty", "paid"]);
+    assert_eq!((0..5).map(|e| { def.is_typed(e) }).collect::<Vec<bool>>(), vec![true, false, true, true, false]);
+    let schema = def.schema("a", &csv::StringRecord::from(vec!["id", "Customer Name", "qty", "paid"])).unwrap();
+    assert_eq!(schema.columns().iter().map(|e| { e.to_string() }).collect::<Vec<String>>(), vec!["a.id", "a.customer", "a.qty", "a.paid"]);
+    assert_eq!((0..4).map(|e| { schema.column_type(e) }).collect::<Vec<value::Type>>(), vec![value::Type::Integer, value::Type::String, value::Type::Integer, value::Type::Boolean]);
+  }
+  
+  #[test]
+  fn parse_json() {
+    let def = Definition::parse(r#"{"columns": [{"name": "id", "type": "decimal", "default": 1.5}, {"name": "note"}]}"#, true).unwrap();
+    assert_eq!(def.headers(), vec!["id", "note"]);
+    assert_eq!(validate(&def, &["", "x"]).unwrap(), vec!["1.5", "x"]);
+    assert!(Definition::parse(ORDERS, true).is_err());
+  }
+  
+  #[test]
+  fn invalid_definitions() {
+    assert!(error("[[columns]]\nname = \"id\"\ntipe = \"int\"\n", false).starts_with("unknown field `tipe`"));
+    assert!(error("[[cols]]\nname = \"id\"\n", false).starts_with("unknown field `cols`"));
+    assert!(error("{\"columns\": [{\"name\": \"id\", \"required\": \"yes\"}]}", true).starts_with("invalid type"));
+    assert!(error("[[columns]]\ntype = \"int\"\n", false).starts_with("missing field `name`"));
+    assert_eq!(error("[[columns]]\nname = \"id\"\ntype = \"money\"\n", false), "Invalid type: money");
+    assert_eq!(error("[[columns]]\nname = \"id\"\ntype = \"int\"\ndefault = \"x\"\n", false), "Invalid default for column id: Invalid integer value: \"x\"");
+    assert_eq!(error("[[columns]]\nname = \"on\"\ntype = \"date\"\ndefault = 2024\n", false), "Invalid default for column on: Invalid date value: \"2024\"");
+  }
+  
+  #[test]
+  fn headers() {
+    let def = Definition::parse(ORDERS, false).unwrap();
+    assert_eq!(def.schema("a", &csv::StringRecord::from(vec!["id", "customer", "qty", "paid"])).unwrap_err().to_string(), "a: schema expects header \"Customer Name\" for column customer but the input has \"customer\"");
+    assert_eq!(def.schema("a", &csv::StringRecord::from(vec!["id", "Customer Name", "qty"])).unwrap_err().to_string(), "a: schema declares 4 columns but the input has 3");
+    assert_eq!(def.schema_headerless("a", 5).unwrap_err().to_string(), "a: schema declares 4 columns but the input has 5");
+  }
+  
+  #[test]
+  fn required_and_defaults() {
+    let def = Definition::parse(ORDERS, false).unwrap();
+    assert_eq!(validate(&def, &["1", "x", "2", "yes"]).unwrap(), vec!["1", "x", "2", "yes"]);
+    assert_eq!(validate(&def, &["1", "", "", ""]).unwrap(), vec!["1", "", "1", "false"]);
+    assert_eq!(validate(&def, &["", "x", "2", "yes"]).unwrap_err().to_string(), "Line 0: column a.id: value is required");
+    assert_eq!(validate(&def, &["1", "x", "two", "yes"]).unwrap_err().to_string(), "Line 0: column a.qty: Invalid integer value: \"two\"");
+    assert_eq!(validate(&def, &["1", "x", "2"]).unwrap_err().to_string(), "Line 0: column a.paid: missing field");
+  }
+}
@@ -0,0 +1,250 @@
+use csv;
+
+use crate::csvql::query::error;
+
+// The settings a dialect recognizes, as in: delim=\t:quote=none
+const SETTINGS: &[&str] = &["delim", "quote", "escape", "doublequote", "comment", "terminator"];
+
+// How records are terminated
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Terminator {
+  Any, // any of \r, \n or \r\n when reading, and \n when writing
+  Crlf, // \r\n when writing, or any when reading
+  Byte(u8),
+}
+
+// When fields are quoted in output
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum Quoting {
+  Always,
+  #[default]
+  Necessary, // only fields which contain a delimiter, quote or terminator
+  NonNumeric,
+  Never,
+}
+
+impl Quoting {
+  pub fn parse(text: &str) -> Result<Quoting, error::Error> {
+    match text.trim().to_lowercase().as_str() {
+      "always" => Ok(Self::Always),
+      "necessary" => Ok(Self::Necessary),
+      "nonnumeric" | "non-numeric" => Ok(Self::NonNumeric),
+      "never" => Ok(Self::Never),
+      _ => Err(error::ParseError::new(&format!("Invalid quoting style, expected always, necessary, nonnumeric or never: {}", text)).into()),
+    }
+  }
+}
+
+// The dialect of CSV data: how its fields are delimited and quoted and
+// how its records are terminated. The default is RFC 4180.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Dialect {
+  pub delimiter: u8,
+  pub quote: Option<u8>, // none disables quoting entirely
+  pub escape: Option<u8>, // escapes quotes when they are not doubled
+  pub double_quote: bool, // quotes in quoted fields are escaped by doubling them
+  pub comment: Option<u8>, // records which begin with this are skipped
+  pub terminator: Terminator,
+}
+
+impl Default for Dialect {
+  fn default() -> Dialect {
+    Dialect{
+      delimiter: b',',
+      quote: Some(b'"'),
+      escape: None,
+      double_quote: true,
+      comment: None,
+      terminator: Terminator::Any,
+    }
+  }
+}
+
+#[allow(clippy::needless_lifetimes)]
+impl Dialect {
+  // Split trailing dialect settings from an input path, as in:
+  // orders.tsv:delim=\t:quote=none. Settings begin at the first colon
+  // which is followed by a setting name, so other colons are left in
+  // the path.
+  pub fn split<'a>(path: &'a str) -> (&'a str, Option<&'a str>) {
+    for (i, _) in path.match_indices(':') {
+      let rest = &path[i + 1..];
+      if SETTINGS.iter().any(|e| { rest.strip_prefix(e).is_some_and(|e| { e.starts_with('=') }) }) {
+        return (&path[..i], Some(rest));
+      }
+    }
+    (path, None)
+  }
+  
+  // Apply colon-separated settings to this dialect, as in:
+  // delim=;:quote=':comment=#. Characters may be given literally or by
+  // name: tab, space, comma, semicolon, colon, pipe or backslash; and
+  // optional characters may be disabled with none. Setting an escape
+  // disables doubled quotes unless doublequote is set after it.
+  pub fn with_settings(&self, text: &str) -> Result<Dialect, error::Error> {
+    let mut dialect = *self;
+    for setting in text.split(':').filter(|e| { !e.is_empty() }) {
+      let (key, val) = match setting.split_once('=') {
+        Some((key, val)) => (key.trim(), val),
+        None => return Err(error::ParseError::new(&format!("Invalid dialect setting, expected <setting>=<value>: {}", setting)).into()),
+      };
+      match key {
+        "delim" => dialect.delimiter = byte(key, val)?,
+        "quote" => dialect.quote = optional(key, val)?,
+        "escape" => {
+          dialect.escape = optional(key, val)?;
+          dialect.double_quote = dialect.escape.is_none();
+        },
+        "doublequote" => dialect.double_quote = match val.to_lowercase().as_str() {
+          "true" | "yes" => true,
+          "false" | "no" => false,
+          _ => return Err(error::ParseError::new(&format!("Invalid dialect setting, expected true or false: {}", setting)).into()),
+        },
+        "comment" => dialect.comment = optional(key, val)?,
+        "terminator" => dialect.terminator = match val.to_lowercase().as_str() {
+          "any" => Terminator::Any,
+          "crlf" => Terminator::Crlf,
+          "lf" | "\\n" => Terminator::Byte(b'\n'),
+          "cr" => Terminator::Byte(b'\r'),
+          _ => Terminator::Byte(byte(key, val)?),
+        },
+        _ => return Err(error::ParseError::new(&format!("Invalid dialect setting, expected one of {}: {}", SETTINGS.join(", "), key)).into()),
+      }
+    }
+    if Some(dialect.delimiter) == dialect.quote {
+      return Err(error::ParseError::new(&format!("Invalid dialect, the delimiter and quote are the same: {}", name(dialect.delimiter))).into());
+    }
+    Ok(dialect)
+  }
+  
+  // A reader builder configured for this dialect
+  pub fn reader(&self) -> csv::ReaderBuilder {
+    let mut builder = csv::ReaderBuilder::new();
+    builder
+      .delimiter(self.delimiter)
+      .quoting(self.quote.is_some())
+      .quote(self.quote.unwrap_or(b'"'))
+      .escape(self.escape)
+      .double_quote(self.double_quote)
+      .comment(self.comment)
+      .terminator(match self.terminator {
+        Terminator::Any | Terminator::Crlf => csv::Terminator::CRLF,
+        Terminator::Byte(b) => csv::Terminator::Any(b),
+      });
+    builder
+  }
+  
+  // A writer builder configured for this dialect, which quotes fields
+  // in the provided style. Output is never quoted if quoting is disabled.
+  pub fn writer(&self, quoting: Quoting) -> csv::WriterBuilder {
+    let mut builder = csv::WriterBuilder::new();
+    builder
+      .delimiter(self.delimiter)
+      .quote(self.quote.unwrap_or(b'"'))
+      .quote_style(match (self.quote, quoting) {
+        (None, _) | (_, Quoting::Never) => csv::QuoteStyle::Never,
+        (_, Quoting::Always) => csv::QuoteStyle::Always,
+        (_, Quoting::Necessary) => csv::QuoteStyle::Necessary,
+        (_, Quoting::NonNumeric) => csv::QuoteStyle::NonNumeric,
+      })
+      .double_quote(self.double_quote)
+      .escape(self.escape.unwrap_or(b'\\'))
+      .terminator(match self.terminator {
+        Terminator::Any => csv::Terminator::Any(b'\n'),
+        Terminator::Crlf => csv::Terminator::CRLF,
+        Terminator::Byte(b) => csv::Terminator::Any(b),
+      });
+    builder
+  }
+}
+
+// Parse a single-byte character, either literally or by name
+fn byte(key: &str, val: &str) -> Result<u8, error::Error> {
+  let b = match val {
+    "tab" | "\\t" => b'\t',
+    "space" => b' ',
+    "comma" => b',',
+    "semicolon" => b';',
+    "colon" => b':',
+    "pipe" => b'|',
+    "backslash" | "\\\\" => b'\\',
+    val if val.len() == 1 && val.is_ascii() => val.as_bytes()[0],
+    _ => return Err(error::ParseError::new(&format!("Invalid dialect setting, expected a single ASCII character for {}: {:?}", key, val)).into()),
+  };
+  Ok(b)
+}
+
+fn optional(key: &str, val: &str) -> Result<Option<u8>, error::Error> {
+  match val {
+    "none" => Ok(None),
+    val => Ok(Some(byte(key, val)?)),
+  }
+}
+
+fn name(b: u8) -> String {
+  match b {
+    b'\t' => "tab".to_owned(),
+    b' ' => "space".to_owned(),
+    b':' => "colon".to_owned(),
+    b => (b as char).to_string(),
+  }
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+  
+  fn settings(text: &str) -> Dialect {
+    Dialect::default().with_settings(text).unwrap()
+  }
+  
+  fn error(text: &str) -> String {
+    Dialect::default().with_settings(text).unwrap_err().to_string()
+  }
+  
+  #[test]
+  fn split() {
+    assert_eq!(Dialect::split("orders.tsv:delim=tab"), ("orders.tsv", Some("delim=tab")));
+    assert_eq!(Dialect::split("orders.csv:quote=none:comment=#"), ("orders.csv", Some("quote=none:comment=#")));
+    assert_eq!(Dialect::split("orders.csv"), ("orders.csv", None));
+    // colons which do not begin settings are left in the path
+    assert_eq!(Dialect::split("C:\\data\\orders.csv"), ("C:\\data\\orders.csv", None));
+    assert_eq!(Dialect::split("x:y/orders.csv:delim=;"), ("x:y/orders.csv", Some("delim=;")));
+    assert_eq!(Dialect::split("orders:delimited.csv"), ("orders:delimited.csv", None));
+    assert_eq!(Dialect::split("orders.csv:delimiter=;"), ("orders.csv:delimiter=;", None));
+  }
+  
+  #[test]
+  fn with_settings() {
+    assert_eq!(settings(""), Dialect::default());
+    let dialect = settings("delim=tab:quote=':comment=#");
+    assert_eq!((dialect.delimiter, dialect.quote, dialect.comment), (b'\t', Some(b'\''), Some(b'#')));
+    assert_eq!(settings("delim=;").delimiter, b';');
+    assert_eq!(settings("delim=\\t").delimiter, b'\t');
+    assert_eq!(settings("delim=pipe:quote=none").quote, None);
+    
+    // an escape disables doubled quotes unless they are enabled after it
+    let dialect = settings("escape=backslash");
+    assert_eq!((dialect.escape, dialect.double_quote), (Some(b'\\'), false));
+    let dialect = settings("escape=\\\\:doublequote=yes");
+    assert_eq!((dialect.escape, dialect.double_quote), (Some(b'\\'), true));
+    assert!(!settings("doublequote=false").double_quote);
+    
+    assert_eq!(settings("terminator=crlf").terminator, Terminator::Crlf);
+    assert_eq!(settings("terminator=lf").terminator, Terminator::Byte(b'\n'));
+    assert_eq!(settings("terminator=cr").terminator, Terminator::Byte(b'\r'));
+    assert_eq!(settings("terminator=;").terminator, Terminator::Byte(b';'));
+    assert_eq!(settings("terminator=crlf:terminator=any").terminator, Terminator::Any);
+  }
+  
+  #[test]
+  fn invalid_settings() {
+    assert_eq!(error("delim"), "Invalid dialect setting, expected <setting>=<value>: delim");
+    assert_eq!(error("delim=ab"), "Invalid dialect setting, expected a single ASCII character for delim: \"ab\"");
+    assert_eq!(error("delim=none"), "Invalid dialect setting, expected a single ASCII character for delim: \"none\"");
+    assert_eq!(error("separator=;"), "Invalid dialect setting, expected one of delim, quote, escape, doublequote, comment, terminator: separator");
+    assert_eq!(error("doublequote=maybe"), "Invalid dialect setting, expected true or false: doublequote=maybe");
+    assert_eq!(error("quote=,"), "Invalid dialect, the delimiter and quote are the same: ,");
+    assert_eq!(error("delim=colon:quote=colon"), "Invalid dialect, the delimiter and quote are the same: colon");
+  }
+}
@@ -1,8 +1,6 @@
 use std::io;
 use std::fmt;
 
-use csv;
-
 #[derive(Debug)]
 pub struct ParseError {
   message: String,
@@ -61,13 +59,13 @@ impl fmt::Display for QueryError {
 }
 
 #[derive(Debug)]
+#[allow(clippy::enum_variant_names)]
 pub enum Error {
   IOError(io::Error),
   CsvError(csv::Error),
   ParseError(ParseError),
   FrameError(FrameError),
   QueryError(QueryError),
-  NotFoundError,
 }
 
 impl From<io::Error> for Error {
@@ -108,7 +106,6 @@ impl fmt::Display for Error {
       Self::ParseError(err) => err.fmt(f),
       Self::FrameError(err) => err.fmt(f),
       Self::QueryError(err) => err.fmt(f),
-      Self::NotFoundError => write!(f, "not found"),
     }
   }
 }
@@ -0,0 +1,250 @@
+use std::fmt;
+
+use csv;
+
+use crate::csvql::query::ast;
+use crate::csvql::query::schema;
+use crate::csvql::query::value;
+use crate::csvql::query::function;
+use crate::csvql::query::error;
+
+// A scalar expression, bound to the schema of the rows it is evaluated
+// against
+#[derive(Debug, Clone)]
+pub enum Expr {
+  Column(schema::QName, usize, value::Type),
+  Literal(value::Value),
+  Arith(Box<Expr>, ast::Arith, Box<Expr>),
+  Neg(Box<Expr>),
+  Call(function::Function, Vec<Expr>),
+}
+
+impl Expr {
+  pub fn new(expr: &ast::Expr, schema: &schema::Schema) -> Result<Expr, error::Error> {
+    match expr {
+      ast::Expr::Column(qname) => match schema.index(qname) {
+        Some(index) => Ok(Self::Column(qname.clone(), index, schema.column_type(index))),
+        None => Err(error::QueryError::new(&format!("Expression column not found: {} ({})", qname, schema)).into()),
+      },
+      ast::Expr::Literal(ast::Literal::String(v)) => Ok(Self::Literal(value::Value::String(v.to_owned()))),
+      ast::Expr::Literal(ast::Literal::Number(v)) => Ok(Self::Literal(value::Value::infer(v))),
+      ast::Expr::Arith(lhs, op, rhs) => Ok(Self::Arith(Box::new(Self::new(lhs, schema)?), *op, Box::new(Self::new(rhs, schema)?))),
+      ast::Expr::Neg(expr) => Ok(Self::Neg(Box::new(Self::new(expr, schema)?))),
+      ast::Expr::Call(name, args) => {
+        let func = function::Function::new(name, args)?;
+        let mut bound: Vec<Expr> = Vec::new();
+        for arg in args {
+          bound.push(Self::new(arg, schema)?);
+        }
+        Ok(Self::Call(func, bound))
+      },
+      expr => Err(error::QueryError::new(&format!("Expected a scalar expression: {}", expr)).into()),
+    }
+  }
+  
+  // The type of the value this expression produces. Columns which are
+  // not typed are treated as decimals in arithmetic, and the values of
+  // arithmetic are converted to this type when they are evaluated.
+  pub fn result_type(&self) -> value::Type {
+    match self {
+      Self::Column(_, _, typ) => *typ,
+      Self::Literal(val) => match val {
+        value::Value::Integer(_) => value::Type::Integer,
+        value::Value::Decimal(_) => value::Type::Decimal,
+        _ => value::Type::String,
+      },
+      Self::Arith(lhs, op, rhs) => match (op, Self::numeric_type(lhs.result_type()), Self::numeric_type(rhs.result_type())) {
+        (ast::Arith::Div, _, _) => value::Type::Float,
+        (_, value::Type::Integer, value::Type::Integer) => value::Type::Integer,
+        (ast::Arith::Rem, _, _) => value::Type::Float,
+        (_, value::Type::Float, _) | (_, _, value::Type::Float) => value::Type::Float,
+        _ => value::Type::Decimal,
+      },
+      Self::Neg(expr) => Self::numeric_type(expr.result_type()),
+      Self::Call(func, args) => func.result_type(&args.iter().map(|e| { e.result_type() }).collect::<Vec<value::Type>>()),
+    }
+  }
+  
+  fn numeric_type(typ: value::Type) -> value::Type {
+    match typ {
+      value::Type::Integer | value::Type::Float => typ,
+      _ => value::Type::Decimal,
+    }
+  }
+  
+  pub fn eval(&self, row: &csv::StringRecord) -> Result<value::Value, error::Error> {
+    match self {
+      Self::Column(qname, index, typ) => match row.get(*index) {
+        Some(text) => match typ.value(text) {
+          Ok(val) => Ok(val),
+          Err(err) => Err(error::FrameError::new(&format!("Column {}: {}", qname, err)).into()),
+        },
+        None => Err(error::QueryError::new(&format!("Index not found in data: {} > {}", index, row.len() as i64 - 1)).into()),
+      },
+      Self::Literal(val) => Ok(val.clone()),
+      Self::Arith(lhs, op, rhs) => {
+        let (a, b) = (lhs.eval(row)?, rhs.eval(row)?);
+        if a.is_null() || b.is_null() {
+          return Ok(value::Value::Null);
+        }
+        let res = match (a.numeric(), b.numeric()) {
+          (Some(a), Some(b)) => match op {
+            ast::Arith::Add => a.add(&b),
+            ast::Arith::Sub => a.sub(&b),
+            ast::Arith::Mul => a.mul(&b),
+            ast::Arith::Div => a.div(&b),
+            ast::Arith::Rem => a.rem(&b),
+          },
+          _ => None,
+        };
+        match res {
+          Some(res) => self.converted(res),
+          None => Err(error::QueryError::new(&format!("Cannot evaluate {}: {:?} {} {:?}", self, a.to_string(), op, b.to_string())).into()),
+        }
+      },
+      Self::Neg(expr) => {
+        let val = expr.eval(row)?;
+        if val.is_null() {
+          return Ok(val);
+        }
+        match val.numeric().and_then(|e| { e.neg() }) {
+          Some(res) => self.converted(res),
+          None => Err(error::QueryError::new(&format!("Cannot evaluate {}: {:?}", self, val.to_string())).into()),
+        }
+      },
+      Self::Call(func, args) => {
+        let mut vals: Vec<value::Value> = Vec::new();
+        for arg in args {
+          vals.push(arg.eval(row)?);
+        }
+        self.converted(func.apply(vals)?)
+      },
+    }
+  }
+  
+  // Convert a numeric value to the numeric type of this expression, which
+  // it may not have if an operand was not typed or an integer overflowed
+  fn converted(&self, val: value::Value) -> Result<value::Value, error::Error> {
+    let typ = self.result_type();
+    match (typ, &val) {
+      (_, value::Value::Null) => Ok(val),
+      (value::Type::Integer, value::Value::Integer(_)) | (value::Type::Float, value::Value::Float(_)) | (value::Type::Decimal, value::Value::Decimal(_)) => Ok(val),
+      (value::Type::Integer, _) | (value::Type::Float, _) | (value::Type::Decimal, _) => match typ.value(&val.to_string()) {
+        Ok(res) => Ok(res),
+        Err(_) => Err(error::QueryError::new(&format!("Cannot evaluate {}: {} is out of range for type {}", self, val, typ)).into()),
+      },
+      _ => Ok(val),
+    }
+  }
+}
+
+impl fmt::Display for Expr {
+  fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+    match self {
+      Self::Column(qname, _, _) => write!(f, "{}", qname),
+      Self::Literal(value::Value::String(v)) => write!(f, "'{}'", v),
+      Self::Literal(v) => write!(f, "{}", v),
+      Self::Arith(lhs, op, rhs) => write!(f, "({} {} {})", lhs, op, rhs),
+      Self::Neg(expr) => write!(f, "-{}", expr),
+      Self::Call(func, args) => write!(f, "{}({})", func, args.iter().map(|e| { e.to_string() }).collect::<Vec<String>>().join(", ")),
+    }
+  }
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+  use crate::csvql::query::parser;
+  
+  // Evaluate an expression against a row of an integer, i, a float, f, a
+  // decimal, d, and an untyped field, s, checking that the result can be
+  // represented by the expression's result type
+  fn eval(text: &str, fields: &[&str]) -> Result<String, error::Error> {
+    let mut schema = schema::Schema::new("a", ["i", "f", "d", "s"].into_iter());
+    schema.set_column_type(0, value::Type::Integer);
+    schema.set_column_type(1, value::Type::Float);
+    schema.set_column_type(2, value::Type::Decimal);
+    let expr = Expr::new(&scalar(text), &schema)?;
+    let res = expr.eval(&csv::StringRecord::from(fields.to_vec()))?.to_string();
+    expr.result_type().value(&res)?;
+    Ok(res)
+  }
+  
+  fn result_type(text: &str) -> value::Type {
+    let mut schema = schema::Schema::new("a", ["i", "f", "d", "s"].into_iter());
+    schema.set_column_type(0, value::Type::Integer);
+    schema.set_column_type(1, value::Type::Float);
+    schema.set_column_type(2, value::Type::Decimal);
+    Expr::new(&scalar(text), &schema).unwrap().result_type()
+  }
+  
+  fn scalar(text: &str) -> ast::Expr {
+    match parser::parse_selection(text).unwrap().remove(0) {
+      ast::Column::Expr(proj) => proj.expr,
+      col => panic!("expected an expression: {}", col),
+    }
+  }
+  
+  #[test]
+  fn promotion() {
+    let row = ["7", "0.5", "2.50", "3"];
+    assert_eq!(eval("a.i + 1", &row).unwrap(), "8");
+    assert_eq!(eval("a.i - 10", &row).unwrap(), "-3");
+    assert_eq!(eval("a.i + 1.5", &row).unwrap(), "8.5");
+    assert_eq!(eval("a.i * a.d", &row).unwrap(), "17.50");
+    assert_eq!(eval("a.i * a.f", &row).unwrap(), "3.5");
+    assert_eq!(eval("a.d + a.f", &row).unwrap(), "3");
+    assert_eq!(eval("a.i / 2", &row).unwrap(), "3.5");
+    assert_eq!(eval("a.i % 2", &row).unwrap(), "1");
+    assert_eq!(eval("a.d % 2", &row).unwrap(), "0.5");
+    assert_eq!(eval("-a.i", &row).unwrap(), "-7");
+    // untyped fields are decimals
+    assert_eq!(eval("a.s + 1", &row).unwrap(), "4");
+    assert_eq!(eval("a.s * 1.5", &row).unwrap(), "4.5");
+    assert_eq!(eval("-a.s", &row).unwrap(), "-3");
+    assert_eq!(eval("a.s + 1", &["7", "0.5", "2.50", "1e3"]).unwrap(), "1001");
+    assert!(eval("a.s + 1", &["7", "0.5", "2.50", "x"]).is_err());
+    // nulls propagate
+    assert_eq!(eval("a.i + a.s", &["7", "", "", ""]).unwrap(), "");
+  }
+  
+  #[test]
+  fn overflow() {
+    let row = ["9223372036854775807", "", "", "9223372036854775807"];
+    assert_eq!(eval("a.i + 1", &row).unwrap_err().to_string(), "Cannot evaluate (a.i + 1): 9223372036854775808 is out of range for type integer");
+    assert!(eval("a.i * 2", &row).is_err());
+    assert!(eval("-a.i - 2", &row).is_err());
+    // decimals are wider than integers
+    assert_eq!(eval("a.s + 1", &row).unwrap(), "9223372036854775808");
+    assert_eq!(eval("a.i + 1.0", &row).unwrap(), "9223372036854775808.0");
+  }
+  
+  #[test]
+  fn division_by_zero() {
+    let row = ["7", "0.5", "2.50", "0"];
+    assert_eq!(eval("a.i / 0", &row).unwrap(), "");
+    assert_eq!(eval("a.i % 0", &row).unwrap(), "");
+    assert_eq!(eval("a.d % 0", &row).unwrap(), "");
+    assert_eq!(eval("a.f / a.s", &row).unwrap(), "");
+    assert_eq!(eval("a.i % a.s", &row).unwrap(), "");
+  }
+  
+  #[test]
+  fn result_types() {
+    assert_eq!(result_type("a.i + 1"), value::Type::Integer);
+    assert_eq!(result_type("a.i + 1.5"), value::Type::Decimal);
+    assert_eq!(result_type("a.i + a.f"), value::Type::Float);
+    assert_eq!(result_type("a.i / a.i"), value::Type::Float);
+    assert_eq!(result_type("a.i % a.i"), value::Type::Integer);
+    assert_eq!(result_type("a.d % a.i"), value::Type::Float);
+    assert_eq!(result_type("a.s + 1"), value::Type::Decimal);
+    assert_eq!(result_type("-a.s"), value::Type::Decimal);
+    assert_eq!(result_type("abs(a.s)"), value::Type::Decimal);
+    assert_eq!(result_type("round(a.f)"), value::Type::Float);
+    assert_eq!(result_type("length(a.s)"), value::Type::Integer);
+    assert_eq!(result_type("upper(a.i)"), value::Type::String);
+    // values are converted to the result type, even from untyped fields
+    assert_eq!(eval("abs(a.s)", &["", "", "", "-1e3"]).unwrap(), "1000");
+    assert_eq!(eval("round(a.s)", &["", "", "", "2"]).unwrap(), "2");
+  }
+}
@@ -2,12 +2,21 @@ use std::io;
 use std::fmt;
 use std::cmp;
 use std::iter;
-use std::collections::BTreeMap;
+use std::collections::HashMap;
+use std::collections::HashSet;
+use std::collections::VecDeque;
 
 use csv;
 
+use crate::csvql::query::ast;
+use crate::csvql::query::aggregate;
+use crate::csvql::query::definition;
+use crate::csvql::query::dialect;
 use crate::csvql::query::error;
 use crate::csvql::query::select;
+use crate::csvql::query::predicate;
+use crate::csvql::query::sort;
+use crate::csvql::query::value;
 use crate::csvql::query::schema;
 
 fn convert_record(e: csv::Result<csv::StringRecord>) -> Result<csv::StringRecord, error::Error> {
@@ -18,12 +27,27 @@ fn convert_record(e: csv::Result<csv::StringRecord>) -> Result<csv::StringRecord
 }
 
 // A frame of data
+#[allow(clippy::needless_lifetimes)]
 pub trait Frame: fmt::Display {
   fn name<'a>(&'a self) -> &'a str;
   fn schema<'a>(&'a self) -> &'a schema::Schema;
   fn rows<'a>(&'a mut self) -> Box<dyn iter::Iterator<Item = Result<csv::StringRecord, error::Error>> + 'a>;
+  
+  // The columns this frame's rows are sorted on, in order of precedence
+  fn sorted_on<'a>(&'a self) -> &'a [schema::QName] {
+    &[]
+  }
+  
+  // The approximate number of bytes of data this frame produces, if known.
+  // Data is measured as delimited text, as a CSV input is on disk, for
+  // inputs of every format, and its rows occupy several times as much
+  // memory.
+  fn size_hint(&self) -> Option<u64> {
+    None
+  }
 }
 
+#[allow(clippy::needless_lifetimes)]
 impl<F: Frame + ?Sized> Frame for Box<F> { // black magic
   fn name<'a>(&'a self) -> &'a str {
     (**self).name()
@@ -33,70 +57,101 @@ impl<F: Frame + ?Sized> Frame for Box<F> { // black magic
     (**self).schema()
   }
   
+  fn sorted_on<'a>(&'a self) -> &'a [schema::QName] {
+    (**self).sorted_on()
+  }
+  
+  fn size_hint(&self) -> Option<u64> {
+    (**self).size_hint()
+  }
+  
   fn rows<'a>(&'a mut self) -> Box<dyn iter::Iterator<Item = Result<csv::StringRecord, error::Error>> + 'a> {
     (**self).rows()
   }
 }
 
-// A random-access frame indexed on a particular column
+// The entries of an index: each key and the rows which share it
+pub type IndexEntries<'a> = Box<dyn iter::Iterator<Item = (&'a [value::Value], &'a [csv::StringRecord])> + 'a>;
+
+// A random-access frame indexed on a set of columns
+#[allow(clippy::needless_lifetimes)]
 pub trait Index: Frame {
-  fn on<'a>(&'a self) -> &'a str; // the indexed column
-  fn get<'a>(&'a self, key: &str) -> Result<&'a csv::StringRecord, error::Error>;
+  fn on<'a>(&'a self) -> &'a [schema::QName]; // the indexed columns
+  fn normalize<'a>(&'a self) -> &'a [value::Normalizer]; // applied to keys before lookup
+  fn get<'a>(&'a self, key: &[value::Value]) -> Option<&'a [csv::StringRecord]>;
+  fn entries<'a>(&'a self) -> IndexEntries<'a>;
 }
 
+#[allow(clippy::needless_lifetimes)]
 impl<I: Index + ?Sized> Index for Box<I> { // black magic
-  fn on<'a>(&'a self) -> &'a str {
+  fn on<'a>(&'a self) -> &'a [schema::QName] {
     (**self).on()
   }
   
-  fn get<'a>(&'a self, key: &str) -> Result<&'a csv::StringRecord, error::Error> {
+  fn normalize<'a>(&'a self) -> &'a [value::Normalizer] {
+    (**self).normalize()
+  }
+  
+  fn get<'a>(&'a self, key: &[value::Value]) -> Option<&'a [csv::StringRecord]> {
     (**self).get(key)
   }
+  
+  fn entries<'a>(&'a self) -> IndexEntries<'a> {
+    (**self).entries()
+  }
 }
 
-// A btree indexed frame
+// A hash indexed frame. Rows which share a key are retained in the
+// order they were read, and keys are produced in the order they were
+// first seen.
 #[derive(Debug)]
-pub struct BTreeIndex {
+pub struct HashIndex {
   name: String,
-  on: String,
+  on: Vec<schema::QName>,
+  normalize: Vec<value::Normalizer>,
   schema: schema::Schema,
-  data: BTreeMap<String, csv::StringRecord>,
+  size: Option<u64>,
+  keys: HashMap<Vec<value::Value>, usize>,
+  data: Vec<(Vec<value::Value>, Vec<csv::StringRecord>)>,
 }
 
-impl BTreeIndex {
-  pub fn _new(source: &mut dyn Frame, on: &str) -> Result<BTreeIndex, error::Error> {
+#[allow(clippy::redundant_field_names)]
+impl HashIndex {
+  pub fn new(source: &mut dyn Frame, on: &[schema::QName], normalize: &[value::Normalizer]) -> Result<HashIndex, error::Error> {
     let name = source.name().to_owned();
     let schema = source.schema().clone();
-    let index_on = schema::QName::new(&name, on);
-    let data = Self::_index(&schema, &index_on, source)?;
-    Ok(BTreeIndex{
-      name: name,
-      on: on.to_owned(),
-      schema: schema,
-      data: data,
-    })
-  }
-  
-  fn _index(schema: &schema::Schema, on: &schema::QName, source: &mut dyn Frame) -> Result<BTreeMap<String, csv::StringRecord>, error::Error> {
-    let mut data: BTreeMap<String, csv::StringRecord> = BTreeMap::new();
-    let index = match schema.index(on) {
-      Some(index) => index,
-      None => return Err(error::FrameError::new("Index column not found").into()),
-    };
+    let size = source.size_hint();
+    let indexes = key_indexes(&schema, on)?;
     
+    let mut keys: HashMap<Vec<value::Value>, usize> = HashMap::new();
+    let mut data: Vec<(Vec<value::Value>, Vec<csv::StringRecord>)> = Vec::new();
     for row in source.rows() {
       let row = row?;
-      match row.get(index) {
-        Some(col) => data.insert(col.to_string(), row),
-        None => /* row is omitted */ None,
+      let key = sort::key(&schema, &indexes, normalize, &row)?;
+      match keys.get(&key) {
+        Some(n) => data[*n].1.push(row),
+        None if has_null(&key) => data.push((key, vec![row])), // never matched, so never looked up
+        None => {
+          keys.insert(key.clone(), data.len());
+          data.push((key, vec![row]));
+        },
       };
     }
     
-    Ok(data)
+    Ok(HashIndex{
+      name: name,
+      on: on.to_vec(),
+      normalize: normalize.to_vec(),
+      schema: schema,
+      size: size,
+      keys: keys,
+      data: data,
+    })
   }
 }
 
-impl Frame for BTreeIndex {
+#[allow(clippy::needless_lifetimes)]
+impl Frame for HashIndex {
   fn name<'a>(&'a self) -> &'a str {
     &self.name
   }
@@ -105,27 +160,37 @@ impl Frame for BTreeIndex {
     &self.schema
   }
   
+  fn size_hint(&self) -> Option<u64> {
+    self.size
+  }
+  
   fn rows<'a>(&'a mut self) -> Box<dyn iter::Iterator<Item = Result<csv::StringRecord, error::Error>> + 'a> {
-    Box::new(self.data.values().map(|e| { Ok(e.to_owned()) }))
+    Box::new(self.data.iter().flat_map(|e| { e.1.iter() }).map(|e| { Ok(e.to_owned()) }))
   }
 }
 
-impl Index for BTreeIndex {
-  fn on<'a>(&'a self) -> &'a str {
+#[allow(clippy::needless_lifetimes)]
+impl Index for HashIndex {
+  fn on<'a>(&'a self) -> &'a [schema::QName] {
     &self.on
   }
   
-  fn get<'a>(&'a self, key: &str) -> Result<&'a csv::StringRecord, error::Error> {
-    match self.data.get(key) {
-      Some(val) => Ok(&val),
-      None => Err(error::Error::NotFoundError.into()),
-    }
+  fn normalize<'a>(&'a self) -> &'a [value::Normalizer] {
+    &self.normalize
+  }
+  
+  fn get<'a>(&'a self, key: &[value::Value]) -> Option<&'a [csv::StringRecord]> {
+    self.keys.get(key).map(|e| { self.data[*e].1.as_slice() })
+  }
+  
+  fn entries<'a>(&'a self) -> IndexEntries<'a> {
+    Box::new(self.data.iter().map(|(k, v)| { (k.as_slice(), v.as_slice()) }))
   }
 }
 
-impl fmt::Display for BTreeIndex {
+impl fmt::Display for HashIndex {
   fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
-    write!(f, "{}[{}]", self.name, &self.on)
+    write!(f, "{}[#{}]", self.name, qnames(&self.on))
   }
 }
 
@@ -133,25 +198,34 @@ impl fmt::Display for BTreeIndex {
 #[derive(Debug)]
 pub struct Filter<F: Frame, S: select::Selector> {
   selector: S,
+  schema: schema::Schema,
   data: F,
 }
 
+#[allow(clippy::redundant_field_names)]
 impl<F: Frame, S: select::Selector> Filter<F, S> {
   pub fn new(source: F, selector: S) -> Result<Filter<F, S>, error::Error> {
+    let schema = selector.schema(source.schema());
     Ok(Filter{
       selector: selector,
+      schema: schema,
       data: source,
     })
   }
 }
 
+#[allow(clippy::needless_lifetimes)]
 impl<F: Frame, S: select::Selector> Frame for Filter<F, S> {
   fn name<'a>(&'a self) -> &'a str {
     self.data.name()
   }
   
   fn schema<'a>(&'a self) -> &'a schema::Schema {
-    self.data.schema()
+    &self.schema
+  }
+  
+  fn size_hint(&self) -> Option<u64> {
+    self.data.size_hint()
   }
   
   fn rows<'a>(&'a mut self) -> Box<dyn iter::Iterator<Item = Result<csv::StringRecord, error::Error>> + 'a> {
@@ -171,76 +245,424 @@ impl<F: Frame, S: select::Selector> fmt::Display for Filter<F, S> {
   }
 }
 
-#[derive(Debug, Eq)]
-struct SortedRecord {
-  on: String,
-  data: csv::StringRecord,
+// A frame that produces only the rows which satisfy a predicate
+#[derive(Debug)]
+pub struct Where<F: Frame> {
+  predicate: predicate::Predicate,
+  data: F,
+}
+
+#[allow(clippy::redundant_field_names)]
+impl<F: Frame> Where<F> {
+  pub fn new(source: F, expr: &ast::Expr) -> Result<Where<F>, error::Error> {
+    let predicate = predicate::Predicate::new(expr, source.schema())?;
+    Ok(Where{
+      predicate: predicate,
+      data: source,
+    })
+  }
+}
+
+#[allow(clippy::needless_lifetimes)]
+impl<F: Frame> Frame for Where<F> {
+  fn name<'a>(&'a self) -> &'a str {
+    self.data.name()
+  }
+  
+  fn schema<'a>(&'a self) -> &'a schema::Schema {
+    self.data.schema()
+  }
+  
+  fn sorted_on<'a>(&'a self) -> &'a [schema::QName] {
+    self.data.sorted_on()
+  }
+  
+  fn size_hint(&self) -> Option<u64> {
+    self.data.size_hint()
+  }
+  
+  fn rows<'a>(&'a mut self) -> Box<dyn iter::Iterator<Item = Result<csv::StringRecord, error::Error>> + 'a> {
+    let pred = &self.predicate;
+    Box::new(self.data.rows().filter_map(move |e| {
+      let row = match e {
+        Ok(row) => row,
+        Err(err) => return Some(Err(err)),
+      };
+      match pred.eval(&row) {
+        Ok(true) => Some(Ok(row)),
+        Ok(false) => None,
+        Err(err) => Some(Err(err)),
+      }
+    }))
+  }
+}
+
+impl<F: Frame> fmt::Display for Where<F> {
+  fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+    write!(f, "{}[{}]", &self.data, &self.predicate)
+  }
+}
+
+// A frame that produces at most a fixed number of rows
+#[derive(Debug)]
+pub struct Limit<F: Frame> {
+  limit: usize,
+  data: F,
+}
+
+#[allow(clippy::redundant_field_names)]
+impl<F: Frame> Limit<F> {
+  pub fn new(source: F, limit: usize) -> Result<Limit<F>, error::Error> {
+    Ok(Limit{
+      limit: limit,
+      data: source,
+    })
+  }
+}
+
+#[allow(clippy::needless_lifetimes)]
+impl<F: Frame> Frame for Limit<F> {
+  fn name<'a>(&'a self) -> &'a str {
+    self.data.name()
+  }
+  
+  fn schema<'a>(&'a self) -> &'a schema::Schema {
+    self.data.schema()
+  }
+  
+  fn sorted_on<'a>(&'a self) -> &'a [schema::QName] {
+    self.data.sorted_on()
+  }
+  
+  fn size_hint(&self) -> Option<u64> {
+    self.data.size_hint()
+  }
+  
+  fn rows<'a>(&'a mut self) -> Box<dyn iter::Iterator<Item = Result<csv::StringRecord, error::Error>> + 'a> {
+    Box::new(self.data.rows().take(self.limit))
+  }
 }
 
-impl Ord for SortedRecord {
-  fn cmp(&self, other: &Self) -> cmp::Ordering {
-    self.on.cmp(&other.on)
+impl<F: Frame> fmt::Display for Limit<F> {
+  fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+    write!(f, "{}[:{}]", &self.data, self.limit)
+  }
+}
+
+// A group of rows which share the same key, being aggregated
+struct Group {
+  key: Vec<value::Value>,
+  text: Vec<String>, // the key as it appears in the first row of the group
+  accs: Vec<aggregate::Accumulator>,
+}
+
+// A frame that groups rows on a set of key columns and computes
+// aggregates over each group. If the source is sorted on the keys, each
+// group is produced as soon as it is complete; otherwise every group is
+// held in memory until the source is exhausted.
+#[derive(Debug)]
+pub struct Aggregate<F: Frame> {
+  keys: Vec<schema::QName>,
+  key_indexes: Vec<usize>,
+  aggs: Vec<ast::Aggregate>,
+  agg_indexes: Vec<Option<usize>>,
+  streaming: bool,
+  schema: schema::Schema,
+  data: F,
+}
+
+#[allow(clippy::redundant_field_names)]
+impl<F: Frame> Aggregate<F> {
+  pub fn new(source: F, keys: &[schema::QName], aggs: &[ast::Aggregate]) -> Result<Aggregate<F>, error::Error> {
+    let src = source.schema();
+    let mut cols: Vec<schema::QName> = Vec::new();
+    let mut types: Vec<value::Type> = Vec::new();
+    
+    let mut key_indexes: Vec<usize> = Vec::new();
+    for key in keys {
+      let index = match src.index(key) {
+        Some(index) => index,
+        None => return Err(error::FrameError::new(&format!("Group column not found: {} ({})", key, src)).into()),
+      };
+      key_indexes.push(index);
+      cols.push(src.columns()[index].clone());
+      types.push(src.column_type(index));
+    }
+    
+    let mut agg_indexes: Vec<Option<usize>> = Vec::new();
+    for agg in aggs {
+      aggregate::Accumulator::new(agg)?; // validate the aggregate
+      let index = match &agg.arg {
+        Some(arg) => match src.index(arg) {
+          Some(index) => Some(index),
+          None => return Err(error::FrameError::new(&format!("Aggregate column not found: {} ({})", arg, src)).into()),
+        },
+        None => None,
+      };
+      agg_indexes.push(index);
+      cols.push(agg.qname());
+      types.push(aggregate::Accumulator::result_type(agg, match index {
+        Some(index) => src.column_type(index),
+        None => value::Type::Integer,
+      }));
+    }
+    
+    // groups can be streamed if the source is sorted on the group keys,
+    // in any order of precedence
+    let sorted = source.sorted_on();
+    let streaming = !keys.is_empty() && sorted.len() >= keys.len() && sorted[..keys.len()].iter().all(|e| {
+      match src.index(e) {
+        Some(index) => key_indexes.contains(&index),
+        None => false,
+      }
+    });
+    
+    Ok(Aggregate{
+      keys: keys.to_vec(),
+      key_indexes: key_indexes,
+      aggs: aggs.to_vec(),
+      agg_indexes: agg_indexes,
+      streaming: streaming,
+      schema: schema::Schema::new_with_columns(cols, types),
+      data: source,
+    })
+  }
+  
+  fn group(schema: &schema::Schema, key_indexes: &[usize], aggs: &[ast::Aggregate], row: &csv::StringRecord) -> Result<Group, error::Error> {
+    let mut key: Vec<value::Value> = Vec::new();
+    let mut text: Vec<String> = Vec::new();
+    for index in key_indexes {
+      let field = row.get(*index).unwrap_or_default();
+      key.push(schema.value(*index, field)?);
+      text.push(field.to_owned());
+    }
+    let mut accs: Vec<aggregate::Accumulator> = Vec::new();
+    for agg in aggs {
+      accs.push(aggregate::Accumulator::new(agg)?);
+    }
+    Ok(Group{
+      key: key,
+      text: text,
+      accs: accs,
+    })
+  }
+  
+  fn accumulate(schema: &schema::Schema, agg_indexes: &[Option<usize>], group: &mut Group, row: &csv::StringRecord) -> Result<(), error::Error> {
+    for (acc, index) in group.accs.iter_mut().zip(agg_indexes) {
+      match index {
+        Some(index) => acc.update(schema.value(*index, row.get(*index).unwrap_or_default())?, false)?,
+        None => acc.update(value::Value::Null, true)?,
+      };
+    }
+    Ok(())
+  }
+  
+  fn record(group: Group) -> csv::StringRecord {
+    let mut row = group.text;
+    row.extend(group.accs.iter().map(|e| { e.value().to_string() }));
+    row.into()
   }
 }
 
-impl PartialOrd for SortedRecord {
-  fn partial_cmp(&self, other: &Self) -> Option<cmp::Ordering> {
-    Some(self.cmp(other))
+#[allow(clippy::needless_lifetimes, clippy::redundant_field_names)]
+impl<F: Frame> Frame for Aggregate<F> {
+  fn name<'a>(&'a self) -> &'a str {
+    self.data.name()
+  }
+  
+  fn schema<'a>(&'a self) -> &'a schema::Schema {
+    &self.schema
+  }
+  
+  fn sorted_on<'a>(&'a self) -> &'a [schema::QName] {
+    if self.streaming {
+      &self.keys
+    }else{
+      &[]
+    }
+  }
+  
+  fn rows<'a>(&'a mut self) -> Box<dyn iter::Iterator<Item = Result<csv::StringRecord, error::Error>> + 'a> {
+    let src = self.data.schema().clone();
+    let key_indexes = &self.key_indexes;
+    let aggs = &self.aggs;
+    let agg_indexes = &self.agg_indexes;
+    let mut rows = self.data.rows();
+    
+    if self.streaming {
+      let mut curr: Option<Group> = None;
+      return Box::new(iter::from_fn(move || {
+        loop {
+          let row = match rows.next() {
+            Some(Ok(row)) => row,
+            Some(Err(err)) => return Some(Err(err)),
+            None => return curr.take().map(|e| { Ok(Self::record(e)) }),
+          };
+          let mut group = match Self::group(&src, key_indexes, aggs, &row) {
+            Ok(group) => group,
+            Err(err) => return Some(Err(err)),
+          };
+          let done = match curr.take() {
+            Some(prev) if prev.key == group.key => {
+              group = prev;
+              None
+            },
+            prev => prev,
+          };
+          if let Err(err) = Self::accumulate(&src, agg_indexes, &mut group, &row) {
+            return Some(Err(err));
+          }
+          curr = Some(group);
+          if let Some(done) = done {
+            return Some(Ok(Self::record(done)));
+          }
+        }
+      }));
+    }
+    
+    let mut index: HashMap<Vec<value::Value>, usize> = HashMap::new();
+    let mut groups: Vec<Group> = Vec::new();
+    for row in rows {
+      let res = row.and_then(|row| {
+        let group = Self::group(&src, key_indexes, aggs, &row)?;
+        let n = match index.get(&group.key) {
+          Some(n) => *n,
+          None => {
+            index.insert(group.key.clone(), groups.len());
+            groups.push(group);
+            groups.len() - 1
+          },
+        };
+        Self::accumulate(&src, agg_indexes, &mut groups[n], &row)
+      });
+      if let Err(err) = res {
+        return Box::new(iter::once(Err(err)));
+      }
+    }
+    
+    // without group keys, an aggregate over no rows still produces a row
+    if groups.is_empty() && key_indexes.is_empty() {
+      let mut accs: Vec<aggregate::Accumulator> = Vec::new();
+      for agg in aggs {
+        match aggregate::Accumulator::new(agg) {
+          Ok(acc) => accs.push(acc),
+          Err(err) => return Box::new(iter::once(Err(err))),
+        };
+      }
+      groups.push(Group{
+        key: Vec::new(),
+        text: Vec::new(),
+        accs: accs,
+      });
+    }
+    
+    Box::new(groups.into_iter().map(|e| { Ok(Self::record(e)) }))
   }
 }
 
-impl PartialEq for SortedRecord {
-  fn eq(&self, other: &Self) -> bool {
-    self.on == other.on
+impl<F: Frame> fmt::Display for Aggregate<F> {
+  fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+    write!(f, "{}{{{:?}: {}}}", &self.data, &self.keys, self.aggs.iter().map(|e| { e.to_string() }).collect::<Vec<String>>().join(", "))
   }
 }
 
-// A sorted frame
+#[derive(Debug)]
+struct SortedRecord {
+  on: Vec<value::Value>,
+  data: csv::StringRecord,
+}
+
+// The rows of a sorted frame, which are either held in memory or, if
+// they exceeded the memory budget, spilled to disk in sorted runs
+#[derive(Debug)]
+enum SortedData {
+  Memory(Vec<SortedRecord>),
+  Spilled(Vec<sort::Run>),
+}
+
+// A sorted frame. Sorting is stable, so rows which compare equal on
+// every sort key retain the order they had in the source.
 #[derive(Debug)]
 pub struct Sorted {
   name: String,
-  on: schema::QName,
+  on: Vec<schema::QName>,
+  order: Vec<ast::Order>,
+  indexes: Vec<usize>,
+  normalize: Vec<value::Normalizer>,
   schema: schema::Schema,
-  data: Vec<SortedRecord>,
+  size: Option<u64>,
+  data: SortedData,
 }
 
+#[allow(clippy::redundant_field_names)]
 impl Sorted {
-  pub fn new(source: &mut dyn Frame, on: &schema::QName) -> Result<Sorted, error::Error> {
+  // Sort a frame on columns whose values are normalized before they are
+  // compared, as when sorting either side of a merge join
+  pub fn new(source: &mut dyn Frame, on: &[schema::QName], normalize: &[value::Normalizer], opts: &sort::SortOptions) -> Result<Sorted, error::Error> {
+    Self::new_normalized(source, &on.iter().map(|e| { ast::Order::new(e.clone()) }).collect::<Vec<ast::Order>>(), normalize, opts)
+  }
+  
+  pub fn new_with_order(source: &mut dyn Frame, order: &[ast::Order], opts: &sort::SortOptions) -> Result<Sorted, error::Error> {
+    Self::new_normalized(source, order, &[], opts)
+  }
+  
+  fn new_normalized(source: &mut dyn Frame, order: &[ast::Order], normalize: &[value::Normalizer], opts: &sort::SortOptions) -> Result<Sorted, error::Error> {
     let name = source.name().to_owned();
     let schema = source.schema().clone();
-    let data = Self::sorted(&schema, on, source)?;
+    let size = source.size_hint();
+    
+    let on: Vec<schema::QName> = order.iter().map(|e| { e.on.clone() }).collect();
+    let indexes = key_indexes(&schema, &on)?;
+    
+    let data = Self::sorted(&schema, order, &indexes, normalize, source, opts)?;
     Ok(Sorted{
       name: name,
-      on: on.clone(),
+      on: on,
+      order: order.to_vec(),
+      indexes: indexes,
+      normalize: normalize.to_vec(),
       schema: schema,
+      size: size,
       data: data,
     })
   }
   
-  fn sorted(schema: &schema::Schema, on: &schema::QName, source: &mut dyn Frame) -> Result<Vec<SortedRecord>, error::Error> {
-    let index = match schema.index(&on) {
-      Some(index) => index,
-      None => return Err(error::FrameError::new(&format!("Index column not found: {} ({})", &on, &schema)).into()),
-    };
-    
+  fn sorted(schema: &schema::Schema, order: &[ast::Order], indexes: &[usize], normalize: &[value::Normalizer], source: &mut dyn Frame, opts: &sort::SortOptions) -> Result<SortedData, error::Error> {
     let mut data: Vec<SortedRecord> = Vec::new();
+    let mut runs: Vec<sort::Run> = Vec::new();
+    let mut size: usize = 0;
+    
     for row in source.rows() {
       let row = row?;
-      let on = match row.get(index) {
-        Some(on) => on,
-        None => return Err(error::FrameError::new(&format!("Index column not found: {}", index)).into()),
-      };
+      let on = sort::key(schema, indexes, normalize, &row)?;
+      size += sort::footprint(&row, &on);
       data.push(SortedRecord{
-        on: on.to_owned(),
+        on: on,
         data: row,
       });
+      if let Some(limit) = opts.memory {
+        if size > limit {
+          data.sort_by(|a, b| { ast::Order::compare_all(order, &a.on, &b.on) });
+          runs.push(sort::Run::write(&opts.temp_dir, data.iter().map(|e| { &e.data }))?);
+          data.clear();
+          size = 0;
+        }
+      }
     }
     
-    data.sort();
-    Ok(data)
+    data.sort_by(|a, b| { ast::Order::compare_all(order, &a.on, &b.on) });
+    if runs.is_empty() {
+      Ok(SortedData::Memory(data))
+    }else{
+      if !data.is_empty() {
+        runs.push(sort::Run::write(&opts.temp_dir, data.iter().map(|e| { &e.data }))?);
+      }
+      Ok(SortedData::Spilled(runs))
+    }
   }
 }
 
+#[allow(clippy::needless_lifetimes)]
 impl Frame for Sorted {
   fn name<'a>(&'a self) -> &'a str {
     &self.name
@@ -250,36 +672,117 @@ impl Frame for Sorted {
     &self.schema
   }
   
+  fn sorted_on<'a>(&'a self) -> &'a [schema::QName] {
+    // Only ascending order with nulls first is the order joins expect,
+    // and normalized values are not the values themselves
+    let ascending = self.order.iter().all(|e| { e.direction == ast::Direction::Asc && e.nulls() == ast::Nulls::First });
+    if ascending && self.normalize.is_empty() {
+      &self.on
+    }else{
+      &[]
+    }
+  }
+  
+  fn size_hint(&self) -> Option<u64> {
+    self.size
+  }
+  
   fn rows<'a>(&'a mut self) -> Box<dyn iter::Iterator<Item = Result<csv::StringRecord, error::Error>> + 'a> {
-    Box::new(self.data.iter().map(|e| { Ok(e.data.clone()) }))
+    match &self.data {
+      SortedData::Memory(data) => Box::new(data.iter().map(|e| { Ok(e.data.clone()) })),
+      SortedData::Spilled(runs) => match sort::Merge::new(&self.schema, &self.indexes, &self.normalize, &self.order, runs) {
+        Ok(merge) => Box::new(merge),
+        Err(err) => Box::new(iter::once(Err(err))),
+      },
+    }
   }
 }
 
 impl fmt::Display for Sorted {
   fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
-    write!(f, "{}{{{}}}", self.name, &self.on)
+    write!(f, "{}{{{}}}", self.name, self.order.iter().map(|e| { e.to_string() }).collect::<Vec<String>>().join(", "))
   }
 }
 
+// Where the names of the columns of a CSV input come from
+#[derive(Debug, Clone, Default)]
+pub enum Headers {
+  #[default]
+  Read, // the first row is a header
+  Positional, // there is no header; columns are named c1, c2, ...
+  Names(Vec<String>), // there is no header; columns are named in order
+}
+
+// Options that control how a CSV input frame is read
+#[derive(Debug, Clone, Default)]
+pub struct CsvOptions {
+  pub infer: Option<usize>, // infer column types from up to this many leading rows
+  pub headers: Headers, // whether the input has a header, and if not how columns are named
+  pub definition: Option<definition::Definition>, // declare and validate columns
+  pub types: Vec<(schema::QName, value::Type)>, // declared column types, which take precedence over all others
+  pub dialect: dialect::Dialect, // how fields and records are delimited and quoted
+  pub size: Option<u64>, // the size of a CSV input in bytes, if known; JSON inputs measure their rows instead
+}
+
 // A CSV input frame
 #[derive(Debug)]
 pub struct Csv<R: io::Read> {
   name: String,
   schema: schema::Schema,
+  definition: Option<definition::Definition>,
+  size: Option<u64>,
+  sample: Vec<csv::StringRecord>,
   data: csv::Reader<R>,
 }
 
+#[allow(clippy::redundant_field_names)]
 impl<R: io::Read> Csv<R> {
-  pub fn new(name: &str, data: R) -> Result<Csv<R>, error::Error> {
-    let mut reader = csv::ReaderBuilder::new().has_headers(true).from_reader(data);
+  pub fn new(name: &str, data: R, opts: &CsvOptions) -> Result<Csv<R>, error::Error> {
+    let mut reader = opts.dialect.reader().has_headers(matches!(opts.headers, Headers::Read)).from_reader(data);
+    // without a header, this is the first row, which is not consumed
+    let hdrs = reader.headers()?;
+    let mut schema = match (&opts.headers, &opts.definition) {
+      (Headers::Read, Some(def)) => def.schema(name, hdrs)?,
+      (Headers::Read, None) => schema::Schema::new(name, hdrs.iter()),
+      (Headers::Positional, Some(def)) => def.schema_headerless(name, hdrs.len())?,
+      (Headers::Positional, None) => schema::Schema::new_positional(name, hdrs.len()),
+      (Headers::Names(_), Some(_)) => return Err(error::FrameError::new(&format!("{}: column names cannot be supplied along with a schema definition", name)).into()),
+      (Headers::Names(names), None) => {
+        if !hdrs.is_empty() && hdrs.len() != names.len() {
+          return Err(error::FrameError::new(&format!("{}: {} column names were supplied but the input has {}", name, names.len(), hdrs.len())).into());
+        }
+        schema::Schema::new(name, names.iter().map(|e| { e.as_str() }))
+      },
+    };
+    
+    let mut sample: Vec<csv::StringRecord> = Vec::new();
+    if let Some(limit) = opts.infer {
+      for row in reader.records().take(limit) {
+        sample.push(row?);
+      }
+      for index in 0..schema.count() {
+        if let Some(def) = &opts.definition {
+          if def.is_typed(index) {
+            continue; // declared types take precedence
+          }
+        }
+        schema.set_column_type(index, value::Type::infer(sample.iter().filter_map(|e| { e.get(index) })));
+      }
+    }
+    declare_types(name, &mut schema, &opts.types)?;
+    
     Ok(Csv{
       name: name.to_owned(),
-      schema: schema::Schema::new(name, reader.headers()?.iter()),
+      schema: schema,
+      definition: opts.definition.clone(),
+      size: opts.size,
+      sample: sample,
       data: reader,
     })
   }
 }
 
+#[allow(clippy::needless_lifetimes)]
 impl<R: io::Read> Frame for Csv<R> {
   fn name<'a>(&'a self) -> &'a str {
     &self.name
@@ -289,8 +792,14 @@ impl<R: io::Read> Frame for Csv<R> {
     &self.schema
   }
   
+  fn size_hint(&self) -> Option<u64> {
+    self.size
+  }
+  
   fn rows<'a>(&'a mut self) -> Box<dyn iter::Iterator<Item = Result<csv::StringRecord, error::Error>> + 'a> {
-    Box::new(self.data.records().map(|e| { convert_record(e) }))
+    let sample = std::mem::take(&mut self.sample); // rows read ahead to infer types are produced first
+    let rows = sample.into_iter().map(Ok).chain(self.data.records().map(|e| { convert_record(e) }));
+    validated(&self.name, &self.schema, self.definition.as_ref(), rows)
   }
 }
 
@@ -300,133 +809,518 @@ impl<R: io::Read> fmt::Display for Csv<R> {
   }
 }
 
-// A frame that left-joins two frames on an indexed column
+// A JSON input frame, which reads either a top-level array of objects or
+// one object per line. Nested objects are flattened into columns with
+// dotted names, as in: user.address.city, and arrays are retained as
+// JSON text. The columns are the union of the keys of every object in
+// the order they are first seen, unless they are declared by a
+// definition, in which case every key must be declared. When inference
+// is enabled, columns are typed by the JSON values they hold: integers,
+// numbers or booleans; columns of strings are inferred from their text,
+// as CSV columns are; and columns of values of conflicting types are
+// strings. Since the columns are only known once every object
+// has been seen, the whole input is read into memory, even when it has
+// one object per line, and must fit there.
 #[derive(Debug)]
-pub struct Join<L: Frame, R: Index> {
-  on: String,
-  left: L,
-  left_schema: schema::Schema,
-  right: R,
-  right_schema: schema::Schema,
-  join_schema: schema::Schema,
+pub struct Json {
+  name: String,
+  schema: schema::Schema,
+  definition: Option<definition::Definition>,
+  size: Option<u64>,
+  data: Vec<csv::StringRecord>,
 }
 
-impl<L: Frame, R: Index> Join<L, R> {
-  pub fn _new(on: &str, left: L, right: R) -> Result<Join<L, R>, error::Error> {
-    if right.on() != on {
-      return Err(error::FrameError::new(&format!("Join index must use same column as indexed (right) frame: {}", on)).into());
+#[allow(clippy::redundant_field_names)]
+impl Json {
+  pub fn new<R: io::Read>(name: &str, mut data: R, opts: &CsvOptions) -> Result<Json, error::Error> {
+    let mut text = String::new();
+    data.read_to_string(&mut text)?;
+    let vals: Result<Vec<serde_json::Value>, serde_json::Error> = if text.trim_start().starts_with('[') {
+      serde_json::from_str(&text)
+    }else{
+      serde_json::Deserializer::from_str(&text).into_iter().collect()
+    };
+    let vals = match vals {
+      Ok(vals) => vals,
+      Err(err) => return Err(error::FrameError::new(&format!("{}: invalid JSON: {}", name, err)).into()),
+    };
+    
+    let mut keys: Vec<String> = Vec::new();
+    let mut kmap: HashMap<String, usize> = HashMap::new();
+    let mut types: Vec<Option<value::Type>> = Vec::new();
+    let mut mixed: Vec<bool> = Vec::new(); // whether a column holds values of conflicting types
+    let mut objs: Vec<Vec<(usize, String)>> = Vec::new();
+    for (i, val) in vals.iter().enumerate() {
+      let obj = match val {
+        serde_json::Value::Object(obj) => obj,
+        val => return Err(error::FrameError::new(&format!("{}: expected a JSON object in record {}: {}", name, i + 1, val)).into()),
+      };
+      let mut fields: Vec<(String, &serde_json::Value)> = Vec::new();
+      flatten(None, obj, &mut fields);
+      let mut row: Vec<(usize, String)> = Vec::new();
+      for (key, val) in fields {
+        let index = *kmap.entry(key.clone()).or_insert_with(|| {
+          keys.push(key);
+          types.push(None);
+          mixed.push(false);
+          keys.len() - 1
+        });
+        if row.iter().any(|e| { e.0 == index }) {
+          // as a key containing a dot and the path of a nested one
+          return Err(error::FrameError::new(&format!("{}: duplicate key in record {}: {}", name, i + 1, keys[index])).into());
+        }
+        types[index] = match (types[index], json_type(val)) {
+          (typ, None) | (None, typ) => typ,
+          (Some(a), Some(b)) if a == b => Some(a),
+          (Some(value::Type::Integer), Some(value::Type::Float)) | (Some(value::Type::Float), Some(value::Type::Integer)) => Some(value::Type::Float),
+          (Some(value::Type::Integer), Some(value::Type::Decimal)) | (Some(value::Type::Decimal), Some(value::Type::Integer)) => Some(value::Type::Decimal),
+          _ => {
+            mixed[index] = true;
+            Some(value::Type::String)
+          },
+        };
+        row.push((index, json_text(val)));
+      }
+      objs.push(row);
     }
     
-    let s1 = left.schema().clone();
-    let s2 = right.schema().clone();
-    let sjoin = s1.join(&s2._exclude(on));
+    // declared columns are read from the keys they are named by
+    let (mut schema, cols): (schema::Schema, Vec<Option<usize>>) = match &opts.definition {
+      Some(def) => {
+        let hdrs = def.headers();
+        let undeclared: Vec<&str> = keys.iter().filter(|e| { !hdrs.contains(&e.as_str()) }).map(|e| { e.as_str() }).collect();
+        if !undeclared.is_empty() {
+          return Err(error::FrameError::new(&format!("{}: schema declares {} columns but the input also has {}", name, hdrs.len(), undeclared.join(", "))).into());
+        }
+        let schema = def.schema(name, &csv::StringRecord::from(hdrs.clone()))?;
+        (schema, hdrs.iter().map(|e| { kmap.get(*e).copied() }).collect())
+      },
+      None => (schema::Schema::new(name, keys.iter().map(|e| { e.as_str() })), (0..keys.len()).map(Some).collect()),
+    };
     
-    Ok(Join{
-      on: on.to_string(),
-      left: left,
-      left_schema: s1,
-      right: right,
-      right_schema: s2,
-      join_schema: sjoin,
+    let mut rows: Vec<csv::StringRecord> = Vec::new();
+    let mut size: u64 = 0;
+    for obj in objs {
+      let mut fields: Vec<String> = vec![String::new(); keys.len()];
+      for (index, text) in obj {
+        fields[index] = text;
+      }
+      let row: csv::StringRecord = cols.iter().map(|e| { e.map(|e| { fields[e].as_str() }).unwrap_or_default() }).collect();
+      size += (row.as_slice().len() + row.len()) as u64; // with a delimiter or terminator after each field
+      rows.push(row);
+    }
+    
+    if opts.infer.is_some() {
+      for (index, col) in cols.iter().enumerate() {
+        if let Some(def) = &opts.definition {
+          if def.is_typed(index) {
+            continue; // declared types take precedence
+          }
+        }
+        let typ = match col.map(|e| { (types[e], mixed[e]) }) {
+          Some((_, true)) => value::Type::String,
+          Some((Some(value::Type::String), false)) | Some((None, false)) | None => value::Type::infer(rows.iter().filter_map(|e| { e.get(index) })),
+          Some((Some(typ), false)) => typ,
+        };
+        schema.set_column_type(index, typ);
+      }
+    }
+    declare_types(name, &mut schema, &opts.types)?;
+    
+    Ok(Json{
+      name: name.to_owned(),
+      schema: schema,
+      definition: opts.definition.clone(),
+      size: Some(size), // the rows as delimited text, rather than the JSON input
+      data: rows,
     })
   }
 }
 
-impl<L: Frame, R: Index> Frame for Join<L, R> {
+#[allow(clippy::needless_lifetimes)]
+impl Frame for Json {
   fn name<'a>(&'a self) -> &'a str {
-    self.left.name()
+    &self.name
   }
   
   fn schema<'a>(&'a self) -> &'a schema::Schema {
-    &self.join_schema
+    &self.schema
+  }
+  
+  fn size_hint(&self) -> Option<u64> {
+    self.size
   }
   
   fn rows<'a>(&'a mut self) -> Box<dyn iter::Iterator<Item = Result<csv::StringRecord, error::Error>> + 'a> {
-    let left_on = schema::QName::new(self.left.name(), &self.on);
-    let left_index = match self.left_schema.index(&left_on) {
-      Some(index) => index,
-      None => return Box::new(iter::once(Err(error::FrameError::new(&format!("Index column not found: {} ({})", &left_on, &self.left_schema)).into()))),
-    };
-    
-    let right = &self.right;
-    let right_on = schema::QName::new(self.right.name(), &self.on);
-    let right_schema = &self.right_schema;
-    let right_index = match self.right_schema.index(&right_on) {
-      Some(index) => index,
-      None => return Box::new(iter::once(Err(error::FrameError::new(&format!("Index column not found: {} ({})", &right_on, &self.right_schema)).into()))),
+    let rows = std::mem::take(&mut self.data).into_iter().map(Ok);
+    validated(&self.name, &self.schema, self.definition.as_ref(), rows)
+  }
+}
+
+impl fmt::Display for Json {
+  fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+    write!(f, "{}", self.name)
+  }
+}
+
+// Flatten the fields of a JSON object, naming the fields of nested
+// objects by their path
+fn flatten<'a>(prefix: Option<&str>, obj: &'a serde_json::Map<String, serde_json::Value>, fields: &mut Vec<(String, &'a serde_json::Value)>) {
+  for (key, val) in obj {
+    let key = match prefix {
+      Some(prefix) => format!("{}.{}", prefix, key),
+      None => key.to_owned(),
     };
-    
-    let rows = self.left.rows().map(move |row| {
-      let row = row?;
-      
-      let mut res: Vec<String> = Vec::new();
-      for field in &row {
-        res.push(field.to_owned()); // can we avoid this copy?
-      }
-      
-      if let Some(field) = row.get(left_index) {
-        match right.get(&field) {
-          Ok(row) => for (i, field) in row.iter().enumerate() {
-            if i != right_index {
-              res.push(field.to_owned()); // can we avoid this copy?
+    match val {
+      serde_json::Value::Object(obj) => flatten(Some(&key), obj, fields),
+      val => fields.push((key, val)),
+    }
+  }
+}
+
+// The type of a JSON value, if it has one
+fn json_type(val: &serde_json::Value) -> Option<value::Type> {
+  match val {
+    serde_json::Value::Null => None,
+    serde_json::Value::Bool(_) => Some(value::Type::Boolean),
+    serde_json::Value::Number(v) if v.is_i64() => Some(value::Type::Integer),
+    serde_json::Value::Number(v) if v.is_u64() => Some(value::Type::Decimal), // too large for an integer, but exact
+    serde_json::Value::Number(_) => Some(value::Type::Float),
+    _ => Some(value::Type::String),
+  }
+}
+
+// The text of a field for a JSON value: nulls are empty, and arrays are
+// JSON text
+fn json_text(val: &serde_json::Value) -> String {
+  match val {
+    serde_json::Value::Null => String::new(),
+    serde_json::Value::String(v) => v.to_owned(),
+    val => val.to_string(),
+  }
+}
+
+// Declare the types of the columns of an input. Types scoped to the input
+// must name one of its columns, while unscoped types apply to whichever
+// inputs have the column.
+fn declare_types(name: &str, schema: &mut schema::Schema, types: &[(schema::QName, value::Type)]) -> Result<(), error::Error> {
+  for (qname, typ) in types {
+    match qname.scope() {
+      Some(scope) if scope == name => schema.set_type(qname, *typ)?,
+      None if schema.index(qname).is_some() => schema.set_type(qname, *typ)?,
+      _ => {},
+    }
+  }
+  Ok(())
+}
+
+// Validate rows against a definition, if there is one
+fn validated<'a>(name: &'a str, schema: &'a schema::Schema, def: Option<&'a definition::Definition>, rows: impl iter::Iterator<Item = Result<csv::StringRecord, error::Error>> + 'a) -> Box<dyn iter::Iterator<Item = Result<csv::StringRecord, error::Error>> + 'a> {
+  match def {
+    Some(def) => {
+      Box::new(rows.map(move |e| {
+        match def.validate(schema, e?) {
+          Ok(row) => Ok(row),
+          Err(err) => Err(error::FrameError::new(&format!("{}: {}", name, err)).into()),
+        }
+      }))
+    },
+    None => Box::new(rows),
+  }
+}
+
+// Resolve the indexes of a set of key columns
+fn key_indexes(schema: &schema::Schema, on: &[schema::QName]) -> Result<Vec<usize>, error::Error> {
+  let mut indexes: Vec<usize> = Vec::new();
+  for qname in on {
+    indexes.push(match schema.index(qname) {
+      Some(index) => index,
+      None => return Err(error::FrameError::new(&format!("Index column not found: {} ({})", qname, schema)).into()),
+    });
+  }
+  Ok(indexes)
+}
+
+// Format a set of key columns
+fn qnames(on: &[schema::QName]) -> String {
+  on.iter().map(|e| { e.to_string() }).collect::<Vec<String>>().join(", ")
+}
+
+// Concatenate the fields of two rows
+fn concat_rows(left: &csv::StringRecord, right: &csv::StringRecord) -> csv::StringRecord {
+  let mut row: Vec<String> = Vec::new();
+  row.extend(left.iter().map(|e| { e.to_owned() }));
+  row.extend(right.iter().map(|e| { e.to_owned() }));
+  row.into()
+}
+
+// Concatenate the fields of a row with empty fields on the left or right
+fn pad_row(row: &csv::StringRecord, pad: &schema::Schema, left: bool) -> csv::StringRecord {
+  let mut res: Vec<String> = Vec::new();
+  if left {
+    res.append(&mut pad.empty_row(0));
+  }
+  res.extend(row.iter().map(|e| { e.to_owned() }));
+  if !left {
+    res.append(&mut pad.empty_row(0));
+  }
+  res.into()
+}
+
+// The schema produced by a join of the specified kind
+fn join_schema(kind: ast::JoinKind, left: &schema::Schema, right: &schema::Schema) -> schema::Schema {
+  if kind.filters() {
+    left.clone()
+  }else{
+    left.join(right)
+  }
+}
+
+// The approximate size of the data a join of the specified kind produces:
+// the size of its left side if it filters that side, or of both sides
+// otherwise, which is exceeded if rows match many times
+fn join_size(kind: ast::JoinKind, left: &dyn Frame, right: &dyn Frame) -> Option<u64> {
+  if kind.filters() {
+    left.size_hint()
+  }else{
+    Some(left.size_hint()? + right.size_hint()?)
+  }
+}
+
+// A frame that hash-joins two frames on a column: one side is indexed
+// and the other is streamed, or probed, against the index. Rows are
+// produced in the order of the probed frame, followed by unmatched
+// indexed rows if the kind of join retains them. The index is usually
+// the right side, but may be the left, in which case the join is said
+// to be swapped; the columns of the left side are produced first either
+// way.
+#[derive(Debug)]
+pub struct HashJoin<F: Frame, I: Index> {
+  kind: ast::JoinKind,
+  swapped: bool,
+  probe: F,
+  probe_schema: schema::Schema,
+  probe_on: Vec<schema::QName>,
+  build: I,
+  build_schema: schema::Schema,
+  join_schema: schema::Schema,
+}
+
+#[allow(clippy::redundant_field_names)]
+impl<F: Frame, I: Index> HashJoin<F, I> {
+  // Join a left frame against a right index
+  pub fn new(kind: ast::JoinKind, left: F, left_on: &[schema::QName], right: I) -> Result<HashJoin<F, I>, error::Error> {
+    if left_on.len() != right.on().len() {
+      return Err(error::FrameError::new(&format!("Join keys have different numbers of columns: ({}) and ({})", qnames(left_on), qnames(right.on()))).into());
+    }
+    let s1 = left.schema().clone();
+    let s2 = right.schema().clone();
+    let sjoin = join_schema(kind, &s1, &s2);
+    
+    Ok(HashJoin{
+      kind: kind,
+      swapped: false,
+      probe: left,
+      probe_schema: s1,
+      probe_on: left_on.to_vec(),
+      build: right,
+      build_schema: s2,
+      join_schema: sjoin,
+    })
+  }
+  
+  // Join a right frame against a left index. Semi and anti joins filter
+  // the left side, so they cannot be swapped.
+  pub fn new_swapped(kind: ast::JoinKind, left: I, right: F, right_on: &[schema::QName]) -> Result<HashJoin<F, I>, error::Error> {
+    if kind.filters() {
+      return Err(error::FrameError::new(&format!("A {} join cannot index its left side", kind)).into());
+    }
+    if left.on().len() != right_on.len() {
+      return Err(error::FrameError::new(&format!("Join keys have different numbers of columns: ({}) and ({})", qnames(left.on()), qnames(right_on))).into());
+    }
+    let s1 = left.schema().clone();
+    let s2 = right.schema().clone();
+    let sjoin = join_schema(kind, &s1, &s2);
+    
+    Ok(HashJoin{
+      kind: kind,
+      swapped: true,
+      probe: right,
+      probe_schema: s2,
+      probe_on: right_on.to_vec(),
+      build: left,
+      build_schema: s1,
+      join_schema: sjoin,
+    })
+  }
+  
+  // Whether probed rows without a match are produced
+  fn keeps_probe(&self) -> bool {
+    if self.swapped { self.kind.keeps_right() } else { self.kind.keeps_left() }
+  }
+  
+  // Whether indexed rows without a match are produced
+  fn keeps_build(&self) -> bool {
+    if self.swapped { self.kind.keeps_left() } else { self.kind.keeps_right() }
+  }
+}
+
+#[allow(clippy::needless_lifetimes)]
+impl<F: Frame, I: Index> Frame for HashJoin<F, I> {
+  fn name<'a>(&'a self) -> &'a str {
+    if self.swapped {
+      self.build.name()
+    }else{
+      self.probe.name()
+    }
+  }
+  
+  fn schema<'a>(&'a self) -> &'a schema::Schema {
+    &self.join_schema
+  }
+  
+  fn sorted_on<'a>(&'a self) -> &'a [schema::QName] {
+    if self.swapped || self.keeps_build() {
+      &[] // rows follow the right side, or unmatched right rows are appended
+    }else{
+      self.probe.sorted_on()
+    }
+  }
+  
+  fn size_hint(&self) -> Option<u64> {
+    // only joins which don't filter are swapped, and their size is that
+    // of both sides in either order
+    join_size(self.kind, &self.probe, &self.build)
+  }
+  
+  fn rows<'a>(&'a mut self) -> Box<dyn iter::Iterator<Item = Result<csv::StringRecord, error::Error>> + 'a> {
+    let probe_indexes = match key_indexes(&self.probe_schema, &self.probe_on) {
+      Ok(indexes) => indexes,
+      Err(err) => return Box::new(iter::once(Err(err))),
+    };
+    
+    let (kind, swapped) = (self.kind, self.swapped);
+    let (keeps_probe, keeps_build) = (self.keeps_probe(), self.keeps_build());
+    let build = &self.build;
+    let normalize = build.normalize();
+    let (probe_schema, build_schema) = (&self.probe_schema, &self.build_schema);
+    let mut rows = self.probe.rows();
+    let mut matched: HashSet<Vec<value::Value>> = HashSet::new();
+    let mut pending: VecDeque<csv::StringRecord> = VecDeque::new();
+    let mut unmatched: Option<IndexEntries<'a>> = None;
+    
+    Box::new(iter::from_fn(move || {
+      loop {
+        if let Some(row) = pending.pop_front() {
+          return Some(Ok(row));
+        }
+        
+        // once the probed rows are exhausted, produce indexed rows which
+        // were never matched
+        if let Some(entries) = &mut unmatched {
+          let (key, rows) = entries.next()?;
+          if !matched.contains(key) {
+            pending.extend(rows.iter().map(|e| { pad_row(e, probe_schema, !swapped) }));
+          }
+          continue;
+        }
+        
+        let row = match rows.next() {
+          Some(Ok(row)) => row,
+          Some(Err(err)) => return Some(Err(err)),
+          None => if keeps_build {
+            unmatched = Some(build.entries());
+            continue;
+          }else{
+            return None;
+          },
+        };
+        let key = match sort::key(probe_schema, &probe_indexes, normalize, &row) {
+          Ok(key) => key,
+          Err(err) => return Some(Err(err)),
+        };
+        
+        let found = if has_null(&key) { None } else { build.get(&key) };
+        match (found, kind) {
+          (Some(_), ast::JoinKind::Semi) => pending.push_back(row),
+          (Some(_), ast::JoinKind::Anti) => {},
+          (Some(matches), _) => {
+            pending.extend(matches.iter().map(|e| {
+              if swapped { concat_rows(e, &row) } else { concat_rows(&row, e) }
+            }));
+            if keeps_build {
+              matched.insert(key);
             }
           },
-          Err(err) => match err {
-            error::Error::NotFoundError => res.append(&mut right_schema.empty_row(-1)),
-            err => return Err(err),
+          (None, ast::JoinKind::Anti) => pending.push_back(row),
+          (None, _) => if keeps_probe {
+            pending.push_back(pad_row(&row, build_schema, swapped));
           },
-        };
+        }
       }
-      
-      Ok(res.into())
-    });
-    
-    Box::new(rows)
+    }))
   }
 }
 
-impl<L: Frame, R: Index> fmt::Display for Join<L, R> {
+impl<F: Frame, I: Index> fmt::Display for HashJoin<F, I> {
   fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
-    write!(f, "({} << {})[{}]", &self.left, &self.right, &self.on)
+    if self.swapped {
+      write!(f, "({} >{}> {})[{}; {}]", &self.build, self.kind, &self.probe, qnames(self.build.on()), qnames(&self.probe_on))
+    }else{
+      write!(f, "({} <{}< {})[{}; {}]", &self.probe, self.kind, &self.build, qnames(&self.probe_on), qnames(self.build.on()))
+    }
   }
 }
 
-// A frame that outer-joins two frames on a column. Both input
-// frames are expected to be sorted by their joining column.
+// A frame that merge-joins two frames on a column. Both input frames are
+// expected to be sorted by their joining column.
 #[derive(Debug)]
-pub struct OuterJoin<L: Frame, R: Frame> {
+pub struct MergeJoin<L: Frame, R: Frame> {
+  kind: ast::JoinKind,
+  
   left: L,
   left_schema: schema::Schema,
-  left_on: schema::QName,
+  left_on: Vec<schema::QName>,
   
   right: R,
   right_schema: schema::Schema,
-  right_on: schema::QName,
+  right_on: Vec<schema::QName>,
   
+  normalize: Vec<value::Normalizer>,
   join_schema: schema::Schema,
 }
 
-impl<L: Frame, R: Frame> OuterJoin<L, R> {
-  pub fn new(left: L, left_on: &schema::QName, right: R, right_on: &schema::QName) -> Result<OuterJoin<L, R>, error::Error> {
+#[allow(clippy::redundant_field_names)]
+impl<L: Frame, R: Frame> MergeJoin<L, R> {
+  // Both sides must be sorted on their joining columns as normalized by
+  // the provided normalizers
+  pub fn new(kind: ast::JoinKind, left: L, left_on: &[schema::QName], right: R, right_on: &[schema::QName], normalize: &[value::Normalizer]) -> Result<MergeJoin<L, R>, error::Error> {
+    if left_on.len() != right_on.len() {
+      return Err(error::FrameError::new(&format!("Join keys have different numbers of columns: ({}) and ({})", qnames(left_on), qnames(right_on))).into());
+    }
     let s1 = left.schema().clone();
     let s2 = right.schema().clone();
-    let sjoin = s1.join(&s2);
+    let sjoin = join_schema(kind, &s1, &s2);
     
-    Ok(OuterJoin{
+    Ok(MergeJoin{
+      kind: kind,
+      
       left: left,
       left_schema: s1,
-      left_on: left_on.clone(),
+      left_on: left_on.to_vec(),
       
       right: right,
       right_schema: s2,
-      right_on: right_on.clone(),
+      right_on: right_on.to_vec(),
       
+      normalize: normalize.to_vec(),
       join_schema: sjoin,
     })
   }
 }
 
-impl<L: Frame, R: Frame> Frame for OuterJoin<L, R> {
+#[allow(clippy::needless_lifetimes)]
+impl<L: Frame, R: Frame> Frame for MergeJoin<L, R> {
   fn name<'a>(&'a self) -> &'a str {
     self.left.name()
   }
@@ -435,96 +1329,884 @@ impl<L: Frame, R: Frame> Frame for OuterJoin<L, R> {
     &self.join_schema
   }
   
+  fn sorted_on<'a>(&'a self) -> &'a [schema::QName] {
+    if self.kind.keeps_right() {
+      &[] // unmatched right rows leave the left key empty
+    }else{
+      self.left.sorted_on()
+    }
+  }
+  
+  fn size_hint(&self) -> Option<u64> {
+    join_size(self.kind, &self.left, &self.right)
+  }
+  
   fn rows<'a>(&'a mut self) -> Box<dyn iter::Iterator<Item = Result<csv::StringRecord, error::Error>> + 'a> {
-    let left_index = match self.left_schema.index(&self.left_on) {
-      Some(index) => index,
-      None => return Box::new(iter::once(Err(error::FrameError::new(&format!("Index column not found: {} ({})", &self.left_on, &self.left_schema)).into()))),
+    let left_indexes = match key_indexes(&self.left_schema, &self.left_on) {
+      Ok(indexes) => indexes,
+      Err(err) => return Box::new(iter::once(Err(err))),
     };
-    let right_index = match self.right_schema.index(&self.right_on) {
-      Some(index) => index,
-      None => return Box::new(iter::once(Err(error::FrameError::new(&format!("Index column not found: {} ({})", &self.right_on, &self.right_schema)).into()))),
+    let right_indexes = match key_indexes(&self.right_schema, &self.right_on) {
+      Ok(indexes) => indexes,
+      Err(err) => return Box::new(iter::once(Err(err))),
     };
     
-    let mut rows: Vec<Result<csv::StringRecord, error::Error>> = Vec::new();
-    let mut iter_left = self.left.rows();
-    let mut curr_left: Option<Result<csv::StringRecord, error::Error>> = None;
-    let mut iter_right = self.right.rows();
-    let mut curr_right: Option<Result<csv::StringRecord, error::Error>> = None;
-    
+    Box::new(MergeJoinRows{
+      kind: self.kind,
+      left: JoinCursor::new("left", &self.left_schema, left_indexes, &self.normalize, self.left.rows()),
+      right: JoinCursor::new("right", &self.right_schema, right_indexes, &self.normalize, self.right.rows()),
+      group: None,
+      done: false,
+    })
+  }
+}
+
+// A cursor over one side of a merge join, which holds the current row
+// of that side and the typed values of its joining columns
+struct JoinCursor<'a> {
+  side: &'static str,
+  schema: &'a schema::Schema,
+  indexes: Vec<usize>,
+  normalize: &'a [value::Normalizer],
+  rows: Box<dyn iter::Iterator<Item = Result<csv::StringRecord, error::Error>> + 'a>,
+  curr: Option<(csv::StringRecord, Vec<value::Value>)>,
+}
+
+#[allow(clippy::redundant_field_names)]
+impl<'a> JoinCursor<'a> {
+  fn new(side: &'static str, schema: &'a schema::Schema, indexes: Vec<usize>, normalize: &'a [value::Normalizer], rows: Box<dyn iter::Iterator<Item = Result<csv::StringRecord, error::Error>> + 'a>) -> JoinCursor<'a> {
+    JoinCursor{
+      side: side,
+      schema: schema,
+      indexes: indexes,
+      normalize: normalize,
+      rows: rows,
+      curr: None,
+    }
+  }
+  
+  // Read the next row into the cursor unless it already holds one
+  fn fill(&mut self) -> Result<(), error::Error> {
+    if self.curr.is_some() {
+      return Ok(());
+    }
+    self.curr = match self.rows.next() {
+      Some(Ok(row)) => {
+        let on = match sort::key(self.schema, &self.indexes, self.normalize, &row) {
+          Ok(on) => on,
+          Err(err) => return Err(error::FrameError::new(&format!("Error reading {} side of join: {}", self.side, err)).into()),
+        };
+        Some((row, on))
+      },
+      Some(Err(err)) => return Err(error::FrameError::new(&format!("Error reading {} side of join: {}", self.side, err)).into()),
+      None => None,
+    };
+    Ok(())
+  }
+  
+  // Take the current row, leaving the cursor to be refilled
+  fn take(&mut self) -> Option<csv::StringRecord> {
+    self.curr.take().map(|e| { e.0 })
+  }
+}
+
+// Whether a key has an empty value in any of its columns, in which case
+// it matches no other key, including itself
+fn has_null(on: &[value::Value]) -> bool {
+  on.iter().any(|e| { e.is_null() })
+}
+
+// A run of right rows which share joining values. Each left row with
+// those values is paired with every row in the run, in order.
+struct JoinGroup {
+  on: Vec<value::Value>,
+  rows: Vec<csv::StringRecord>,
+  next: usize, // the next row in the run to pair with the current left row
+}
+
+// The rows of a merge join, which are produced lazily by advancing a
+// cursor over each side, so only the current rows and the current run of
+// equal right rows are held in memory
+struct MergeJoinRows<'a> {
+  kind: ast::JoinKind,
+  left: JoinCursor<'a>,
+  right: JoinCursor<'a>,
+  group: Option<JoinGroup>,
+  done: bool,
+}
+
+#[allow(clippy::redundant_field_names)]
+impl<'a> MergeJoinRows<'a> {
+  fn next_row(&mut self) -> Result<Option<csv::StringRecord>, error::Error> {
     loop {
-      curr_left = match curr_left {
-        Some(val) => Some(val),
-        None => iter_left.next(),
-      };
-      let (left, left_cmp) = match &curr_left {
-        Some(left) => match left {
-          Ok(left) => match left.get(left_index) {
-            Some(cmp) => (Some(left), Some(cmp)),
-            None => (Some(left), None),
-          },
-          Err(err) => {
-            rows.push(Err(error::FrameError::new(&format!("Error reading left side of join: {}", err)).into()));
-            break;
+      self.left.fill()?;
+      
+      if let Some(group) = &mut self.group {
+        match &self.left.curr {
+          Some((left, left_cmp)) if *left_cmp == group.on => {
+            match self.kind {
+              ast::JoinKind::Semi => return Ok(self.left.take()),
+              ast::JoinKind::Anti => {
+                self.left.take();
+              },
+              _ => {
+                if let Some(right) = group.rows.get(group.next) {
+                  group.next += 1;
+                  return Ok(Some(concat_rows(left, right)));
+                }
+                group.next = 0;
+                self.left.take();
+              },
+            }
+            continue;
           },
+          _ => self.group = None,
+        }
+      }
+      
+      self.right.fill()?;
+      let ord = match (&self.left.curr, &self.right.curr) {
+        (Some((_, left_cmp)), Some((_, right_cmp))) => match left_cmp.cmp(right_cmp) {
+          // empty keys never match, so the left row is unmatched, and the
+          // right rows with the same key will be in turn
+          cmp::Ordering::Equal if has_null(left_cmp) => cmp::Ordering::Less,
+          ord => ord,
         },
-        None => (None, None),
+        (Some(_), None) => cmp::Ordering::Less,
+        (None, Some(_)) => cmp::Ordering::Greater,
+        (None, None) => return Ok(None), // no data left; done processing
       };
       
-      curr_right = match curr_right {
-        Some(val) => Some(val),
-        None => iter_right.next(),
-      };
-      let (right, right_cmp) = match &curr_right {
-        Some(right) => match right {
-          Ok(right) => match right.get(right_index) {
-            Some(cmp) => (Some(right), Some(cmp)),
-            None => (Some(right), None),
-          },
-          Err(err) => {
-            rows.push(Err(error::FrameError::new(&format!("Error reading right side of join: {}", err)).into()));
-            break;
-          },
+      match ord {
+        cmp::Ordering::Less => {
+          let left = self.left.take().unwrap();
+          if self.kind.keeps_left() {
+            return Ok(Some(pad_row(&left, self.right.schema, false)));
+          }else if self.kind == ast::JoinKind::Anti {
+            return Ok(Some(left));
+          }
         },
-        None => (None, None),
-      };
+        cmp::Ordering::Greater => {
+          let right = self.right.take().unwrap();
+          if self.kind.keeps_right() {
+            return Ok(Some(pad_row(&right, self.left.schema, true)));
+          }
+        },
+        cmp::Ordering::Equal => {
+          self.group = Some(self.read_group()?);
+        },
+      }
+    }
+  }
+  
+  // Consume the run of right rows which share the current right values
+  fn read_group(&mut self) -> Result<JoinGroup, error::Error> {
+    let on = match &self.right.curr {
+      Some((_, on)) => on.clone(),
+      None => return Err(error::FrameError::new("No right row to join").into()),
+    };
+    let mut rows: Vec<csv::StringRecord> = Vec::new();
+    loop {
+      match &self.right.curr {
+        Some((_, right_cmp)) if *right_cmp == on => rows.push(self.right.take().unwrap()),
+        _ => break,
+      }
+      self.right.fill()?;
+    }
+    Ok(JoinGroup{
+      on: on,
+      rows: rows,
+      next: 0,
+    })
+  }
+}
+
+impl<'a> iter::Iterator for MergeJoinRows<'a> {
+  type Item = Result<csv::StringRecord, error::Error>;
+  
+  fn next(&mut self) -> Option<Self::Item> {
+    if self.done {
+      return None;
+    }
+    match self.next_row() {
+      Ok(Some(row)) => Some(Ok(row)),
+      Ok(None) => {
+        self.done = true;
+        None
+      },
+      Err(err) => {
+        self.done = true;
+        Some(Err(err))
+      },
+    }
+  }
+}
+
+impl<L: Frame, R: Frame> fmt::Display for MergeJoin<L, R> {
+  fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+    write!(f, "({} <{}> {})[{}; {}]", &self.left, self.kind, &self.right, qnames(&self.left_on), qnames(&self.right_on))
+  }
+}
+
+// A frame that as-of joins two frames: each left row is paired with the
+// right row which has equal values in all but the last joining column,
+// and the greatest value at or below the left value in the last. Both
+// input frames are expected to be sorted by their joining columns.
+#[derive(Debug)]
+pub struct AsOfJoin<L: Frame, R: Frame> {
+  kind: ast::JoinKind,
+  
+  left: L,
+  left_schema: schema::Schema,
+  left_on: Vec<schema::QName>,
+  
+  right: R,
+  right_schema: schema::Schema,
+  right_on: Vec<schema::QName>,
+  
+  normalize: Vec<value::Normalizer>,
+  tolerance: Option<value::Tolerance>,
+  join_schema: schema::Schema,
+}
+
+#[allow(clippy::redundant_field_names)]
+impl<L: Frame, R: Frame> AsOfJoin<L, R> {
+  // Only inner and left joins are supported, since a right row may be
+  // paired with any number of left rows
+  pub fn new(kind: ast::JoinKind, left: L, left_on: &[schema::QName], right: R, right_on: &[schema::QName], asof: &ast::AsOf, normalize: &[value::Normalizer]) -> Result<AsOfJoin<L, R>, error::Error> {
+    if kind != ast::JoinKind::Inner && kind != ast::JoinKind::Left {
+      return Err(error::FrameError::new(&format!("An as-of join must be an inner or left join: {}", kind)).into());
+    }
+    if left_on.is_empty() || left_on.len() != right_on.len() {
+      return Err(error::FrameError::new(&format!("Join keys have different numbers of columns: ({}) and ({})", qnames(left_on), qnames(right_on))).into());
+    }
+    let s1 = left.schema().clone();
+    let s2 = right.schema().clone();
+    let sjoin = join_schema(kind, &s1, &s2);
+    
+    Ok(AsOfJoin{
+      kind: kind,
+      
+      left: left,
+      left_schema: s1,
+      left_on: left_on.to_vec(),
+      
+      right: right,
+      right_schema: s2,
+      right_on: right_on.to_vec(),
       
-      let mut row: Vec<String> = Vec::new();
-      if let (Some(left_cmp), Some(right_cmp)) = (left_cmp, right_cmp) {
-        if left_cmp < right_cmp {
-          row.extend(&mut left.unwrap().iter().map(|e| { e.to_owned() }));
-          row.append(&mut self.right_schema.empty_row(0));
-          curr_left = None;
-        }else if right_cmp < left_cmp {
-          row.append(&mut self.left_schema.empty_row(0));
-          row.extend(&mut right.unwrap().iter().map(|e| { e.to_owned() }));
-          curr_right = None;
-        }else{
-          row.extend(&mut left.unwrap().iter().map(|e| { e.to_owned() }));
-          curr_left = None;
-          row.extend(&mut right.unwrap().iter().map(|e| { e.to_owned() }));
-          curr_right = None;
+      normalize: normalize.to_vec(),
+      tolerance: asof.tolerance,
+      join_schema: sjoin,
+    })
+  }
+}
+
+#[allow(clippy::needless_lifetimes)]
+impl<L: Frame, R: Frame> Frame for AsOfJoin<L, R> {
+  fn name<'a>(&'a self) -> &'a str {
+    self.left.name()
+  }
+  
+  fn schema<'a>(&'a self) -> &'a schema::Schema {
+    &self.join_schema
+  }
+  
+  fn sorted_on<'a>(&'a self) -> &'a [schema::QName] {
+    self.left.sorted_on()
+  }
+  
+  fn size_hint(&self) -> Option<u64> {
+    join_size(self.kind, &self.left, &self.right)
+  }
+  
+  fn rows<'a>(&'a mut self) -> Box<dyn iter::Iterator<Item = Result<csv::StringRecord, error::Error>> + 'a> {
+    let left_indexes = match key_indexes(&self.left_schema, &self.left_on) {
+      Ok(indexes) => indexes,
+      Err(err) => return Box::new(iter::once(Err(err))),
+    };
+    let right_indexes = match key_indexes(&self.right_schema, &self.right_on) {
+      Ok(indexes) => indexes,
+      Err(err) => return Box::new(iter::once(Err(err))),
+    };
+    
+    let mut left = JoinCursor::new("left", &self.left_schema, left_indexes, &self.normalize, self.left.rows());
+    let mut right = JoinCursor::new("right", &self.right_schema, right_indexes, &self.normalize, self.right.rows());
+    let (kind, tolerance) = (self.kind, self.tolerance);
+    let mut last: Option<(csv::StringRecord, Vec<value::Value>)> = None; // the greatest right row at or below the current left row
+    let mut done = false;
+    
+    Box::new(iter::from_fn(move || {
+      while !done {
+        let (row, on) = match left.fill().map(|_| { left.curr.take() }) {
+          Ok(Some(curr)) => curr,
+          Ok(None) => return None,
+          Err(err) => {
+            done = true;
+            return Some(Err(err));
+          },
+        };
+        
+        // advance the right side past every row at or below the left row
+        loop {
+          if let Err(err) = right.fill() {
+            done = true;
+            return Some(Err(err));
+          }
+          match &right.curr {
+            Some((_, right_on)) if *right_on <= on => last = right.curr.take(),
+            _ => break,
+          }
+        }
+        
+        let (by, at) = on.split_at(on.len() - 1);
+        let matched = match &last {
+          Some((right_row, right_on)) if right_on.starts_with(by) && !has_null(&on) && !right_on[by.len()].is_null() => match tolerance.map(|e| { e.within(&at[0], &right_on[by.len()]) }) {
+            Some(Err(err)) => {
+              done = true;
+              return Some(Err(err));
+            },
+            Some(Ok(false)) => None,
+            _ => Some(right_row),
+          },
+          _ => None,
+        };
+        
+        match matched {
+          Some(right_row) => return Some(Ok(concat_rows(&row, right_row))),
+          None => if kind.keeps_left() {
+            return Some(Ok(pad_row(&row, right.schema, false)));
+          },
         }
-      }else if let Some(_) = left_cmp {
-        row.extend(&mut left.unwrap().iter().map(|e| { e.to_owned() }));
-        row.append(&mut self.right_schema.empty_row(0));
-        curr_left = None;
-      }else if let Some(_) = right_cmp {
-        row.append(&mut self.left_schema.empty_row(0));
-        row.extend(&mut right.unwrap().iter().map(|e| { e.to_owned() }));
-        curr_right = None;
-      }else{
-        break; // no data left; done processing
       }
-      
-      rows.push(Ok(row.into()));
+      None
+    }))
+  }
+}
+
+impl<L: Frame, R: Frame> fmt::Display for AsOfJoin<L, R> {
+  fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+    write!(f, "({} <{} ASOF> {})[{}; {}]", &self.left, self.kind, &self.right, qnames(&self.left_on), qnames(&self.right_on))
+  }
+}
+
+
+// A frame whose columns are retyped, so that their fields are read as
+// values of other types; its rows are those of its source. Retyped
+// columns may no longer be in the order of their values, so the frame
+// is only sorted on the columns which precede them.
+#[derive(Debug)]
+pub struct Retyped<F: Frame> {
+  sorted: usize,
+  schema: schema::Schema,
+  data: F,
+}
+
+#[allow(clippy::redundant_field_names)]
+impl<F: Frame> Retyped<F> {
+  pub fn new(source: F, types: &[(schema::QName, value::Type)]) -> Result<Retyped<F>, error::Error> {
+    let mut schema = source.schema().clone();
+    let on: Vec<schema::QName> = types.iter().map(|e| { e.0.clone() }).collect();
+    for (index, (_, typ)) in key_indexes(&schema, &on)?.into_iter().zip(types) {
+      schema.set_column_type(index, *typ);
     }
-    
-    Box::new(rows.into_iter())
+    let sorted = source.sorted_on().iter().position(|e| { on.contains(e) }).unwrap_or(source.sorted_on().len());
+    Ok(Retyped{
+      sorted: sorted,
+      schema: schema,
+      data: source,
+    })
   }
 }
 
-impl<L: Frame, R: Frame> fmt::Display for OuterJoin<L, R> {
+#[allow(clippy::needless_lifetimes)]
+impl<F: Frame> Frame for Retyped<F> {
+  fn name<'a>(&'a self) -> &'a str {
+    self.data.name()
+  }
+  
+  fn schema<'a>(&'a self) -> &'a schema::Schema {
+    &self.schema
+  }
+  
+  fn sorted_on<'a>(&'a self) -> &'a [schema::QName] {
+    &self.data.sorted_on()[..self.sorted]
+  }
+  
+  fn size_hint(&self) -> Option<u64> {
+    self.data.size_hint()
+  }
+  
+  fn rows<'a>(&'a mut self) -> Box<dyn iter::Iterator<Item = Result<csv::StringRecord, error::Error>> + 'a> {
+    self.data.rows()
+  }
+}
+
+impl<F: Frame> fmt::Display for Retyped<F> {
+  fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+    write!(f, "{}", &self.data)
+  }
+}
+
+// A frame that merges pairs of columns, typically the keys of a join,
+// into a single column which holds the value of the first column of the
+// pair, or of the second if the first is empty. The second column of
+// each pair is removed, but its name continues to refer to the merged
+// column.
+#[derive(Debug)]
+pub struct Coalesce<F: Frame> {
+  pairs: Vec<(usize, usize)>,
+  keep: Vec<usize>,
+  schema: schema::Schema,
+  data: F,
+}
+
+#[allow(clippy::redundant_field_names)]
+impl<F: Frame> Coalesce<F> {
+  pub fn new(source: F, on: &[(schema::QName, schema::QName)]) -> Result<Coalesce<F>, error::Error> {
+    let src = source.schema();
+    let mut pairs: Vec<(usize, usize)> = Vec::new();
+    for (a, b) in on {
+      let indexes = key_indexes(src, &[a.clone(), b.clone()])?;
+      if indexes[0] != indexes[1] {
+        pairs.push((indexes[0], indexes[1]));
+      }
+    }
+    let keep: Vec<usize> = (0..src.count()).filter(|e| { !pairs.iter().any(|p| { p.1 == *e }) }).collect();
+    let schema = src.coalesce(&pairs);
+    Ok(Coalesce{
+      pairs: pairs,
+      keep: keep,
+      schema: schema,
+      data: source,
+    })
+  }
+}
+
+#[allow(clippy::needless_lifetimes)]
+impl<F: Frame> Frame for Coalesce<F> {
+  fn name<'a>(&'a self) -> &'a str {
+    self.data.name()
+  }
+  
+  fn schema<'a>(&'a self) -> &'a schema::Schema {
+    &self.schema
+  }
+  
+  fn sorted_on<'a>(&'a self) -> &'a [schema::QName] {
+    self.data.sorted_on()
+  }
+  
+  fn size_hint(&self) -> Option<u64> {
+    self.data.size_hint()
+  }
+  
+  fn rows<'a>(&'a mut self) -> Box<dyn iter::Iterator<Item = Result<csv::StringRecord, error::Error>> + 'a> {
+    let (pairs, keep) = (&self.pairs, &self.keep);
+    Box::new(self.data.rows().map(move |e| {
+      let row = e?;
+      let mut res: Vec<&str> = row.iter().collect();
+      for (a, b) in pairs {
+        if res.get(*a).is_some_and(|e| { e.is_empty() }) {
+          res[*a] = row.get(*b).unwrap_or_default();
+        }
+      }
+      Ok(keep.iter().map(|e| { res.get(*e).copied().unwrap_or_default() }).collect())
+    }))
+  }
+}
+
+impl<F: Frame> fmt::Display for Coalesce<F> {
   fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
-    write!(f, "({} <> {})[{}, {}]", &self.left, &self.right, &self.left_on, &self.right_on)
+    write!(f, "{}<coalesce: {:?}>", &self.data, &self.pairs)
+  }
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+  
+  fn join(left: &'static str, right: &'static str) -> Vec<Vec<String>> {
+    join_kind(ast::JoinKind::Full, left, right)
+  }
+  
+  fn join_kind(kind: ast::JoinKind, left: &'static str, right: &'static str) -> Vec<Vec<String>> {
+    let opts = CsvOptions::default();
+    let left = Csv::new("a", left.as_bytes(), &opts).unwrap();
+    let right = Csv::new("b", right.as_bytes(), &opts).unwrap();
+    let mut frm = MergeJoin::new(kind, left, &[schema::QName::parse("a.id").unwrap()], right, &[schema::QName::parse("b.id").unwrap()], &[]).unwrap();
+    frm.rows().map(|e| { e.unwrap().iter().map(|e| { e.to_owned() }).collect() }).collect()
+  }
+  
+  // Hash join, indexing the left side if swapped and otherwise the right
+  fn hash_join(kind: ast::JoinKind, swapped: bool, left: &'static str, right: &'static str) -> Vec<Vec<String>> {
+    let opts = CsvOptions::default();
+    let mut left = Csv::new("a", left.as_bytes(), &opts).unwrap();
+    let mut right = Csv::new("b", right.as_bytes(), &opts).unwrap();
+    let (left_on, right_on) = ([schema::QName::parse("a.id").unwrap()], [schema::QName::parse("b.id").unwrap()]);
+    let mut frm: Box<dyn Frame> = if swapped {
+      Box::new(HashJoin::new_swapped(kind, HashIndex::new(&mut left, &left_on, &[]).unwrap(), right, &right_on).unwrap())
+    }else{
+      Box::new(HashJoin::new(kind, left, &left_on, HashIndex::new(&mut right, &right_on, &[]).unwrap()).unwrap())
+    };
+    frm.rows().map(|e| { e.unwrap().iter().map(|e| { e.to_owned() }).collect() }).collect()
+  }
+  
+  fn rows(text: &str) -> Vec<Vec<String>> {
+    text.lines().map(|e| { e.split(',').map(|e| { e.to_owned() }).collect() }).collect()
+  }
+  
+  #[test]
+  fn outer_join_one_to_many() {
+    let res = join("id,x\n1,a\n2,b\n3,c\n", "id,y\n1,p\n2,q\n2,r\n2,s\n4,t\n");
+    assert_eq!(rows("1,a,1,p\n2,b,2,q\n2,b,2,r\n2,b,2,s\n3,c,,\n,,4,t"), res);
+  }
+  
+  #[test]
+  fn outer_join_many_to_one() {
+    let res = join("id,x\n1,a\n1,b\n1,c\n2,d\n", "id,y\n0,p\n1,q\n2,r\n");
+    assert_eq!(rows(",,0,p\n1,a,1,q\n1,b,1,q\n1,c,1,q\n2,d,2,r"), res);
+  }
+  
+  #[test]
+  fn outer_join_many_to_many() {
+    let res = join("id,x\n1,a\n2,b\n2,c\n3,d\n", "id,y\n2,p\n2,q\n2,r\n3,s\n3,t\n");
+    assert_eq!(rows("1,a,,\n2,b,2,p\n2,b,2,q\n2,b,2,r\n2,c,2,p\n2,c,2,q\n2,c,2,r\n3,d,3,s\n3,d,3,t"), res);
+  }
+  
+  #[test]
+  fn join_kinds() {
+    let (left, right) = ("id,x\n1,a\n2,b\n3,c\n", "id,y\n2,p\n3,q\n3,r\n4,s\n");
+    let expected = [
+      (ast::JoinKind::Inner, "2,b,2,p\n3,c,3,q\n3,c,3,r"),
+      (ast::JoinKind::Left, "1,a,,\n2,b,2,p\n3,c,3,q\n3,c,3,r"),
+      (ast::JoinKind::Right, "2,b,2,p\n3,c,3,q\n3,c,3,r\n,,4,s"),
+      (ast::JoinKind::Full, "1,a,,\n2,b,2,p\n3,c,3,q\n3,c,3,r\n,,4,s"),
+      (ast::JoinKind::Semi, "2,b\n3,c"),
+      (ast::JoinKind::Anti, "1,a"),
+    ];
+    for (kind, res) in expected {
+      assert_eq!(rows(res), join_kind(kind, left, right), "merge {}", kind);
+      assert_eq!(rows(res), hash_join(kind, false, left, right), "hash {}", kind);
+    }
+  }
+  
+  #[test]
+  fn swapped_hash_join_kinds() {
+    // rows follow the right side, followed by unmatched left rows
+    let (left, right) = ("id,x\n1,a\n2,b\n3,c\n", "id,y\n2,p\n3,q\n3,r\n4,s\n");
+    let expected = [
+      (ast::JoinKind::Inner, "2,b,2,p\n3,c,3,q\n3,c,3,r"),
+      (ast::JoinKind::Left, "2,b,2,p\n3,c,3,q\n3,c,3,r\n1,a,,"),
+      (ast::JoinKind::Right, "2,b,2,p\n3,c,3,q\n3,c,3,r\n,,4,s"),
+      (ast::JoinKind::Full, "2,b,2,p\n3,c,3,q\n3,c,3,r\n,,4,s\n1,a,,"),
+    ];
+    for (kind, res) in expected {
+      assert_eq!(rows(res), hash_join(kind, true, left, right), "{}", kind);
+    }
+  }
+  
+  #[test]
+  fn hash_join_empty_keys() {
+    let (left, right) = ("id,x\n,a\n1,c\n", "id,y\n,p\n1,q\n");
+    assert_eq!(rows(",a,,\n1,c,1,q\n,,,p"), hash_join(ast::JoinKind::Full, false, left, right));
+    assert_eq!(rows(",,,p\n1,c,1,q\n,a,,"), hash_join(ast::JoinKind::Full, true, left, right));
+    assert_eq!(rows("1,c,1,q"), hash_join(ast::JoinKind::Inner, false, left, right));
+    assert_eq!(rows("1,c"), hash_join(ast::JoinKind::Semi, false, left, right));
+    assert_eq!(rows(",a"), hash_join(ast::JoinKind::Anti, false, left, right));
+  }
+  
+  #[test]
+  fn composite_keys() {
+    let opts = CsvOptions::default();
+    let (left, right) = ("country,sku,x\nde,1,a\nde,2,b\nfr,1,c\n", "sku,country,y\n2,de,q\n1,fr,p\n1,uk,r\n");
+    let qnames = |e: &[&str]| -> Vec<schema::QName> { e.iter().map(|e| { schema::QName::parse(e).unwrap() }).collect() };
+    let (left_on, right_on) = (qnames(&["a.country", "a.sku"]), qnames(&["b.country", "b.sku"]));
+    let expected = rows("de,1,a,,,\nde,2,b,2,de,q\nfr,1,c,1,fr,p\n,,,1,uk,r");
+    
+    let mut frm = MergeJoin::new(ast::JoinKind::Full, Csv::new("a", left.as_bytes(), &opts).unwrap(), &left_on, Csv::new("b", right.as_bytes(), &opts).unwrap(), &right_on, &[]).unwrap();
+    let res: Vec<Vec<String>> = frm.rows().map(|e| { e.unwrap().iter().map(|e| { e.to_owned() }).collect() }).collect();
+    assert_eq!(expected, res);
+    
+    let index = HashIndex::new(&mut Csv::new("b", right.as_bytes(), &opts).unwrap(), &right_on, &[]).unwrap();
+    let mut frm = HashJoin::new(ast::JoinKind::Full, Csv::new("a", left.as_bytes(), &opts).unwrap(), &left_on, index).unwrap();
+    let res: Vec<Vec<String>> = frm.rows().map(|e| { e.unwrap().iter().map(|e| { e.to_owned() }).collect() }).collect();
+    assert_eq!(expected, res);
+    
+    // keys of different lengths
+    let err = MergeJoin::new(ast::JoinKind::Full, Csv::new("a", left.as_bytes(), &opts).unwrap(), &left_on, Csv::new("b", right.as_bytes(), &opts).unwrap(), &right_on[..1], &[]).unwrap_err();
+    assert_eq!("Join keys have different numbers of columns: (a.country, a.sku) and (b.country)", err.to_string());
+    let index = HashIndex::new(&mut Csv::new("b", right.as_bytes(), &opts).unwrap(), &right_on[..1], &[]).unwrap();
+    let err = HashJoin::new(ast::JoinKind::Full, Csv::new("a", left.as_bytes(), &opts).unwrap(), &left_on, index).unwrap_err();
+    assert_eq!("Join keys have different numbers of columns: (a.country, a.sku) and (b.country)", err.to_string());
+  }
+  
+  #[test]
+  fn coalesce() {
+    let opts = CsvOptions::default();
+    let left = Csv::new("a", "id,x\n1,a\n2,b\n".as_bytes(), &opts).unwrap();
+    let right = Csv::new("b", "key,y\n2,p\n3,q\n".as_bytes(), &opts).unwrap();
+    let (left_on, right_on) = ([schema::QName::parse("a.id").unwrap()], [schema::QName::parse("b.key").unwrap()]);
+    let join = MergeJoin::new(ast::JoinKind::Full, left, &left_on, right, &right_on, &[]).unwrap();
+    let mut frm = Coalesce::new(join, &[(left_on[0].clone(), right_on[0].clone())]).unwrap();
+    
+    // the merged column is unqualified, and either name refers to it
+    assert_eq!(vec!["id", "a.x", "b.y"], frm.schema().header(schema::Header::Qualified).unwrap());
+    for name in ["id", "a.id", "b.key"] {
+      assert_eq!(Some(0), frm.schema().index(&schema::QName::parse(name).unwrap()), "{}", name);
+    }
+    let res: Vec<Vec<String>> = frm.rows().map(|e| { e.unwrap().iter().map(|e| { e.to_owned() }).collect() }).collect();
+    assert_eq!(rows("1,a,\n2,b,p\n3,,q"), res);
+    
+    // aliases carry over into further joins
+    let other = Csv::new("c", "id,z\n3,s\n".as_bytes(), &opts).unwrap();
+    let joined = frm.schema().join(other.schema());
+    assert_eq!(vec!["id", "a.x", "b.y", "c.id", "c.z"], joined.header(schema::Header::Qualified).unwrap());
+    for name in ["a.id", "b.key"] {
+      assert_eq!(Some(0), joined.index(&schema::QName::parse(name).unwrap()), "{}", name);
+    }
+  }
+  
+  #[test]
+  fn normalized_keys() {
+    let opts = CsvOptions::default();
+    let (left, right) = ("id,x\n-007,a\n 0042,b\nABC,c\n", "id,y\n42,q\nabc,r\n-7,p\n");
+    let (left_on, right_on) = ([schema::QName::parse("a.id").unwrap()], [schema::QName::parse("b.id").unwrap()]);
+    let normalize = value::Normalizer::parse_list("trim, zeros, lower").unwrap();
+    let collect = |frm: &mut dyn Frame| -> Vec<Vec<String>> { frm.rows().map(|e| { e.unwrap().iter().map(|e| { e.to_owned() }).collect() }).collect() };
+    
+    let sort_opts = sort::SortOptions::default();
+    let left_sorted = Sorted::new(&mut Csv::new("a", left.as_bytes(), &opts).unwrap(), &left_on, &normalize, &sort_opts).unwrap();
+    let right_sorted = Sorted::new(&mut Csv::new("b", right.as_bytes(), &opts).unwrap(), &right_on, &normalize, &sort_opts).unwrap();
+    let mut frm = MergeJoin::new(ast::JoinKind::Inner, left_sorted, &left_on, right_sorted, &right_on, &normalize).unwrap();
+    assert_eq!(rows("-007,a,-7,p\n 0042,b,42,q\nABC,c,abc,r"), collect(&mut frm));
+    
+    let index = HashIndex::new(&mut Csv::new("b", right.as_bytes(), &opts).unwrap(), &right_on, &normalize).unwrap();
+    let mut frm = HashJoin::new(ast::JoinKind::Inner, Csv::new("a", left.as_bytes(), &opts).unwrap(), &left_on, index).unwrap();
+    assert_eq!(rows("-007,a,-7,p\n 0042,b,42,q\nABC,c,abc,r"), collect(&mut frm));
+  }
+  
+  fn asof(kind: ast::JoinKind, tolerance: Option<&str>) -> Result<Vec<Vec<String>>, error::Error> {
+    let opts = CsvOptions{
+      types: vec![(schema::QName::parse("ts").unwrap(), value::Type::Integer)],
+      ..CsvOptions::default()
+    };
+    let left = "sym,ts,x\na,5,l2\n,4,l0\na,,lx\na,1,l1\na,10,l3\nb,2,l4\nb,7,l5\nc,3,l6\n";
+    let right = "sym,ts,y\na,2,q1\na,9,q3\n,3,q5\na,4,q2\nb,1,q4\n";
+    let qnames = |e: &[&str]| -> Vec<schema::QName> { e.iter().map(|e| { schema::QName::parse(e).unwrap() }).collect() };
+    let (left_on, right_on) = (qnames(&["a.sym", "a.ts"]), qnames(&["b.sym", "b.ts"]));
+    let sort_opts = sort::SortOptions::default();
+    let left = Sorted::new(&mut Csv::new("a", left.as_bytes(), &opts).unwrap(), &left_on, &[], &sort_opts).unwrap();
+    let right = Sorted::new(&mut Csv::new("b", right.as_bytes(), &opts).unwrap(), &right_on, &[], &sort_opts).unwrap();
+    let asof = ast::AsOf{
+      tolerance: tolerance.map(|e| { value::Tolerance::parse(e).unwrap() }),
+    };
+    let mut frm = AsOfJoin::new(kind, left, &left_on, right, &right_on, &asof, &[])?;
+    let res: Vec<Vec<String>> = frm.rows().map(|e| { e.unwrap().iter().map(|e| { e.to_owned() }).collect() }).collect();
+    Ok(res)
+  }
+  
+  #[test]
+  fn asof_join() {
+    // rows are paired within each symbol, never on an empty symbol or time
+    let res = asof(ast::JoinKind::Left, None).unwrap();
+    assert_eq!(rows(",4,l0,,,\na,,lx,,,\na,1,l1,,,\na,5,l2,a,4,q2\na,10,l3,a,9,q3\nb,2,l4,b,1,q4\nb,7,l5,b,1,q4\nc,3,l6,,,"), res);
+    
+    let res = asof(ast::JoinKind::Inner, None).unwrap();
+    assert_eq!(rows("a,5,l2,a,4,q2\na,10,l3,a,9,q3\nb,2,l4,b,1,q4\nb,7,l5,b,1,q4"), res);
+  }
+  
+  #[test]
+  fn asof_join_tolerance() {
+    let res = asof(ast::JoinKind::Left, Some("1")).unwrap();
+    assert_eq!(rows(",4,l0,,,\na,,lx,,,\na,1,l1,,,\na,5,l2,a,4,q2\na,10,l3,a,9,q3\nb,2,l4,b,1,q4\nb,7,l5,,,\nc,3,l6,,,"), res);
+    
+    let res = asof(ast::JoinKind::Inner, Some("0")).unwrap();
+    assert!(res.is_empty());
+  }
+  
+  #[test]
+  fn asof_join_kinds() {
+    for kind in [ast::JoinKind::Right, ast::JoinKind::Full, ast::JoinKind::Semi, ast::JoinKind::Anti] {
+      let err = asof(kind, None).unwrap_err();
+      assert_eq!(format!("An as-of join must be an inner or left join: {}", kind), err.to_string());
+    }
+  }
+  
+  #[test]
+  fn outer_join_empty_keys() {
+    let res = join("id,x\n,a\n,b\n1,c\n", "id,y\n,p\n1,q\n");
+    assert_eq!(rows(",a,,\n,b,,\n,,,p\n1,c,1,q"), res);
+    
+    let res = join_kind(ast::JoinKind::Inner, "id,x\n,a\n,b\n1,c\n", "id,y\n,p\n1,q\n");
+    assert_eq!(rows("1,c,1,q"), res);
+  }
+  
+  #[allow(clippy::redundant_field_names)]
+  fn sort(text: &'static str, direction: ast::Direction, nulls: Option<ast::Nulls>) -> (Vec<Vec<String>>, Vec<schema::QName>) {
+    let opts = CsvOptions{
+      types: vec![(schema::QName::parse("n").unwrap(), value::Type::Integer)],
+      ..CsvOptions::default()
+    };
+    let mut source = Csv::new("a", text.as_bytes(), &opts).unwrap();
+    let order = ast::Order{
+      on: schema::QName::parse("a.n").unwrap(),
+      direction: direction,
+      nulls: nulls,
+    };
+    let mut frm = Sorted::new_with_order(&mut source, &[order], &sort::SortOptions::default()).unwrap();
+    let on = frm.sorted_on().to_vec();
+    (frm.rows().map(|e| { e.unwrap().iter().map(|e| { e.to_owned() }).collect() }).collect(), on)
+  }
+  
+  #[test]
+  fn sort_direction() {
+    let (res, on) = sort("n,x\n10,a\n9,b\n,c\n10,d\n", ast::Direction::Asc, None);
+    assert_eq!(rows(",c\n9,b\n10,a\n10,d"), res);
+    assert_eq!(vec![schema::QName::parse("a.n").unwrap()], on);
+    
+    let (res, on) = sort("n,x\n10,a\n9,b\n,c\n10,d\n", ast::Direction::Desc, None);
+    assert_eq!(rows("10,a\n10,d\n9,b\n,c"), res);
+    assert!(on.is_empty());
+  }
+  
+  #[test]
+  fn sort_nulls() {
+    let (res, on) = sort("n,x\n2,a\n,b\n1,c\n", ast::Direction::Asc, Some(ast::Nulls::Last));
+    assert_eq!(rows("1,c\n2,a\n,b"), res);
+    assert!(on.is_empty());
+    
+    let (res, on) = sort("n,x\n2,a\n,b\n1,c\n", ast::Direction::Asc, Some(ast::Nulls::First));
+    assert_eq!(rows(",b\n1,c\n2,a"), res);
+    assert_eq!(vec![schema::QName::parse("a.n").unwrap()], on);
+    
+    let (res, on) = sort("n,x\n2,a\n,b\n1,c\n", ast::Direction::Desc, Some(ast::Nulls::First));
+    assert_eq!(rows(",b\n2,a\n1,c"), res);
+    assert!(on.is_empty());
+  }
+  
+  #[test]
+  fn sort_spilled() {
+    let opts = CsvOptions{
+      types: vec![(schema::QName::parse("n").unwrap(), value::Type::Integer)],
+      ..CsvOptions::default()
+    };
+    let text = "n,x\n5,a\n3,b\n,c\n8,d\n3,e\n1,f\n5,g\n10,h\n";
+    let mut source = Csv::new("a", text.as_bytes(), &opts).unwrap();
+    let sort_opts = sort::SortOptions{
+      memory: Some(150), // a few rows per run
+      ..sort::SortOptions::default()
+    };
+    let mut frm = Sorted::new(&mut source, &[schema::QName::parse("a.n").unwrap()], &[], &sort_opts).unwrap();
+    match &frm.data {
+      SortedData::Spilled(runs) => assert!(runs.len() > 2),
+      SortedData::Memory(_) => panic!("expected the rows to spill"),
+    }
+    let res: Vec<Vec<String>> = frm.rows().map(|e| { e.unwrap().iter().map(|e| { e.to_owned() }).collect() }).collect();
+    assert_eq!(rows(",c\n1,f\n3,b\n3,e\n5,a\n5,g\n8,d\n10,h"), res);
+  }
+  
+  fn read_json(text: &str, opts: &CsvOptions) -> (Vec<String>, Vec<value::Type>, Vec<Vec<String>>) {
+    let mut frm = Json::new("a", text.as_bytes(), opts).unwrap();
+    let names = frm.schema().columns().iter().map(|e| { e.to_string() }).collect();
+    let types = (0..frm.schema().count()).map(|e| { frm.schema().column_type(e) }).collect();
+    let res = frm.rows().map(|e| { e.unwrap().iter().map(|e| { e.to_owned() }).collect() }).collect();
+    (names, types, res)
+  }
+  
+  #[test]
+  fn json_flatten() {
+    let text = r#"[{"id": 1, "user": {"name": "x", "address": {"city": "p"}}, "tags": ["a", "b"]}]"#;
+    let (names, _, res) = read_json(text, &CsvOptions::default());
+    assert_eq!(vec!["a.id", "a.user.name", "a.user.address.city", "a.tags"], names);
+    assert_eq!(vec![vec!["1", "x", "p", "[\"a\",\"b\"]"]], res);
+  }
+  
+  #[test]
+  fn json_duplicate_keys() {
+    let err = Json::new("a", "{\"a\": 1}\n{\"a.b\": 1, \"a\": {\"b\": 2}}\n".as_bytes(), &CsvOptions::default()).unwrap_err();
+    assert_eq!("a: duplicate key in record 2: a.b", err.to_string());
+  }
+  
+  #[test]
+  fn json_union_of_keys() {
+    // keys are ordered as they are first seen, and missing ones are empty
+    let text = "{\"b\": 1, \"a\": 2}\n{\"c\": 3, \"a\": 4}\n{\"d\": {\"e\": 5}, \"b\": null}\n";
+    let (names, _, res) = read_json(text, &CsvOptions::default());
+    assert_eq!(vec!["a.b", "a.a", "a.c", "a.d.e"], names);
+    assert_eq!(rows("1,2,,\n,4,3,\n,,,5"), res);
+  }
+  
+  #[test]
+  fn json_types() {
+    let opts = CsvOptions{
+      infer: Some(100),
+      ..CsvOptions::default()
+    };
+    let text = concat!(
+      // m mixes an integer and a string, whose text alone would be boolean
+      "{\"i\": 1, \"f\": 1, \"b\": true, \"s\": \"1\", \"m\": 1, \"n\": null, \"t\": \"2024-01-02\"}\n",
+      "{\"i\": 2, \"f\": 2.5, \"b\": null, \"s\": \"x\", \"m\": \"y\", \"n\": null, \"t\": \"2024-01-03\"}\n",
+    );
+    let (_, types, res) = read_json(text, &opts);
+    assert_eq!(vec![value::Type::Integer, value::Type::Float, value::Type::Boolean, value::Type::String, value::Type::String, value::Type::String, value::Type::Date], types);
+    assert_eq!(rows("1,1,true,1,1,,2024-01-02\n2,2.5,,x,y,,2024-01-03"), res);
+    
+    // declared types take precedence over those of the values
+    let opts = CsvOptions{
+      types: vec![(schema::QName::parse("i").unwrap(), value::Type::String)],
+      ..opts
+    };
+    assert_eq!(value::Type::String, read_json(text, &opts).1[0]);
+    
+    // integers beyond the range of an integer are exact decimals
+    let opts = CsvOptions{
+      infer: Some(100),
+      ..CsvOptions::default()
+    };
+    let (_, types, res) = read_json("{\"n\": 1}\n{\"n\": 18446744073709551615}\n", &opts);
+    assert_eq!(vec![value::Type::Decimal], types);
+    assert_eq!(rows("1\n18446744073709551615"), res);
+  }
+  
+  #[test]
+  fn json_definition() {
+    let text = r#"{"columns": [{"name": "id", "type": "integer"}, {"name": "city", "header": "address.city"}]}"#;
+    let opts = CsvOptions{
+      definition: Some(definition::Definition::parse(text, true).unwrap()),
+      ..CsvOptions::default()
+    };
+    
+    let (names, types, res) = read_json("{\"address\": {\"city\": \"p\"}, \"id\": 1}\n{\"id\": 2}\n", &opts);
+    assert_eq!(vec!["a.id", "a.city"], names);
+    assert_eq!(value::Type::Integer, types[0]);
+    assert_eq!(rows("1,p\n2,"), res);
+    
+    // keys which are not declared are rejected, as extra CSV columns are
+    let err = Json::new("a", "{\"id\": 1, \"name\": \"x\", \"address\": {\"zip\": 2}}\n".as_bytes(), &opts).unwrap_err();
+    assert_eq!("a: schema declares 2 columns but the input also has name, address.zip", err.to_string());
+  }
+  
+  #[test]
+  fn json_size_hint() {
+    // the size of the rows as CSV, as for a CSV input
+    let frm = Json::new("a", "{\"id\": 1, \"x\": \"ab\"}\n{\"id\": 22, \"x\": null}\n".as_bytes(), &CsvOptions{ size: Some(1), ..CsvOptions::default() }).unwrap();
+    assert_eq!(Some("1,ab\n22,\n".len() as u64), frm.size_hint());
+  }
+  
+  #[test]
+  fn declared_types() {
+    let declare = |qname: &str| {
+      let opts = CsvOptions{
+        types: vec![(schema::QName::parse(qname).unwrap(), value::Type::Integer)],
+        ..CsvOptions::default()
+      };
+      Csv::new("a", "id,x\n1,p\n".as_bytes(), &opts).map(|e| { e.schema().column_type(0) })
+    };
+    assert_eq!(value::Type::Integer, declare("a.id").unwrap());
+    assert_eq!(value::Type::Integer, declare("id").unwrap());
+    assert_eq!(value::Type::String, declare("b.id").unwrap()); // for another input
+    assert_eq!(value::Type::String, declare("zz").unwrap()); // for whichever inputs have it
+    assert_eq!("Column not found: a.zz (id, x)", declare("a.zz").unwrap_err().to_string());
   }
 }
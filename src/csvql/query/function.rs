@@ -0,0 +1,402 @@
+use std::fmt;
+
+use chrono;
+use chrono::{Datelike, Timelike};
+use regex;
+
+use crate::csvql::query::ast;
+use crate::csvql::query::value;
+use crate::csvql::query::error;
+
+// The units to which date_trunc truncates a date or timestamp
+const DATE_UNITS: &[&str] = &["year", "quarter", "month", "week", "day", "hour", "minute", "second"];
+
+// A scalar function, which computes a value from the values of its
+// arguments for each row
+#[derive(Debug, Clone)]
+pub enum Function {
+  Abs,
+  Round,
+  Concat,
+  Substr,
+  Lower,
+  Upper,
+  Trim,
+  Length,
+  Replace,
+  RegexExtract(Option<regex::Regex>), // the pattern, if it is a literal
+  Coalesce,
+  DateTrunc,
+  ToDate,
+  ToTimestamp,
+}
+
+impl Function {
+  // Resolve a function by name and check its arguments
+  pub fn new(name: &str, args: &[ast::Expr]) -> Result<Function, error::Error> {
+    let (func, min, max) = match name.to_lowercase().as_ref() {
+      "abs" => (Self::Abs, 1, Some(1)),
+      "round" => (Self::Round, 1, Some(2)),
+      "concat" => (Self::Concat, 1, None),
+      "substr" | "substring" => (Self::Substr, 2, Some(3)),
+      "lower" => (Self::Lower, 1, Some(1)),
+      "upper" => (Self::Upper, 1, Some(1)),
+      "trim" => (Self::Trim, 1, Some(1)),
+      "length" => (Self::Length, 1, Some(1)),
+      "replace" => (Self::Replace, 3, Some(3)),
+      "regex_extract" => (Self::RegexExtract(None), 2, Some(2)),
+      "coalesce" => (Self::Coalesce, 1, None),
+      "date_trunc" => (Self::DateTrunc, 2, Some(2)),
+      "to_date" => (Self::ToDate, 1, Some(2)),
+      "to_timestamp" => (Self::ToTimestamp, 1, Some(2)),
+      _ => return Err(error::QueryError::new(&format!("No such function: {}", name)).into()),
+    };
+    if args.len() < min || max.is_some_and(|e| { args.len() > e }) {
+      let expect = match max {
+        Some(max) if max == min => format!("{}", min),
+        Some(max) => format!("{} to {}", min, max),
+        None => format!("at least {}", min),
+      };
+      return Err(error::QueryError::new(&format!("Function {} expects {} arguments, not {}", func, expect, args.len())).into());
+    }
+    
+    // literal patterns and units are checked, and patterns compiled, once
+    match (func, args.get(1)) {
+      (Self::RegexExtract(_), Some(ast::Expr::Literal(ast::Literal::String(pattern)))) => Ok(Self::RegexExtract(Some(Self::regex(pattern)?))),
+      (Self::DateTrunc, _) => {
+        if let Some(ast::Expr::Literal(ast::Literal::String(unit))) = args.first() {
+          Self::date_unit(unit)?;
+        }
+        Ok(Self::DateTrunc)
+      },
+      (func, _) => Ok(func),
+    }
+  }
+  
+  // The type of the value produced by this function for arguments of
+  // the specified types
+  pub fn result_type(&self, args: &[value::Type]) -> value::Type {
+    match self {
+      Self::Abs | Self::Round => match args.first() {
+        Some(value::Type::Integer) => value::Type::Integer,
+        Some(value::Type::Float) => value::Type::Float,
+        _ => value::Type::Decimal,
+      },
+      Self::Length => value::Type::Integer,
+      Self::Coalesce => match args.first() {
+        Some(typ) if args.iter().all(|e| { e == typ }) => *typ,
+        _ => value::Type::String,
+      },
+      Self::DateTrunc => match args.get(1) {
+        Some(value::Type::Date) => value::Type::Date,
+        _ => value::Type::Timestamp,
+      },
+      Self::ToDate => value::Type::Date,
+      Self::ToTimestamp => value::Type::Timestamp,
+      _ => value::Type::String,
+    }
+  }
+  
+  // Apply this function to the values of its arguments. Except for
+  // concat and coalesce, a null argument produces null.
+  pub fn apply(&self, args: Vec<value::Value>) -> Result<value::Value, error::Error> {
+    match self {
+      Self::Concat => return Ok(value::Value::String(args.iter().map(|e| { e.to_string() }).collect())),
+      Self::Coalesce => return Ok(args.into_iter().find(|e| { !e.is_null() }).unwrap_or(value::Value::Null)),
+      _ => {},
+    }
+    if args.iter().any(|e| { e.is_null() }) {
+      return Ok(value::Value::Null);
+    }
+    
+    let res = match self {
+      Self::Abs => match self.number(&args[0])? {
+        value::Value::Integer(v) => v.checked_abs().map(value::Value::Integer),
+        value::Value::Float(v) => Some(value::Value::Float(v.abs())),
+        value::Value::Decimal(v) => v.checked_abs().map(value::Value::Decimal),
+        _ => None,
+      },
+      Self::Round => {
+        let digits = match args.get(1) {
+          Some(arg) => self.integer(arg)?,
+          None => 0,
+        };
+        // a negative number of digits rounds to tens, hundreds and so on,
+        // whatever the type of the number
+        match self.number(&args[0])? {
+          value::Value::Integer(v) if digits >= 0 => Some(value::Value::Integer(v)),
+          value::Value::Integer(v) => value::Decimal::from_integer(v).round(digits).to_integer().map(value::Value::Integer),
+          value::Value::Float(v) => {
+            let scale = 10f64.powi(digits.clamp(-308, 308) as i32);
+            Some(value::Value::Float((v * scale).round() / scale))
+          },
+          value::Value::Decimal(v) => Some(value::Value::Decimal(v.round(digits))),
+          _ => None,
+        }
+      },
+      Self::Substr => {
+        // positions are counted in characters, from 1
+        let text = args[0].to_string();
+        let start = self.integer(&args[1])?.max(1) as usize - 1;
+        let sub: String = match args.get(2) {
+          Some(len) => text.chars().skip(start).take(self.integer(len)?.max(0) as usize).collect(),
+          None => text.chars().skip(start).collect(),
+        };
+        Some(value::Value::String(sub))
+      },
+      Self::Lower => Some(value::Value::String(args[0].to_string().to_lowercase())),
+      Self::Upper => Some(value::Value::String(args[0].to_string().to_uppercase())),
+      Self::Trim => Some(value::Value::String(args[0].to_string().trim().to_owned())),
+      Self::Length => Some(value::Value::Integer(args[0].to_string().chars().count() as i64)),
+      Self::Replace => Some(value::Value::String(args[0].to_string().replace(&args[1].to_string(), &args[2].to_string()))),
+      Self::RegexExtract(pattern) => {
+        // produce the first capture group if the pattern has one, or
+        // otherwise the entire match
+        let compiled;
+        let pattern = match pattern {
+          Some(pattern) => pattern,
+          None => {
+            compiled = Self::regex(&args[1].to_string())?;
+            &compiled
+          },
+        };
+        let text = args[0].to_string();
+        Some(match pattern.captures(&text) {
+          Some(caps) => match caps.get(if caps.len() > 1 { 1 } else { 0 }) {
+            Some(m) => value::Value::String(m.as_str().to_owned()),
+            None => value::Value::Null,
+          },
+          None => value::Value::Null,
+        })
+      },
+      Self::DateTrunc => {
+        let unit = Self::date_unit(&args[0].to_string())?;
+        match &args[1] {
+          value::Value::Date(v) => Self::trunc(unit, v.and_time(chrono::NaiveTime::MIN)).map(|e| { value::Value::Date(e.date()) }),
+          v => Self::trunc(unit, self.timestamp(v, None)?).map(value::Value::Timestamp),
+        }
+      },
+      Self::ToDate => Some(value::Value::Date(match (&args[0], args.get(1)) {
+        (value::Value::Date(v), None) => *v,
+        (v, None) => self.timestamp(v, None)?.date(),
+        (v, Some(format)) => match chrono::NaiveDate::parse_from_str(&v.to_string(), &format.to_string()) {
+          Ok(v) => v,
+          Err(err) => return Err(error::QueryError::new(&format!("Function {}: invalid date {:?}: {}", self, v.to_string(), err)).into()),
+        },
+      })),
+      Self::ToTimestamp => Some(value::Value::Timestamp(self.timestamp(&args[0], args.get(1))?)),
+      Self::Concat | Self::Coalesce => None, // handled above
+    };
+    
+    match res {
+      Some(res) => Ok(res),
+      None => Err(error::QueryError::new(&format!("Function {} cannot be applied to: {}", self, args.iter().map(|e| { format!("{:?}", e.to_string()) }).collect::<Vec<String>>().join(", "))).into()),
+    }
+  }
+  
+  fn number(&self, arg: &value::Value) -> Result<value::Value, error::Error> {
+    match arg.numeric() {
+      Some(v) => Ok(v),
+      None => Err(error::QueryError::new(&format!("Function {} expects a number: {:?}", self, arg.to_string())).into()),
+    }
+  }
+  
+  fn integer(&self, arg: &value::Value) -> Result<i64, error::Error> {
+    match arg.numeric() {
+      Some(value::Value::Integer(v)) => Ok(v),
+      _ => Err(error::QueryError::new(&format!("Function {} expects an integer: {:?}", self, arg.to_string())).into()),
+    }
+  }
+  
+  // Interpret a value as a timestamp, parsing text with a format if one
+  // is provided; dates are interpreted as midnight
+  fn timestamp(&self, arg: &value::Value, format: Option<&value::Value>) -> Result<chrono::NaiveDateTime, error::Error> {
+    let res = match (arg, format) {
+      (value::Value::Timestamp(v), None) => Ok(*v),
+      (value::Value::Date(v), None) => Ok(v.and_time(chrono::NaiveTime::MIN)),
+      (v, None) => match value::Type::Timestamp.value(&v.to_string()) {
+        Ok(value::Value::Timestamp(v)) => Ok(v),
+        _ => Err(format!("invalid timestamp {:?}", v.to_string())),
+      },
+      (v, Some(format)) => {
+        let (text, format) = (v.to_string(), format.to_string());
+        match chrono::NaiveDateTime::parse_from_str(&text, &format) {
+          Ok(v) => Ok(v),
+          Err(err) => match chrono::NaiveDate::parse_from_str(&text, &format) {
+            Ok(v) => Ok(v.and_time(chrono::NaiveTime::MIN)),
+            Err(_) => Err(format!("invalid timestamp {:?}: {}", text, err)),
+          },
+        }
+      },
+    };
+    match res {
+      Ok(v) => Ok(v),
+      Err(err) => Err(error::QueryError::new(&format!("Function {}: {}", self, err)).into()),
+    }
+  }
+  
+  fn regex(pattern: &str) -> Result<regex::Regex, error::Error> {
+    match regex::Regex::new(pattern) {
+      Ok(v) => Ok(v),
+      Err(err) => Err(error::QueryError::new(&format!("Invalid pattern: {}", err)).into()),
+    }
+  }
+  
+  fn date_unit(unit: &str) -> Result<&'static str, error::Error> {
+    let unit = unit.trim().to_lowercase();
+    match DATE_UNITS.iter().find(|e| { **e == unit }) {
+      Some(unit) => Ok(unit),
+      None => Err(error::QueryError::new(&format!("Invalid date unit, expected one of {}: {}", DATE_UNITS.join(", "), unit)).into()),
+    }
+  }
+  
+  // Truncate a timestamp to the start of the unit which contains it;
+  // weeks start on Monday
+  fn trunc(unit: &str, v: chrono::NaiveDateTime) -> Option<chrono::NaiveDateTime> {
+    let date = v.date();
+    let date = match unit {
+      "year" => chrono::NaiveDate::from_ymd_opt(date.year(), 1, 1)?,
+      "quarter" => chrono::NaiveDate::from_ymd_opt(date.year(), (date.month0() / 3) * 3 + 1, 1)?,
+      "month" => chrono::NaiveDate::from_ymd_opt(date.year(), date.month(), 1)?,
+      "week" => date - chrono::Duration::days(date.weekday().num_days_from_monday() as i64),
+      _ => date,
+    };
+    let time = match unit {
+      "hour" => chrono::NaiveTime::from_hms_opt(v.hour(), 0, 0)?,
+      "minute" => chrono::NaiveTime::from_hms_opt(v.hour(), v.minute(), 0)?,
+      "second" => chrono::NaiveTime::from_hms_opt(v.hour(), v.minute(), v.second())?,
+      _ => chrono::NaiveTime::MIN,
+    };
+    Some(date.and_time(time))
+  }
+}
+
+impl fmt::Display for Function {
+  fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+    match self {
+      Self::Abs => write!(f, "abs"),
+      Self::Round => write!(f, "round"),
+      Self::Concat => write!(f, "concat"),
+      Self::Substr => write!(f, "substr"),
+      Self::Lower => write!(f, "lower"),
+      Self::Upper => write!(f, "upper"),
+      Self::Trim => write!(f, "trim"),
+      Self::Length => write!(f, "length"),
+      Self::Replace => write!(f, "replace"),
+      Self::RegexExtract(_) => write!(f, "regex_extract"),
+      Self::Coalesce => write!(f, "coalesce"),
+      Self::DateTrunc => write!(f, "date_trunc"),
+      Self::ToDate => write!(f, "to_date"),
+      Self::ToTimestamp => write!(f, "to_timestamp"),
+    }
+  }
+}
+
+#[cfg(test)]
+mod tests {
+  use crate::csvql::query::ast;
+  use crate::csvql::query::expr;
+  use crate::csvql::query::parser;
+  use crate::csvql::query::schema;
+  use crate::csvql::query::value;
+  use crate::csvql::query::error;
+  
+  // Evaluate a call against a row with an empty field, n, a date, d, a
+  // timestamp, t, and a float, f, checking that the result can be
+  // represented by the call's result type
+  fn call(text: &str) -> Result<String, error::Error> {
+    let mut schema = schema::Schema::new("a", ["n", "d", "t", "f"].into_iter());
+    schema.set_column_type(1, value::Type::Date);
+    schema.set_column_type(2, value::Type::Timestamp);
+    schema.set_column_type(3, value::Type::Float);
+    let expr = expr::Expr::new(&scalar(text), &schema)?;
+    let res = expr.eval(&csv::StringRecord::from(vec!["", "2024-05-17", "2024-05-17T13:45:30", "1250.5"]))?.to_string();
+    expr.result_type().value(&res)?;
+    Ok(res)
+  }
+  
+  fn scalar(text: &str) -> ast::Expr {
+    match parser::parse_selection(text).unwrap().remove(0) {
+      ast::Column::Expr(proj) => proj.expr,
+      col => panic!("expected an expression: {}", col),
+    }
+  }
+  
+  #[test]
+  fn substr() {
+    assert_eq!(call("substr('hello', 2)").unwrap(), "ello");
+    assert_eq!(call("substr('hello', 2, 2)").unwrap(), "el");
+    assert_eq!(call("substr('héllo', 2, 2)").unwrap(), "él"); // in characters
+    // positions before the first character are the first
+    assert_eq!(call("substr('hello', 0, 2)").unwrap(), "he");
+    assert_eq!(call("substr('hello', -3, 2)").unwrap(), "he");
+    assert_eq!(call("substr('hello', 4, 10)").unwrap(), "lo");
+    assert_eq!(call("substr('hello', 9)").unwrap(), "");
+    assert_eq!(call("substr('hello', 2, -1)").unwrap(), "");
+    assert_eq!(call("substr(a.n, 2)").unwrap(), "");
+    assert!(call("substr('hello', 'x')").is_err());
+    assert!(call("substr('hello')").is_err());
+  }
+  
+  #[test]
+  fn round() {
+    assert_eq!(call("round(1.25, 1)").unwrap(), "1.3");
+    assert_eq!(call("round(-1.25, 1)").unwrap(), "-1.3");
+    assert_eq!(call("round(1.5)").unwrap(), "2");
+    assert_eq!(call("round(12.345, 5)").unwrap(), "12.345");
+    assert_eq!(call("round(7)").unwrap(), "7");
+    // negative digits round to tens, hundreds and so on for every type
+    assert_eq!(call("round(125, -1)").unwrap(), "130");
+    assert_eq!(call("round(-125, -1)").unwrap(), "-130");
+    assert_eq!(call("round(1250.5, -2)").unwrap(), "1300");
+    assert_eq!(call("round(a.f, -2)").unwrap(), "1300");
+    assert_eq!(call("round(1250.5, -40)").unwrap(), "0");
+    assert_eq!(call("round(a.n, 1)").unwrap(), "");
+    assert!(call("round(9223372036854775807, -1)").is_err());
+  }
+  
+  #[test]
+  fn date_trunc() {
+    let units = [
+      ("year", "2024-01-01T00:00:00"),
+      ("quarter", "2024-04-01T00:00:00"),
+      ("month", "2024-05-01T00:00:00"),
+      ("week", "2024-05-13T00:00:00"), // a Monday
+      ("day", "2024-05-17T00:00:00"),
+      ("hour", "2024-05-17T13:00:00"),
+      ("minute", "2024-05-17T13:45:00"),
+      ("second", "2024-05-17T13:45:30"),
+    ];
+    for (unit, expected) in units {
+      assert_eq!(call(&format!("date_trunc('{}', a.t)", unit)).unwrap(), expected, "{}", unit);
+    }
+    assert_eq!(call("date_trunc('MONTH', a.d)").unwrap(), "2024-05-01");
+    assert_eq!(call("date_trunc('quarter', '2024-12-31')").unwrap(), "2024-10-01T00:00:00");
+    assert_eq!(call("date_trunc('day', a.n)").unwrap(), "");
+    assert_eq!(call("date_trunc('decade', a.t)").unwrap_err().to_string(), "Invalid date unit, expected one of year, quarter, month, week, day, hour, minute, second: decade");
+  }
+  
+  #[test]
+  fn regex_extract() {
+    assert_eq!(call("regex_extract('order-42', '[0-9]+')").unwrap(), "42");
+    assert_eq!(call("regex_extract('order-42', '([a-z]+)-[0-9]+')").unwrap(), "order");
+    assert_eq!(call("regex_extract('order-42', 'x')").unwrap(), ""); // no match
+    assert_eq!(call("regex_extract('order-42', '(x)?order')").unwrap(), ""); // no group
+    assert_eq!(call("regex_extract(a.n, 'x')").unwrap(), "");
+    assert!(call("regex_extract('order-42', '(')").is_err());
+  }
+  
+  #[test]
+  fn nulls() {
+    assert_eq!(call("coalesce(a.n, 'x')").unwrap(), "x");
+    assert_eq!(call("coalesce(a.n, a.n)").unwrap(), "");
+    assert_eq!(call("coalesce(a.n, a.d)").unwrap(), "2024-05-17");
+    assert_eq!(call("concat('a', a.n, 'b')").unwrap(), "ab");
+    assert_eq!(call("concat(a.n)").unwrap(), "");
+    assert_eq!(call("to_date(a.n)").unwrap(), "");
+    assert_eq!(call("to_date(a.t)").unwrap(), "2024-05-17");
+    assert_eq!(call("to_date('17/05/2024', '%d/%m/%Y')").unwrap(), "2024-05-17");
+    assert_eq!(call("to_date('17/05/2024', a.n)").unwrap(), "");
+    assert!(call("to_date('x')").is_err());
+  }
+}
@@ -2,6 +2,18 @@ pub mod frame;
 pub mod select;
 pub mod schema;
 pub mod error;
+pub mod ast;
+pub mod parser;
+pub mod plan;
+pub mod predicate;
+pub mod expr;
+pub mod function;
+pub mod value;
+pub mod definition;
+pub mod dialect;
+pub mod aggregate;
+pub mod sort;
+pub mod sink;
 
 // use frame::Frame;
 
@@ -0,0 +1,642 @@
+use nom::IResult;
+use nom::branch::alt;
+use nom::bytes::complete::{tag, tag_no_case, take_while, take_while1, is_not};
+use nom::character::complete::{char, digit1, multispace0, multispace1};
+use nom::combinator::{all_consuming, map, map_res, not, opt, peek, recognize, value};
+use nom::multi::{fold_many0, many0, separated_list0, separated_list1};
+use nom::sequence::{delimited, pair, preceded, terminated, tuple};
+
+use crate::csvql::query::ast;
+use crate::csvql::query::schema;
+use crate::csvql::query::error;
+use crate::csvql::query::value::{Normalizer, Tolerance};
+
+// Parse a query
+pub fn parse(text: &str) -> Result<ast::Select, error::Error> {
+  match all_consuming(delimited(multispace0, select, tuple((multispace0, opt(char(';')), multispace0))))(text) {
+    Ok((_, query)) => Ok(query),
+    Err(err) => Err(convert_error("query", text, err)),
+  }
+}
+
+// Parse a list of aggregates, as in: count(*), sum(a.amount)
+pub fn parse_aggregates(text: &str) -> Result<Vec<ast::Aggregate>, error::Error> {
+  match all_consuming(delimited(multispace0, separated_list1(comma, aggregate), multispace0))(text) {
+    Ok((_, aggs)) => Ok(aggs),
+    Err(err) => Err(convert_error("aggregate", text, err)),
+  }
+}
+
+// Parse a list of column names, as in: a.region, a.country
+pub fn parse_columns(text: &str) -> Result<Vec<schema::QName>, error::Error> {
+  match all_consuming(delimited(multispace0, separated_list1(comma, qname), multispace0))(text) {
+    Ok((_, cols)) => Ok(cols),
+    Err(err) => Err(convert_error("column list", text, err)),
+  }
+}
+
+// Parse a list of selected columns, each of which is a wildcard or an
+// expression that may be named by an alias, as in: a.*, b.name,
+// a.qty * a.price as total, * except (a.secret), /^internal_/
+pub fn parse_selection(text: &str) -> Result<Vec<ast::Column>, error::Error> {
+  match all_consuming(delimited(multispace0, separated_list1(comma, column), multispace0))(text) {
+    Ok((_, cols)) => Ok(cols),
+    Err(err) => Err(convert_error("selection", text, err)),
+  }
+}
+
+// Parse a list of sort keys, as in: a.region asc, a.amount desc nulls last
+pub fn parse_order(text: &str) -> Result<Vec<ast::Order>, error::Error> {
+  match all_consuming(delimited(multispace0, separated_list1(comma, order), multispace0))(text) {
+    Ok((_, order)) => Ok(order),
+    Err(err) => Err(convert_error("sort order", text, err)),
+  }
+}
+
+// Parse a list of join conditions, each of which is a conjunction of
+// equalities between columns or tuples of columns, as in: a.id = b.id,
+// a.(country, sku) = b.(country, sku), a.country = b.country and a.sku =
+// b.sku, or a.id = b.a_id, b.c_id = c.id. The conditions are expanded
+// into chains of columns which must be equal.
+pub fn parse_join(text: &str) -> Result<Vec<Vec<schema::QName>>, error::Error> {
+  match all_consuming(delimited(multispace0, separated_list1(comma, join_condition), multispace0))(text) {
+    Ok((_, cond)) => Ok(cond.concat()),
+    Err(err) => Err(convert_error("join condition", text, err)),
+  }
+}
+
+// Parse a standalone expression, such as a row predicate
+pub fn parse_expr(text: &str) -> Result<ast::Expr, error::Error> {
+  match all_consuming(delimited(multispace0, expr, multispace0))(text) {
+    Ok((_, expr)) => Ok(expr),
+    Err(err) => Err(convert_error("expression", text, err)),
+  }
+}
+
+fn convert_error(what: &str, text: &str, err: nom::Err<nom::error::Error<&str>>) -> error::Error {
+  match err {
+    nom::Err::Error(err) | nom::Err::Failure(err) => error::ParseError::new(&format!("Invalid {}: syntax error at offset {}: {}", what, text.len() - err.input.len(), excerpt(err.input))).into(),
+    nom::Err::Incomplete(_) => error::ParseError::new(&format!("Invalid {}: unexpected end of input", what)).into(),
+  }
+}
+
+fn excerpt(text: &str) -> String {
+  if text.is_empty() {
+    "<end of input>".to_string()
+  }else if text.chars().count() > 24 {
+    format!("{}...", text.chars().take(24).collect::<String>())
+  }else{
+    text.to_string()
+  }
+}
+
+fn is_ident_start(c: char) -> bool {
+  c.is_alphabetic() || c == '_'
+}
+
+fn is_ident_char(c: char) -> bool {
+  c.is_alphanumeric() || c == '_'
+}
+
+// Match a keyword, case-insensitively, which is not followed by more identifier characters
+fn keyword<'a>(word: &'static str) -> impl FnMut(&'a str) -> IResult<&'a str, &'a str> {
+  terminated(tag_no_case(word), not(peek(take_while1(is_ident_char))))
+}
+
+fn reserved(text: &str) -> bool {
+  const RESERVED: &[&str] = &["select", "as", "except", "from", "join", "inner", "left", "right", "full", "outer", "semi", "anti", "asof", "on", "using", "tolerance", "normalize", "where", "group", "order", "by", "limit", "and", "or", "not", "is", "in", "like", "empty", "distinct"];
+  RESERVED.iter().any(|e| { e.eq_ignore_ascii_case(text) })
+}
+
+fn bare_ident(input: &str) -> IResult<&str, String> {
+  let (rest, ident) = recognize(pair(take_while1(is_ident_start), take_while(is_ident_char)))(input)?;
+  if reserved(ident) {
+    Err(nom::Err::Error(nom::error::Error::new(input, nom::error::ErrorKind::Tag)))
+  }else{
+    Ok((rest, ident.to_string()))
+  }
+}
+
+// Parse text delimited by a quote character, where the quote itself is escaped by doubling it
+fn quoted<'a>(quote: &'static str) -> impl FnMut(&'a str) -> IResult<&'a str, String> {
+  let escaped: &'static str = if quote == "'" { "''" } else { "\"\"" };
+  delimited(
+    tag(quote),
+    fold_many0(alt((is_not(quote), value(quote, tag(escaped)))), String::new, |mut acc, e| { acc.push_str(e); acc }),
+    tag(quote),
+  )
+}
+
+fn quoted_ident(input: &str) -> IResult<&str, String> {
+  quoted("\"")(input)
+}
+
+fn ident(input: &str) -> IResult<&str, String> {
+  alt((quoted_ident, bare_ident))(input)
+}
+
+// A qualified name. Names may themselves contain dots, as flattened
+// JSON fields do, in which case they must be qualified: a.user.name
+fn qname(input: &str) -> IResult<&str, schema::QName> {
+  map(pair(ident, opt(preceded(char('.'), separated_list1(char('.'), ident)))), |(a, b)| {
+    match b {
+      Some(b) => schema::QName::new(&a, &b.join(".")),
+      None => schema::QName::new_unscoped(&a),
+    }
+  })(input)
+}
+
+fn function(input: &str) -> IResult<&str, ast::Function> {
+  alt((
+    value(ast::Function::Count, keyword("count")),
+    value(ast::Function::Sum, keyword("sum")),
+    value(ast::Function::Min, keyword("min")),
+    value(ast::Function::Max, keyword("max")),
+    value(ast::Function::Avg, keyword("avg")),
+  ))(input)
+}
+
+#[allow(clippy::redundant_field_names)]
+fn aggregate(input: &str) -> IResult<&str, ast::Aggregate> {
+  map(
+    tuple((
+      function, multispace0, char('('), multispace0,
+      opt(terminated(keyword("distinct"), multispace1)),
+      alt((value(None, char('*')), map(qname, Some))),
+      multispace0, char(')'),
+    )),
+    |(func, _, _, _, distinct, arg, _, _)| {
+      ast::Aggregate{
+        func: func,
+        arg: arg,
+        distinct: distinct.is_some(),
+      }
+    },
+  )(input)
+}
+
+fn column(input: &str) -> IResult<&str, ast::Column> {
+  alt((
+    map(wildcard, ast::Column::All),
+    map(pair(aggregate, opt(alias)), |(agg, alias)| { ast::Column::Aggregate(agg, alias) }),
+    map(projection, |e| {
+      match e {
+        ast::Projection{expr: ast::Expr::Column(qname), alias: None} => ast::Column::Name(qname),
+        proj => ast::Column::Expr(proj),
+      }
+    }),
+  ))(input)
+}
+
+// A pattern which matches columns: *, a.*, /regex/ or a./regex/. A
+// slash is escaped in a regular expression as \/.
+fn pattern(input: &str) -> IResult<&str, ast::Pattern> {
+  let (input, scope) = opt(terminated(ident, char('.')))(input)?;
+  let (input, regex) = alt((
+    value(None, char('*')),
+    map(
+      delimited(
+        char('/'),
+        fold_many0(alt((value("/", tag("\\/")), is_not("\\/"), tag("\\"))), String::new, |mut acc, e| { acc.push_str(e); acc }),
+        char('/'),
+      ),
+      Some,
+    ),
+  ))(input)?;
+  match regex {
+    Some(regex) => Ok((input, ast::Pattern::Regex(scope, regex))),
+    None => Ok((input, ast::Pattern::All(scope))),
+  }
+}
+
+#[allow(clippy::redundant_field_names)]
+fn wildcard(input: &str) -> IResult<&str, ast::Wildcard> {
+  map(
+    pair(pattern, opt(preceded(
+      tuple((multispace1, keyword("except"), multispace0, char('('), multispace0)),
+      terminated(
+        separated_list1(comma, alt((map(pattern, ast::Exclusion::Pattern), map(qname, ast::Exclusion::Name)))),
+        pair(multispace0, char(')')),
+      ),
+    ))),
+    |(pattern, except)| {
+      ast::Wildcard{
+        pattern: pattern,
+        except: except.unwrap_or_default(),
+      }
+    },
+  )(input)
+}
+
+// An alias which names a column, with or without AS
+fn alias(input: &str) -> IResult<&str, String> {
+  preceded(pair(multispace1, opt(terminated(keyword("as"), multispace1))), ident)(input)
+}
+
+#[allow(clippy::redundant_field_names)]
+fn projection(input: &str) -> IResult<&str, ast::Projection> {
+  map(pair(scalar, opt(alias)), |(expr, alias)| {
+    ast::Projection{
+      expr: expr,
+      alias: alias,
+    }
+  })(input)
+}
+
+// A scalar expression, which computes a value from columns, literals and
+// function calls with arithmetic; multiplication, division and remainder
+// take precedence over addition and subtraction
+fn scalar(input: &str) -> IResult<&str, ast::Expr> {
+  let (input, first) = scalar_product(input)?;
+  let (input, rest) = many0(pair(
+    delimited(multispace0, alt((value(ast::Arith::Add, char('+')), value(ast::Arith::Sub, char('-')))), multispace0),
+    scalar_product,
+  ))(input)?;
+  Ok((input, rest.into_iter().fold(first, |acc, (op, e)| { ast::Expr::Arith(Box::new(acc), op, Box::new(e)) })))
+}
+
+fn scalar_product(input: &str) -> IResult<&str, ast::Expr> {
+  let (input, first) = scalar_unary(input)?;
+  let (input, rest) = many0(pair(
+    delimited(multispace0, alt((value(ast::Arith::Mul, char('*')), value(ast::Arith::Div, char('/')), value(ast::Arith::Rem, char('%')))), multispace0),
+    scalar_unary,
+  ))(input)?;
+  Ok((input, rest.into_iter().fold(first, |acc, (op, e)| { ast::Expr::Arith(Box::new(acc), op, Box::new(e)) })))
+}
+
+fn scalar_unary(input: &str) -> IResult<&str, ast::Expr> {
+  alt((
+    map(literal, ast::Expr::Literal),
+    map(preceded(pair(char('-'), multispace0), scalar_unary), |e| { ast::Expr::Neg(Box::new(e)) }),
+    call,
+    map(qname, ast::Expr::Column),
+    delimited(pair(char('('), multispace0), scalar, pair(multispace0, char(')'))),
+  ))(input)
+}
+
+fn call(input: &str) -> IResult<&str, ast::Expr> {
+  map(
+    tuple((
+      bare_ident, multispace0, char('('), multispace0,
+      separated_list0(comma, scalar),
+      multispace0, char(')'),
+    )),
+    |(name, _, _, _, args, _, _)| { ast::Expr::Call(name.to_lowercase(), args) },
+  )(input)
+}
+
+fn comma(input: &str) -> IResult<&str, char> {
+  delimited(multispace0, char(','), multispace0)(input)
+}
+
+fn string_literal(input: &str) -> IResult<&str, ast::Literal> {
+  map(quoted("'"), ast::Literal::String)(input)
+}
+
+fn number_literal(input: &str) -> IResult<&str, ast::Literal> {
+  map(recognize(tuple((opt(char('-')), digit1, opt(pair(char('.'), digit1))))), |e: &str| {
+    ast::Literal::Number(e.to_string())
+  })(input)
+}
+
+fn literal(input: &str) -> IResult<&str, ast::Literal> {
+  alt((string_literal, number_literal))(input)
+}
+
+fn operator(input: &str) -> IResult<&str, ast::Operator> {
+  alt((
+    value(ast::Operator::Ne, alt((tag("!="), tag("<>")))),
+    value(ast::Operator::Le, tag("<=")),
+    value(ast::Operator::Ge, tag(">=")),
+    value(ast::Operator::Eq, tag("=")),
+    value(ast::Operator::Lt, tag("<")),
+    value(ast::Operator::Gt, tag(">")),
+  ))(input)
+}
+
+fn operand(input: &str) -> IResult<&str, ast::Expr> {
+  alt((
+    map(literal, ast::Expr::Literal),
+    map(qname, ast::Expr::Column),
+  ))(input)
+}
+
+// The right-hand side of a predicate, which is applied to its left-hand operand
+enum Predicate {
+  Compare(ast::Operator, ast::Expr),
+  IsEmpty(bool),
+  In(bool, Vec<ast::Expr>),
+  Like(bool, ast::Expr),
+}
+
+fn negated<'a>(word: &'static str) -> impl FnMut(&'a str) -> IResult<&'a str, bool> {
+  map(pair(opt(terminated(keyword("not"), multispace1)), keyword(word)), |(neg, _)| { neg.is_some() })
+}
+
+fn predicate(input: &str) -> IResult<&str, Predicate> {
+  alt((
+    map(pair(delimited(multispace0, operator, multispace0), operand), |(op, rhs)| { Predicate::Compare(op, rhs) }),
+    map(tuple((multispace1, keyword("is"), multispace1, opt(terminated(keyword("not"), multispace1)), keyword("empty"))), |(_, _, _, neg, _)| { Predicate::IsEmpty(neg.is_some()) }),
+    map(tuple((multispace1, negated("in"), multispace0, char('('), multispace0, separated_list1(comma, operand), multispace0, char(')'))), |(_, neg, _, _, _, set, _, _)| { Predicate::In(neg, set) }),
+    map(tuple((multispace1, negated("like"), multispace1, operand)), |(_, neg, _, pattern)| { Predicate::Like(neg, pattern) }),
+  ))(input)
+}
+
+fn comparison(input: &str) -> IResult<&str, ast::Expr> {
+  let (input, lhs) = operand(input)?;
+  let (input, rhs) = opt(predicate)(input)?;
+  let lhs = Box::new(lhs);
+  let (expr, neg) = match rhs {
+    Some(Predicate::Compare(op, rhs)) => (ast::Expr::Compare(lhs, op, Box::new(rhs)), false),
+    Some(Predicate::IsEmpty(neg)) => (ast::Expr::IsEmpty(lhs), neg),
+    Some(Predicate::In(neg, set)) => (ast::Expr::In(lhs, set), neg),
+    Some(Predicate::Like(neg, pattern)) => (ast::Expr::Like(lhs, Box::new(pattern)), neg),
+    None => return Ok((input, *lhs)),
+  };
+  if neg {
+    Ok((input, ast::Expr::Not(Box::new(expr))))
+  }else{
+    Ok((input, expr))
+  }
+}
+
+fn term(input: &str) -> IResult<&str, ast::Expr> {
+  alt((
+    map(preceded(pair(keyword("not"), multispace0), term), |e| { ast::Expr::Not(Box::new(e)) }),
+    delimited(pair(char('('), multispace0), expr, pair(multispace0, char(')'))),
+    comparison,
+  ))(input)
+}
+
+fn conjunction(input: &str) -> IResult<&str, ast::Expr> {
+  let (input, first) = term(input)?;
+  let (input, rest) = many0(preceded(tuple((multispace1, keyword("and"), multispace1)), term))(input)?;
+  Ok((input, rest.into_iter().fold(first, |acc, e| { ast::Expr::And(Box::new(acc), Box::new(e)) })))
+}
+
+fn expr(input: &str) -> IResult<&str, ast::Expr> {
+  let (input, first) = conjunction(input)?;
+  let (input, rest) = many0(preceded(tuple((multispace1, keyword("or"), multispace1)), conjunction))(input)?;
+  Ok((input, rest.into_iter().fold(first, |acc, e| { ast::Expr::Or(Box::new(acc), Box::new(e)) })))
+}
+
+#[allow(clippy::redundant_field_names)]
+fn order(input: &str) -> IResult<&str, ast::Order> {
+  map(
+    tuple((
+      qname,
+      opt(preceded(multispace1, alt((
+        value(ast::Direction::Asc, keyword("asc")),
+        value(ast::Direction::Desc, keyword("desc")),
+      )))),
+      opt(preceded(tuple((multispace1, keyword("nulls"), multispace1)), alt((
+        value(ast::Nulls::First, keyword("first")),
+        value(ast::Nulls::Last, keyword("last")),
+      )))),
+    )),
+    |(on, direction, nulls)| {
+      ast::Order{
+        on: on,
+        direction: direction.unwrap_or(ast::Direction::Asc),
+        nulls: nulls,
+      }
+    },
+  )(input)
+}
+
+// A keyword which may be followed by OUTER, as in: LEFT OUTER
+fn outer<'a>(word: &'static str) -> impl FnMut(&'a str) -> IResult<&'a str, &'a str> {
+  terminated(keyword(word), opt(pair(multispace1, keyword("outer"))))
+}
+
+// The kind of a join, which precedes the JOIN keyword; a bare JOIN is an
+// inner join
+fn join_kind(input: &str) -> IResult<&str, ast::JoinKind> {
+  let (input, kind) = opt(terminated(alt((
+    value(ast::JoinKind::Inner, keyword("inner")),
+    value(ast::JoinKind::Semi, preceded(opt(pair(keyword("left"), multispace1)), keyword("semi"))),
+    value(ast::JoinKind::Anti, preceded(opt(pair(keyword("left"), multispace1)), keyword("anti"))),
+    value(ast::JoinKind::Left, outer("left")),
+    value(ast::JoinKind::Right, outer("right")),
+    value(ast::JoinKind::Full, outer("full")),
+  )), multispace1))(input)?;
+  Ok((input, kind.unwrap_or(ast::JoinKind::Inner)))
+}
+
+#[allow(clippy::redundant_field_names)]
+fn join(input: &str) -> IResult<&str, ast::Join> {
+  let (input, (kind, asof, _, _, source, _)) = tuple((
+    join_kind,
+    opt(terminated(keyword("asof"), multispace1)),
+    keyword("join"), multispace1, ident, multispace1,
+  ))(input)?;
+  
+  // USING names columns which appear in both the joined source and a
+  // source which precedes it, and merges them; the last is the as-of
+  // column of an as-of join
+  let (input, using) = opt(preceded(
+    pair(keyword("using"), multispace0),
+    delimited(pair(char('('), multispace0), separated_list1(comma, ident), pair(multispace0, char(')'))),
+  ))(input)?;
+  let (input, on, using) = match using {
+    Some(using) => (input, using.iter().map(|e| { (schema::QName::new_unscoped(e), schema::QName::new(&source, e)) }).collect(), true),
+    None => {
+      let (input, on) = if asof.is_some() { asof_on(input)? } else { join_on(input)? };
+      (input, on, false)
+    },
+  };
+  
+  // TOLERANCE limits how far the as-of value of a right row may fall
+  // below the left value, as in: TOLERANCE '5m'
+  let (input, tolerance) = match asof {
+    Some(_) => opt(preceded(
+      tuple((multispace1, keyword("tolerance"), multispace1)),
+      map_res(alt((quoted("'"), map(recognize(pair(digit1, opt(pair(char('.'), digit1)))), |e: &str| { e.to_owned() }))), |e| { Tolerance::parse(&e) }),
+    ))(input)?,
+    None => (input, None),
+  };
+  
+  // NORMALIZE applies normalizers to the joining values before they are
+  // compared, as in: NORMALIZE (trim, lower)
+  let (input, normalize) = opt(preceded(
+    tuple((multispace1, keyword("normalize"), multispace0)),
+    delimited(
+      pair(char('('), multispace0),
+      separated_list1(comma, map_res(ident, |e| { Normalizer::parse(&e) })),
+      pair(multispace0, char(')')),
+    ),
+  ))(input)?;
+  
+  Ok((input, ast::Join{
+    kind: kind,
+    source: source,
+    on: on,
+    using: using,
+    normalize: normalize.unwrap_or_default(),
+    asof: asof.map(|_| { ast::AsOf{tolerance: tolerance} }),
+  }))
+}
+
+// The ON conditions of a join, each of which relates two columns
+fn join_on(input: &str) -> IResult<&str, Vec<(schema::QName, schema::QName)>> {
+  preceded(pair(keyword("on"), multispace1), join_pairs)(input)
+}
+
+// The ON conditions of an as-of join: any equalities, followed by an
+// inequality, which is produced last as its greater column and its
+// lesser column, as in: a.sym = b.sym AND a.time >= b.time
+fn asof_on(input: &str) -> IResult<&str, Vec<(schema::QName, schema::QName)>> {
+  let (input, (on, (a, op, b))) = preceded(
+    pair(keyword("on"), multispace1),
+    pair(
+      opt(terminated(join_pairs, tuple((multispace1, keyword("and"), multispace1)))),
+      tuple((qname, delimited(multispace0, alt((tag(">="), tag("<="))), multispace0), qname)),
+    ),
+  )(input)?;
+  let mut on = on.unwrap_or_default();
+  on.push(if op == ">=" { (a, b) } else { (b, a) });
+  Ok((input, on))
+}
+
+fn join_pairs(input: &str) -> IResult<&str, Vec<(schema::QName, schema::QName)>> {
+  map_res(join_condition, |e| {
+    // each chain must relate exactly two columns
+    e.into_iter().map(|e| {
+      match <[schema::QName; 2]>::try_from(e) {
+        Ok([a, b]) => Ok((a, b)),
+        Err(_) => Err("expected an equality between two columns"),
+      }
+    }).collect::<Result<Vec<(schema::QName, schema::QName)>, &str>>()
+  })(input)
+}
+
+// A join term, which is either a column or a tuple of columns in the
+// same scope, as in: a.(country, sku)
+fn join_term(input: &str) -> IResult<&str, Vec<schema::QName>> {
+  alt((
+    map(
+      tuple((
+        ident, char('.'), char('('), multispace0,
+        separated_list1(comma, ident),
+        multispace0, char(')'),
+      )),
+      |(scope, _, _, _, names, _, _)| {
+        names.iter().map(|e| { schema::QName::new(&scope, e) }).collect()
+      },
+    ),
+    map(qname, |e| { vec![e] }),
+  ))(input)
+}
+
+// An equality between join terms, which is expanded into one chain of
+// equal columns for each column in the terms
+fn join_equality(input: &str) -> IResult<&str, Vec<Vec<schema::QName>>> {
+  map_res(separated_list1(delimited(multispace0, char('='), multispace0), join_term), |terms| {
+    if terms.len() < 2 {
+      return Err("expected an equality");
+    }
+    if terms.iter().any(|e| { e.len() != terms[0].len() }) {
+      return Err("expected tuples of the same length");
+    }
+    Ok((0..terms[0].len()).map(|i| { terms.iter().map(|e| { e[i].clone() }).collect() }).collect())
+  })(input)
+}
+
+fn join_condition(input: &str) -> IResult<&str, Vec<Vec<schema::QName>>> {
+  map(separated_list1(tuple((multispace1, keyword("and"), multispace1)), join_equality), |e| { e.concat() })(input)
+}
+
+fn limit(input: &str) -> IResult<&str, usize> {
+  map_res(digit1, |e: &str| { e.parse::<usize>() })(input)
+}
+
+#[allow(clippy::redundant_field_names)]
+fn select(input: &str) -> IResult<&str, ast::Select> {
+  let (input, _) = pair(keyword("select"), multispace1)(input)?;
+  let (input, columns) = separated_list1(comma, column)(input)?;
+  let (input, _) = tuple((multispace1, keyword("from"), multispace1))(input)?;
+  let (input, from) = ident(input)?;
+  let (input, joins) = many0(preceded(multispace1, join))(input)?;
+  let (input, filter) = opt(preceded(tuple((multispace1, keyword("where"), multispace1)), expr))(input)?;
+  let (input, group) = opt(preceded(tuple((multispace1, keyword("group"), multispace1, keyword("by"), multispace1)), separated_list1(comma, qname)))(input)?;
+  let (input, order) = opt(preceded(tuple((multispace1, keyword("order"), multispace1, keyword("by"), multispace1)), separated_list1(comma, order)))(input)?;
+  let (input, limit) = opt(preceded(tuple((multispace1, keyword("limit"), multispace1)), limit))(input)?;
+  Ok((input, ast::Select{
+    columns: columns,
+    from: from,
+    joins: joins,
+    filter: filter,
+    group: group.unwrap_or_default(),
+    order: order.unwrap_or_default(),
+    limit: limit,
+  }))
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+  
+  // Parse a query and check that its canonical form parses to itself
+  fn round_trip(text: &str) -> String {
+    let query = parse(text).unwrap().to_string();
+    assert_eq!(query, parse(&query).unwrap().to_string());
+    query
+  }
+  
+  fn error(text: &str) -> String {
+    parse(text).unwrap_err().to_string()
+  }
+  
+  #[test]
+  fn parse_select() {
+    assert_eq!(round_trip("select * from a"), "SELECT * FROM a");
+    assert_eq!(round_trip("SELECT a.id, a.name AS n, b.* except (b.secret) FROM a;"), "SELECT a.id, a.name AS n, b.* EXCEPT (b.secret) FROM a");
+    assert_eq!(round_trip("select a.qty * a.price + 1 total, upper(a.name) from a"), "SELECT (a.qty * a.price) + 1 AS total, upper(a.name) FROM a");
+  }
+  
+  #[test]
+  fn parse_join() {
+    assert_eq!(round_trip("select * from a join b on a.id = b.id"), "SELECT * FROM a INNER JOIN b ON a.id = b.id");
+    assert_eq!(round_trip("select * from a left outer join b on a.id = b.id and a.k = b.k full join c using (id)"), "SELECT * FROM a LEFT JOIN b ON a.id = b.id AND a.k = b.k FULL JOIN c USING (id)");
+    assert_eq!(round_trip("select * from a semi join b on a.id = b.id anti join c on a.id = c.id"), "SELECT * FROM a SEMI JOIN b ON a.id = b.id ANTI JOIN c ON a.id = c.id");
+    assert_eq!(round_trip("select * from a left asof join b on a.sym = b.sym and a.ts >= b.ts tolerance '5m' normalize (lower, trim)"), "SELECT * FROM a LEFT ASOF JOIN b ON a.sym = b.sym AND a.ts >= b.ts TOLERANCE '300s' NORMALIZE (lower, trim)");
+  }
+  
+  #[test]
+  fn parse_composite_keys() {
+    let chains = |text: &str| -> Vec<Vec<String>> {
+      super::parse_join(text).unwrap().iter().map(|e| { e.iter().map(|e| { e.to_string() }).collect() }).collect()
+    };
+    assert_eq!(chains("a.(country, sku) = b.(country,sku)"), vec![vec!["a.country", "b.country"], vec!["a.sku", "b.sku"]]);
+    assert_eq!(chains("a.(k1,k2)=b.(x1,x2)=c.(y1,y2)"), vec![vec!["a.k1", "b.x1", "c.y1"], vec!["a.k2", "b.x2", "c.y2"]]);
+    assert_eq!(chains("a.country = b.country and a.(sku) = b.sku"), vec![vec!["a.country", "b.country"], vec!["a.sku", "b.sku"]]);
+    assert_eq!(round_trip("select * from a join b on a.(country, sku) = b.(country, sku)"), "SELECT * FROM a INNER JOIN b ON a.country = b.country AND a.sku = b.sku");
+    
+    // tuples of different lengths
+    assert_eq!(super::parse_join("a.(country, sku) = b.(country)").unwrap_err().to_string(), "Invalid join condition: syntax error at offset 0: a.(country, sku) = b.(co...");
+    assert_eq!(super::parse_join("a.(country, sku) = b.sku").unwrap_err().to_string(), "Invalid join condition: syntax error at offset 0: a.(country, sku) = b.sku");
+    assert_eq!(error("select * from a join b on a.(country, sku) = b.country"), "Invalid query: syntax error at offset 16: join b on a.(country, sk...");
+  }
+  
+  #[test]
+  fn parse_where() {
+    assert_eq!(round_trip("select * from a where a.id = 1 and not a.name like 'x%' or a.v is not empty"), "SELECT * FROM a WHERE ((a.id = 1 AND NOT a.name LIKE 'x%') OR NOT a.v IS EMPTY)");
+    assert_eq!(round_trip("select * from a where (a.id >= 1 or a.id < -2) and a.name in ('x', 'y')"), "SELECT * FROM a WHERE ((a.id >= 1 OR a.id < -2) AND a.name IN ('x', 'y'))");
+  }
+  
+  #[test]
+  fn parse_group_order_limit() {
+    assert_eq!(round_trip("select a.k, count(*), sum(a.v) as total from a group by a.k order by a.k desc nulls first, total limit 10"), "SELECT a.k, count(*), sum(a.v) AS total FROM a GROUP BY a.k ORDER BY a.k DESC NULLS FIRST, total LIMIT 10");
+  }
+  
+  #[test]
+  fn parse_errors() {
+    assert_eq!(error("select from a"), "Invalid query: syntax error at offset 7: from a");
+    assert_eq!(error("select * form a"), "Invalid query: syntax error at offset 9: form a");
+    assert_eq!(error("select * from a join b"), "Invalid query: syntax error at offset 16: join b");
+    assert_eq!(error("select * from a where"), "Invalid query: syntax error at offset 16: where");
+    assert_eq!(error("select * from a group a.k"), "Invalid query: syntax error at offset 16: group a.k");
+    assert_eq!(error("select * from a order by"), "Invalid query: syntax error at offset 16: order by");
+    assert_eq!(error("select * from a limit x"), "Invalid query: syntax error at offset 16: limit x");
+  }
+}
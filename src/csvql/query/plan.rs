@@ -0,0 +1,413 @@
+use crate::csvql::query::ast;
+use crate::csvql::query::frame;
+use crate::csvql::query::frame::Frame;
+use crate::csvql::query::select;
+use crate::csvql::query::schema;
+use crate::csvql::query::sort;
+use crate::csvql::query::value;
+use crate::csvql::query::error;
+
+// By default, inputs up to this many bytes may be indexed in memory by a
+// hash join when the strategy is chosen automatically
+pub const HASH_LIMIT: u64 = 64 << 20;
+
+// The strategy used to join two frames
+#[derive(Debug, Clone, Copy, PartialEq, Default)]
+pub enum Strategy {
+  #[default]
+  Auto, // choose a strategy based on the size of the inputs
+  Merge, // sort both sides and merge them
+  Hash, // index the smaller side and stream the other against it
+}
+
+impl Strategy {
+  pub fn parse(text: &str) -> Result<Strategy, error::Error> {
+    match text.trim().to_lowercase().as_ref() {
+      "auto" => Ok(Self::Auto),
+      "merge" => Ok(Self::Merge),
+      "hash" => Ok(Self::Hash),
+      _ => Err(error::ParseError::new(&format!("Invalid join strategy, expected one of auto, merge or hash: {}", text)).into()),
+    }
+  }
+}
+
+// Options that control how a query is planned
+#[derive(Debug, Clone)]
+pub struct Options {
+  pub sort: sort::SortOptions,
+  pub strategy: Strategy,
+  pub hash_limit: u64, // the greatest size hint of an input which is indexed when the strategy is chosen automatically
+}
+
+impl Default for Options {
+  fn default() -> Options {
+    Options{
+      sort: sort::SortOptions::default(),
+      strategy: Strategy::default(),
+      hash_limit: HASH_LIMIT,
+    }
+  }
+}
+
+// Options that control how two frames are joined
+#[derive(Debug, Clone)]
+pub struct JoinOptions {
+  pub kind: ast::JoinKind,
+  pub coalesce: bool, // merge each pair of joining columns into a single column
+  pub normalize: Vec<value::Normalizer>, // applied to joining values before they are compared
+  pub asof: Option<ast::AsOf>, // join the last pair of columns as of the left value
+}
+
+// Join a frame against another on the specified columns
+pub fn join<'a>(left: Box<dyn Frame + 'a>, left_on: &[schema::QName], right: Box<dyn Frame + 'a>, right_on: &[schema::QName], join: &JoinOptions, opts: &Options) -> Result<Box<dyn Frame + 'a>, error::Error> {
+  let kind = join.kind;
+  let frm = join_strategy(left, left_on, right, right_on, join, opts)?;
+  if join.coalesce && !kind.filters() {
+    let on: Vec<(schema::QName, schema::QName)> = left_on.iter().cloned().zip(right_on.iter().cloned()).collect();
+    Ok(Box::new(frame::Coalesce::new(frm, &on)?))
+  }else{
+    Ok(frm)
+  }
+}
+
+// The joining columns of a frame which must be retyped to compare with
+// those of the frame it is joined against: columns of types whose values
+// do not compare with each other, such as an integer and a string, are
+// both compared as strings
+fn join_types(schema: &schema::Schema, on: &[schema::QName], other: &schema::Schema, other_on: &[schema::QName]) -> Result<Vec<(schema::QName, value::Type)>, error::Error> {
+  let mut types: Vec<(schema::QName, value::Type)> = Vec::new();
+  for (a, b) in on.iter().zip(other_on) {
+    let typ = column_type(schema, a)?;
+    if typ != value::Type::String && !typ.compares_with(&column_type(other, b)?) {
+      types.push((a.clone(), value::Type::String));
+    }
+  }
+  Ok(types)
+}
+
+fn retyped<'a>(frm: Box<dyn Frame + 'a>, types: &[(schema::QName, value::Type)]) -> Result<Box<dyn Frame + 'a>, error::Error> {
+  if types.is_empty() {
+    Ok(frm)
+  }else{
+    Ok(Box::new(frame::Retyped::new(frm, types)?))
+  }
+}
+
+fn column_type(schema: &schema::Schema, qname: &schema::QName) -> Result<value::Type, error::Error> {
+  match schema.index(qname) {
+    Some(index) => Ok(schema.column_type(index)),
+    None => Err(error::QueryError::new(&format!("Join column not found: {} ({})", qname, schema)).into()),
+  }
+}
+
+fn join_strategy<'a>(left: Box<dyn Frame + 'a>, left_on: &[schema::QName], right: Box<dyn Frame + 'a>, right_on: &[schema::QName], join: &JoinOptions, opts: &Options) -> Result<Box<dyn Frame + 'a>, error::Error> {
+  let (kind, normalize) = (join.kind, &join.normalize);
+  let left_types = join_types(left.schema(), left_on, right.schema(), right_on)?;
+  let right_types = join_types(right.schema(), right_on, left.schema(), left_on)?;
+  let (mut left, mut right) = (retyped(left, &left_types)?, retyped(right, &right_types)?);
+  let (left_size, right_size) = (left.size_hint(), right.size_hint());
+  
+  // the smaller side is indexed if the size of both sides is known;
+  // semi and anti joins must index their right side
+  let swap = !kind.filters() && match (left_size, right_size) {
+    (Some(left_size), Some(right_size)) => left_size < right_size,
+    _ => false,
+  };
+  
+  let strategy = match opts.strategy {
+    Strategy::Hash if join.asof.is_some() => return Err(error::QueryError::new("An as-of join cannot use the hash strategy, only merge").into()),
+    _ if join.asof.is_some() => Strategy::Merge, // an as-of join is only merged
+    Strategy::Auto => {
+      let sorted = normalize.is_empty() && left.sorted_on().starts_with(left_on) && right.sorted_on().starts_with(right_on);
+      let build_size = if swap { left_size } else { right_size };
+      match build_size {
+        _ if sorted => Strategy::Merge, // both sides are already sorted
+        Some(size) if size <= opts.hash_limit => Strategy::Hash,
+        _ => Strategy::Merge,
+      }
+    },
+    strategy => strategy,
+  };
+  
+  match strategy {
+    Strategy::Hash => if swap {
+      Ok(Box::new(frame::HashJoin::new_swapped(kind, frame::HashIndex::new(&mut left, left_on, normalize)?, right, right_on)?))
+    }else{
+      Ok(Box::new(frame::HashJoin::new(kind, left, left_on, frame::HashIndex::new(&mut right, right_on, normalize)?)?))
+    },
+    _ => {
+      // either side is only sorted if it isn't already sorted on its joining
+      // column; a frame is never sorted on normalized values beforehand
+      if !normalize.is_empty() || !left.sorted_on().starts_with(left_on) {
+        left = Box::new(frame::Sorted::new(&mut left, left_on, normalize, &opts.sort)?);
+      }
+      if !normalize.is_empty() || !right.sorted_on().starts_with(right_on) {
+        right = Box::new(frame::Sorted::new(&mut right, right_on, normalize, &opts.sort)?);
+      }
+      match &join.asof {
+        Some(asof) => Ok(Box::new(frame::AsOfJoin::new(kind, left, left_on, right, right_on, asof, normalize)?)),
+        None => Ok(Box::new(frame::MergeJoin::new(kind, left, left_on, right, right_on, normalize)?)),
+      }
+    },
+  }
+}
+
+// Lower a query onto a pipeline of frames which read from the provided sources
+pub fn plan(query: &ast::Select, mut sources: Vec<Box<dyn Frame>>, opts: &Options) -> Result<Box<dyn Frame>, error::Error> {
+  let mut base = take_source(&mut sources, &query.from)?;
+  
+  for join in &query.joins {
+    let frm = take_source(&mut sources, &join.source)?;
+    let (mut left_on, mut right_on): (Vec<schema::QName>, Vec<schema::QName>) = (Vec::new(), Vec::new());
+    for (n, (a, b)) in join.on.iter().enumerate() {
+      let (l, r) = if b.scope() == Some(frm.name()) {
+        (a, b)
+      }else if a.scope() == Some(frm.name()) {
+        if join.asof.is_some() && n + 1 == join.on.len() {
+          return Err(error::QueryError::new(&format!("As-of condition must compare a column of the joined source as the lesser: {} ({} >= {})", frm.name(), a, b)).into());
+        }
+        (b, a)
+      }else{
+        return Err(error::QueryError::new(&format!("Join condition does not reference source: {} ({} = {})", frm.name(), a, b)).into());
+      };
+      // resolve unscoped columns, as named by USING, against the sources joined so far
+      left_on.push(match base.schema().index(l) {
+        Some(index) => base.schema().columns()[index].clone(),
+        None => return Err(error::QueryError::new(&format!("Join column not found: {} ({})", l, base.schema())).into()),
+      });
+      right_on.push(r.clone());
+    }
+    
+    let join_opts = JoinOptions{
+      kind: join.kind,
+      coalesce: join.using,
+      normalize: join.normalize.clone(),
+      asof: join.asof.clone(),
+    };
+    base = self::join(base, &left_on, frm, &right_on, &join_opts, opts)?;
+  }
+  
+  if let Some(filter) = &query.filter {
+    base = Box::new(frame::Where::new(base, filter)?);
+  }
+  
+  let aggs: Vec<ast::Aggregate> = query.columns.iter().filter_map(|e| {
+    match e {
+      ast::Column::Aggregate(agg, _) => Some(agg.clone()),
+      _ => None,
+    }
+  }).collect();
+  if !query.group.is_empty() || !aggs.is_empty() {
+    base = Box::new(frame::Aggregate::new(base, &query.group, &aggs)?);
+  }
+  
+  // rows are sorted before they are projected, so they may be sorted on
+  // columns which are not selected, unless the order names the alias of
+  // a selected column, which only the projected rows have
+  let project = !query.columns.iter().all(|e| { matches!(e, ast::Column::All(wildcard) if wildcard.is_all()) });
+  let sort_projected = project && query.order.iter().any(|e| { base.schema().index(&e.on).is_none() });
+  if !query.order.is_empty() && !sort_projected {
+    base = Box::new(frame::Sorted::new_with_order(&mut base, &query.order, &opts.sort)?);
+  }
+  
+  if project {
+    let projs = select::resolve(base.schema(), &query.columns)?;
+    let sel = select::projection(base.schema(), &projs)?;
+    base = Box::new(frame::Filter::new(base, sel)?);
+  }
+  
+  if sort_projected {
+    base = Box::new(frame::Sorted::new_with_order(&mut base, &query.order, &opts.sort)?);
+  }
+  
+  if let Some(limit) = query.limit {
+    base = Box::new(frame::Limit::new(base, limit)?);
+  }
+  
+  Ok(base)
+}
+
+fn take_source(sources: &mut Vec<Box<dyn Frame>>, name: &str) -> Result<Box<dyn Frame>, error::Error> {
+  match sources.iter().position(|e| { e.name() == name }) {
+    Some(index) => Ok(sources.remove(index)),
+    None => Err(error::QueryError::new(&format!("No input matches source: {}", name)).into()),
+  }
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+  
+  use crate::csvql::query::parser;
+  
+  fn source(name: &str, text: &'static str, types: &[(&str, value::Type)]) -> Box<dyn Frame> {
+    let opts = frame::CsvOptions{
+      types: types.iter().map(|(qname, typ)| { (schema::QName::parse(qname).unwrap(), *typ) }).collect(),
+      ..frame::CsvOptions::default()
+    };
+    Box::new(frame::Csv::new(name, text.as_bytes(), &opts).unwrap())
+  }
+  
+  fn collect(mut frm: Box<dyn Frame>) -> Vec<Vec<String>> {
+    frm.rows().map(|e| { e.unwrap().iter().map(|e| { e.to_owned() }).collect() }).collect()
+  }
+  
+  fn rows(text: &str) -> Vec<Vec<String>> {
+    text.lines().map(|e| { e.split(',').map(|e| { e.to_owned() }).collect() }).collect()
+  }
+  
+  #[allow(clippy::redundant_field_names)]
+  fn join_opts(kind: ast::JoinKind) -> JoinOptions {
+    JoinOptions{
+      kind: kind,
+      coalesce: false,
+      normalize: Vec::new(),
+      asof: None,
+    }
+  }
+  
+  #[test]
+  #[allow(clippy::redundant_field_names)]
+  fn join_mixed_types() {
+    // an integer key is compared as text with a string key
+    let (left, right) = ("id,x\n1,a\n2,b\n10,c\n", "id,y\n1,p\n10,q\nN/A,r\n");
+    for strategy in [Strategy::Merge, Strategy::Hash] {
+      let opts = Options{
+        strategy: strategy,
+        ..Options::default()
+      };
+      let frm = join(source("a", left, &[("a.id", value::Type::Integer)]), &[schema::QName::parse("a.id").unwrap()], source("b", right, &[]), &[schema::QName::parse("b.id").unwrap()], &join_opts(ast::JoinKind::Inner), &opts).unwrap();
+      assert_eq!(value::Type::String, frm.schema().column_type(0));
+      let mut res = collect(frm);
+      res.sort();
+      assert_eq!(rows("1,a,1,p\n10,c,10,q"), res);
+    }
+    
+    let query = parser::parse("select * from a join b on a.id = b.id order by a.x").unwrap();
+    let frm = plan(&query, vec![source("a", left, &[("a.id", value::Type::Integer)]), source("b", right, &[])], &Options::default()).unwrap();
+    assert_eq!(rows("1,a,1,p\n10,c,10,q"), collect(frm));
+    
+    // types whose values compare with each other are left as they are
+    let frm = join(source("a", left, &[("a.id", value::Type::Integer)]), &[schema::QName::parse("a.id").unwrap()], source("b", "id,y\n1.0,p\n", &[("b.id", value::Type::Decimal)]), &[schema::QName::parse("b.id").unwrap()], &join_opts(ast::JoinKind::Inner), &Options::default()).unwrap();
+    assert_eq!(value::Type::Integer, frm.schema().column_type(0));
+    assert_eq!(rows("1,a,1.0,p"), collect(frm));
+  }
+  
+  #[test]
+  fn join_inferred_types() {
+    // inference types each input alone, so the same key may be an
+    // integer in one and a string in the other
+    let opts = frame::CsvOptions{
+      infer: Some(100),
+      ..frame::CsvOptions::default()
+    };
+    let left: Box<dyn Frame> = Box::new(frame::Csv::new("a", "id,x\n1,a\n2,b\n10,c\n".as_bytes(), &opts).unwrap());
+    let right: Box<dyn Frame> = Box::new(frame::Csv::new("b", "id,y\n1,p\n10,q\nN/A,r\n".as_bytes(), &opts).unwrap());
+    assert_eq!((value::Type::Integer, value::Type::String), (left.schema().column_type(0), right.schema().column_type(0)));
+    let frm = join(left, &[schema::QName::parse("a.id").unwrap()], right, &[schema::QName::parse("b.id").unwrap()], &join_opts(ast::JoinKind::Left), &Options::default()).unwrap();
+    let mut res = collect(frm);
+    res.sort();
+    assert_eq!(rows("1,a,1,p\n10,c,10,q\n2,b,,"), res);
+  }
+  
+  fn query(text: &str, sources: Vec<Box<dyn Frame>>) -> Vec<Vec<String>> {
+    collect(plan(&parser::parse(text).unwrap(), sources, &Options::default()).unwrap())
+  }
+  
+  #[test]
+  fn order_by_alias() {
+    let text = "id,x\n1,p\n2,q\n3,p\n4,r\n5,p\n6,q\n";
+    let a = || { source("a", text, &[("a.id", value::Type::Integer)]) };
+    assert_eq!(rows("p,3\nq,2\nr,1"), query("select a.x, count(*) as n from a group by a.x order by n desc", vec![a()]));
+    assert_eq!(rows("12\n10\n8"), query("select a.id * 2 as d from a order by d desc limit 3", vec![a()]));
+    assert_eq!(rows("r,1,4\nq,2,8\np,3,9"), query("select a.x, count(*), sum(a.id) as total from a group by a.x order by a.x desc nulls first, total limit 10", vec![a()]));
+    // columns which are not selected may still be sorted on
+    assert_eq!(rows("5\n3\n1\n6\n2\n4"), query("select a.id from a order by a.x, a.id desc", vec![a()]));
+    
+    let err = plan(&parser::parse("select a.id as d from a order by d, a.x").unwrap(), vec![a()], &Options::default()).err().unwrap();
+    assert!(err.to_string().starts_with("Index column not found: a.x"));
+  }
+  
+  #[allow(clippy::redundant_field_names)]
+  fn sized(name: &str, size: Option<u64>, sorted: bool) -> Box<dyn Frame> {
+    let opts = frame::CsvOptions{
+      size: size,
+      ..frame::CsvOptions::default()
+    };
+    let text = match name {
+      "a" => "id,x\n1,a\n2,b\n3,c\n",
+      _ => "id,y\n2,p\n3,q\n4,r\n",
+    };
+    let mut frm: Box<dyn Frame> = Box::new(frame::Csv::new(name, text.as_bytes(), &opts).unwrap());
+    if sorted {
+      frm = Box::new(frame::Sorted::new(&mut frm, &[schema::QName::new(name, "id")], &[], &sort::SortOptions::default()).unwrap());
+    }
+    frm
+  }
+  
+  // The plan of a join of inputs of the specified sizes, and its rows
+  #[allow(clippy::redundant_field_names)]
+  fn plan_join(kind: ast::JoinKind, left: Box<dyn Frame>, right: Box<dyn Frame>, strategy: Strategy, asof: bool) -> Result<(String, Vec<Vec<String>>), error::Error> {
+    let opts = Options{
+      strategy: strategy,
+      hash_limit: 100,
+      ..Options::default()
+    };
+    let join_opts = JoinOptions{
+      asof: if asof { Some(ast::AsOf{ tolerance: None }) } else { None },
+      ..join_opts(kind)
+    };
+    let frm = join(left, &[schema::QName::parse("a.id").unwrap()], right, &[schema::QName::parse("b.id").unwrap()], &join_opts, &opts)?;
+    let plan = frm.to_string();
+    let mut res = collect(frm);
+    res.sort();
+    Ok((plan, res))
+  }
+  
+  #[test]
+  fn join_strategies() {
+    let inner = rows("2,b,2,p\n3,c,3,q");
+    let plan = |left: Option<u64>, right: Option<u64>, sorted: bool, strategy: Strategy| {
+      let (plan, res) = plan_join(ast::JoinKind::Inner, sized("a", left, sorted), sized("b", right, sorted), strategy, false).unwrap();
+      assert_eq!(inner, res);
+      plan
+    };
+    let (hash, swapped, merge) = ("(a <INNER< b[#b.id])[a.id; b.id]", "(a[#a.id] >INNER> b)[a.id; b.id]", "(a{a.id} <INNER> b{b.id})[a.id; b.id]");
+    // the smaller side is indexed if it is within the limit
+    assert_eq!(hash, plan(Some(50), Some(10), false, Strategy::Auto));
+    assert_eq!(swapped, plan(Some(10), Some(50), false, Strategy::Auto));
+    assert_eq!(hash, plan(Some(200), Some(100), false, Strategy::Auto));
+    assert_eq!(merge, plan(Some(500), Some(200), false, Strategy::Auto));
+    // the right side is indexed unless both sizes are known
+    assert_eq!(hash, plan(None, Some(10), false, Strategy::Auto));
+    assert_eq!(merge, plan(Some(10), None, false, Strategy::Auto));
+    assert_eq!(merge, plan(None, None, false, Strategy::Auto));
+    // inputs which are already sorted are merged
+    assert_eq!(merge, plan(Some(50), Some(10), true, Strategy::Auto));
+    // a strategy which is specified is used whatever the sizes
+    assert_eq!("(a{a.id} <INNER< b[#b.id])[a.id; b.id]", plan(Some(50), Some(10), true, Strategy::Hash));
+    assert_eq!(merge, plan(Some(5), Some(10), false, Strategy::Merge));
+    assert_eq!(hash, plan(Some(500), Some(200), false, Strategy::Hash));
+    assert_eq!(swapped, plan(Some(200), Some(500), false, Strategy::Hash));
+  }
+  
+  #[test]
+  fn filtering_joins_never_swap() {
+    for (kind, res) in [(ast::JoinKind::Semi, "2,b\n3,c"), (ast::JoinKind::Anti, "1,a")] {
+      for strategy in [Strategy::Auto, Strategy::Hash] {
+        let (plan, rows) = plan_join(kind, sized("a", Some(5), false), sized("b", Some(50), false), strategy, false).unwrap();
+        assert_eq!(format!("(a <{}< b[#b.id])[a.id; b.id]", kind), plan);
+        assert_eq!(self::rows(res), rows);
+      }
+    }
+  }
+  
+  #[test]
+  fn asof_strategies() {
+    let (plan, res) = plan_join(ast::JoinKind::Inner, sized("a", Some(5), false), sized("b", Some(50), false), Strategy::Auto, true).unwrap();
+    assert_eq!("(a{a.id} <INNER ASOF> b{b.id})[a.id; b.id]", plan);
+    assert_eq!(rows("2,b,2,p\n3,c,3,q"), res);
+    let err = plan_join(ast::JoinKind::Inner, sized("a", Some(5), false), sized("b", Some(50), false), Strategy::Hash, true).err().unwrap();
+    assert_eq!("An as-of join cannot use the hash strategy, only merge", err.to_string());
+  }
+}
@@ -0,0 +1,229 @@
+use std::fmt;
+
+use csv;
+
+use crate::csvql::query::ast;
+use crate::csvql::query::schema;
+use crate::csvql::query::value;
+use crate::csvql::query::error;
+
+// An operand, bound to a column index and type if it refers to a column
+#[derive(Debug, Clone)]
+pub enum Operand {
+  Column(schema::QName, usize, value::Type),
+  Literal(String, value::Value),
+}
+
+impl Operand {
+  fn new(expr: &ast::Expr, schema: &schema::Schema) -> Result<Operand, error::Error> {
+    match expr {
+      ast::Expr::Column(qname) => match schema.index(qname) {
+        Some(index) => Ok(Operand::Column(qname.clone(), index, schema.column_type(index))),
+        None => Err(error::QueryError::new(&format!("Predicate column not found: {} ({})", qname, schema)).into()),
+      },
+      ast::Expr::Literal(ast::Literal::String(v)) => Ok(Operand::Literal(v.to_owned(), value::Value::String(v.to_owned()))),
+      ast::Expr::Literal(ast::Literal::Number(v)) => Ok(Operand::Literal(v.to_owned(), value::Value::infer(v))),
+      expr => Err(error::QueryError::new(&format!("Expected a column or literal: {}", expr)).into()),
+    }
+  }
+  
+  // Convert a literal to the type of the column it is compared against
+  fn coerce(self, other: &Operand) -> Result<Operand, error::Error> {
+    match (self, other) {
+      (Self::Literal(text, _), Self::Column(qname, _, typ)) => match typ.value(&text) {
+        Ok(val) => Ok(Self::Literal(text, val)),
+        Err(err) => Err(error::QueryError::new(&format!("Cannot compare {} with {:?}: {}", qname, text, err)).into()),
+      },
+      (oper, _) => Ok(oper),
+    }
+  }
+  
+  fn text<'a>(&'a self, row: &'a csv::StringRecord) -> Result<&'a str, error::Error> {
+    match self {
+      Self::Column(_, index, _) => match row.get(*index) {
+        Some(v) => Ok(v),
+        None => Err(error::QueryError::new(&format!("Index not found in data: {} > {}", index, row.len() as i64 - 1)).into()),
+      },
+      Self::Literal(v, _) => Ok(v),
+    }
+  }
+  
+  fn eval(&self, row: &csv::StringRecord) -> Result<value::Value, error::Error> {
+    match self {
+      Self::Column(qname, _, typ) => match typ.value(self.text(row)?) {
+        Ok(val) => Ok(val),
+        Err(err) => Err(error::FrameError::new(&format!("Column {}: {}", qname, err)).into()),
+      },
+      Self::Literal(_, v) => Ok(v.clone()),
+    }
+  }
+}
+
+impl fmt::Display for Operand {
+  fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+    match self {
+      Self::Column(qname, _, _) => write!(f, "{}", qname),
+      Self::Literal(v, _) => write!(f, "'{}'", v),
+    }
+  }
+}
+
+// A row predicate, bound to the schema of the rows it is evaluated against
+#[derive(Debug, Clone)]
+pub enum Predicate {
+  Compare(Operand, ast::Operator, Operand),
+  IsEmpty(Operand),
+  In(Operand, Vec<Operand>),
+  Like(Operand, Operand),
+  And(Box<Predicate>, Box<Predicate>),
+  Or(Box<Predicate>, Box<Predicate>),
+  Not(Box<Predicate>),
+}
+
+impl Predicate {
+  pub fn new(expr: &ast::Expr, schema: &schema::Schema) -> Result<Predicate, error::Error> {
+    match expr {
+      ast::Expr::Compare(lhs, op, rhs) => {
+        let (lhs, rhs) = (Operand::new(lhs, schema)?, Operand::new(rhs, schema)?);
+        let lhs = lhs.coerce(&rhs)?;
+        let rhs = rhs.coerce(&lhs)?;
+        Ok(Self::Compare(lhs, *op, rhs))
+      },
+      ast::Expr::IsEmpty(expr) => Ok(Self::IsEmpty(Operand::new(expr, schema)?)),
+      ast::Expr::In(expr, set) => {
+        let oper = Operand::new(expr, schema)?;
+        let mut opers: Vec<Operand> = Vec::new();
+        for e in set {
+          opers.push(Operand::new(e, schema)?.coerce(&oper)?);
+        }
+        Ok(Self::In(oper, opers))
+      },
+      ast::Expr::Like(expr, pattern) => Ok(Self::Like(Operand::new(expr, schema)?, Operand::new(pattern, schema)?)),
+      ast::Expr::And(lhs, rhs) => Ok(Self::And(Box::new(Self::new(lhs, schema)?), Box::new(Self::new(rhs, schema)?))),
+      ast::Expr::Or(lhs, rhs) => Ok(Self::Or(Box::new(Self::new(lhs, schema)?), Box::new(Self::new(rhs, schema)?))),
+      ast::Expr::Not(expr) => Ok(Self::Not(Box::new(Self::new(expr, schema)?))),
+      expr => Err(error::QueryError::new(&format!("Expression is not a predicate: {}", expr)).into()),
+    }
+  }
+  
+  pub fn eval(&self, row: &csv::StringRecord) -> Result<bool, error::Error> {
+    match self {
+      Self::Compare(lhs, op, rhs) => {
+        let cmp = lhs.eval(row)?.cmp(&rhs.eval(row)?);
+        Ok(match op {
+          ast::Operator::Eq => cmp.is_eq(),
+          ast::Operator::Ne => cmp.is_ne(),
+          ast::Operator::Lt => cmp.is_lt(),
+          ast::Operator::Le => cmp.is_le(),
+          ast::Operator::Gt => cmp.is_gt(),
+          ast::Operator::Ge => cmp.is_ge(),
+        })
+      },
+      Self::IsEmpty(oper) => Ok(oper.text(row)?.is_empty()),
+      Self::In(oper, set) => {
+        let v = oper.eval(row)?;
+        for e in set {
+          if e.eval(row)? == v {
+            return Ok(true);
+          }
+        }
+        Ok(false)
+      },
+      Self::Like(oper, pattern) => Ok(like(oper.text(row)?, pattern.text(row)?)),
+      Self::And(lhs, rhs) => Ok(lhs.eval(row)? && rhs.eval(row)?),
+      Self::Or(lhs, rhs) => Ok(lhs.eval(row)? || rhs.eval(row)?),
+      Self::Not(pred) => Ok(!pred.eval(row)?),
+    }
+  }
+}
+
+impl fmt::Display for Predicate {
+  fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+    match self {
+      Self::Compare(lhs, op, rhs) => write!(f, "{} {} {}", lhs, op, rhs),
+      Self::IsEmpty(oper) => write!(f, "{} IS EMPTY", oper),
+      Self::In(oper, set) => {
+        let set: Vec<String> = set.iter().map(|e| { e.to_string() }).collect();
+        write!(f, "{} IN ({})", oper, set.join(", "))
+      },
+      Self::Like(oper, pattern) => write!(f, "{} LIKE {}", oper, pattern),
+      Self::And(lhs, rhs) => write!(f, "({} AND {})", lhs, rhs),
+      Self::Or(lhs, rhs) => write!(f, "({} OR {})", lhs, rhs),
+      Self::Not(pred) => write!(f, "NOT {}", pred),
+    }
+  }
+}
+
+// Match text against a SQL LIKE pattern, where '%' matches any sequence
+// of characters and '_' matches exactly one character.
+fn like(text: &str, pattern: &str) -> bool {
+  let text: Vec<char> = text.chars().collect();
+  let pattern: Vec<char> = pattern.chars().collect();
+  let (mut t, mut p) = (0, 0);
+  let mut backtrack: Option<(usize, usize)> = None; // the last '%' and the text position it has consumed up to
+  while t < text.len() {
+    if p < pattern.len() && pattern[p] == '%' {
+      backtrack = Some((p, t));
+      p += 1;
+    }else if p < pattern.len() && (pattern[p] == '_' || pattern[p] == text[t]) {
+      t += 1;
+      p += 1;
+    }else if let Some((bp, bt)) = backtrack {
+      backtrack = Some((bp, bt + 1));
+      p = bp + 1;
+      t = bt + 1;
+    }else{
+      return false;
+    }
+  }
+  pattern[p..].iter().all(|e| { *e == '%' })
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+  use crate::csvql::query::parser;
+  
+  fn eval(text: &str, row: &[&str]) -> bool {
+    let mut schema = schema::Schema::new("a", ["id", "name", "amount"].into_iter());
+    schema.set_column_type(0, value::Type::Integer);
+    schema.set_column_type(2, value::Type::Decimal);
+    let pred = Predicate::new(&parser::parse_expr(text).unwrap(), &schema).unwrap();
+    pred.eval(&csv::StringRecord::from(row.to_vec())).unwrap()
+  }
+  
+  #[test]
+  fn like_patterns() {
+    assert!(like("abc", "abc"));
+    assert!(!like("abc", "ab"));
+    assert!(like("abc", "a_c"));
+    assert!(!like("ac", "a_c"));
+    assert!(like("abc", "%"));
+    assert!(like("", "%"));
+    assert!(like("abc", "a%"));
+    assert!(like("abc", "%c"));
+    assert!(like("abcbc", "%bc"));
+    assert!(like("axbyc", "a%b%c"));
+    assert!(!like("axbyd", "a%b%c"));
+    assert!(like("%xa", "%a"));
+    assert!(like("a%", "a%"));
+    assert!(like("50%", "%0%"));
+  }
+  
+  #[test]
+  fn eval_predicates() {
+    let row = ["10", "alice", "2.50"];
+    assert!(eval("a.id = 10", &row));
+    assert!(eval("a.id > 9", &row)); // compared as integers, not text
+    assert!(!eval("a.id < 9", &row));
+    assert!(eval("a.amount = 2.5", &row));
+    assert!(eval("a.name != 'bob'", &row));
+    assert!(eval("a.name like 'al%' and a.id >= 10", &row));
+    assert!(eval("a.name not like 'b%'", &row));
+    assert!(eval("a.id in (1, 10)", &row));
+    assert!(!eval("a.id not in (1, 10)", &row));
+    assert!(eval("a.id = 1 or not a.name is empty", &row));
+    assert!(eval("a.amount is empty", &["1", "x", ""]));
+    assert!(!eval("(a.id = 1 or a.id = 10) and a.name = 'bob'", &row));
+  }
+}
@@ -4,6 +4,7 @@ use std::iter;
 use std::hash;
 use std::collections::HashMap;
 
+use crate::csvql::query::value;
 use crate::csvql::query::error;
 
 #[derive(Clone, Eq)]
@@ -12,6 +13,7 @@ pub struct QName {
   name: String,
 }
 
+#[allow(clippy::needless_lifetimes)]
 impl QName {
   pub fn parse(text: &str) -> Result<QName, error::Error> {
     let split: Vec<&str> = text.splitn(2, ".").collect();
@@ -86,10 +88,9 @@ impl PartialEq for QName {
 
 impl hash::Hash for QName {
   fn hash<H: hash::Hasher>(&self, state: &mut H) {
-    match &self.scope {
-      Some(scope) => scope.hash(state),
-      None => {},
-    };
+    if let Some(scope) = &self.scope {
+      scope.hash(state);
+    }
     self.name.hash(state);
   }
 }
@@ -106,18 +107,52 @@ impl fmt::Debug for QName {
   }
 }
 
+// The style in which the names of columns are written to an output header
+#[derive(Debug, Clone, Copy, PartialEq, Default)]
+pub enum Header {
+  #[default]
+  Alias, // the alias of a column if it has one, otherwise its qualified name
+  Bare, // the alias of a column if it has one, otherwise its unqualified name
+  Qualified, // the qualified name of a column, even if it has an alias
+}
+
+impl Header {
+  pub fn parse(text: &str) -> Result<Header, error::Error> {
+    match text.trim().to_lowercase().as_ref() {
+      "alias" => Ok(Self::Alias),
+      "bare" => Ok(Self::Bare),
+      "qualified" => Ok(Self::Qualified),
+      _ => Err(error::ParseError::new(&format!("Invalid header style, expected one of qualified, bare or alias: {}", text)).into()),
+    }
+  }
+}
+
 #[derive(Clone)]
 pub struct Schema {
   cmap: HashMap<QName, usize>,
   keys: Vec<QName>,
+  types: Vec<value::Type>,
+  labels: Vec<Option<String>>, // the alias by which each column is selected, if any
+  aliases: Vec<(QName, usize)>, // names which refer to a column other than by its own name, in the order they were added
 }
 
+#[allow(clippy::needless_lifetimes, clippy::redundant_field_names)]
 impl Schema {
   pub fn new<'a>(scope: &str, hdrs: impl iter::Iterator<Item=&'a str>) -> Schema {
-    Self::new_with_keys(hdrs.map(|e| { QName::new(scope, e) }).collect())
+    let keys: Vec<QName> = hdrs.map(|e| { QName::new(scope, e) }).collect();
+    let types = vec![value::Type::String; keys.len()];
+    Self::new_with_columns(keys, types)
   }
   
-  fn new_with_keys<'a>(keys: Vec<QName>) -> Schema {
+  // Create a schema for an input without a header, with positional
+  // column names: c1, c2, ...
+  pub fn new_positional(scope: &str, count: usize) -> Schema {
+    let keys: Vec<QName> = (1..=count).map(|e| { QName::new(scope, &format!("c{}", e)) }).collect();
+    let types = vec![value::Type::String; keys.len()];
+    Self::new_with_columns(keys, types)
+  }
+  
+  pub fn new_with_columns(keys: Vec<QName>, types: Vec<value::Type>) -> Schema {
     let mut cmap: HashMap<QName, usize> = HashMap::new();
     for (i, k) in keys.iter().enumerate() {
       cmap.insert(k.clone(), i);
@@ -125,32 +160,139 @@ impl Schema {
     
     Schema{
       cmap: cmap,
+      labels: vec![None; keys.len()],
       keys: keys,
+      types: types,
+      aliases: Vec::new(),
     }
   }
   
   pub fn join(&self, with: &Schema) -> Schema {
     let mut keys: Vec<QName> = Vec::new();
-    for k in &self.keys {
+    let mut types: Vec<value::Type> = Vec::new();
+    for (k, t) in self.keys.iter().zip(&self.types) {
       keys.push(k.clone());
+      types.push(*t);
     }
-    for k in &with.keys {
+    for (k, t) in with.keys.iter().zip(&with.types) {
       keys.push(k.clone());
+      types.push(*t);
     }
-    
-    let mut cmap: HashMap<QName, usize> = HashMap::new();
-    for (i, k) in keys.iter().enumerate() {
-      cmap.insert(k.clone(), i);
+    let mut schema = Self::new_with_columns(keys, types);
+    schema.labels = self.labels.iter().chain(&with.labels).cloned().collect();
+    for (alias, index) in self.aliases() {
+      schema.set_alias(alias, index);
     }
-    
-    Schema{
-      cmap: cmap,
-      keys: keys,
+    for (alias, index) in with.aliases() {
+      schema.set_alias(alias, index + self.count());
+    }
+    schema
+  }
+  
+  pub fn select(&self, indexes: &[usize]) -> Schema {
+    let indexes: Vec<usize> = indexes.iter().filter(|e| { **e < self.keys.len() }).copied().collect();
+    let mut schema = Self::new_with_columns(
+      indexes.iter().map(|e| { self.keys[*e].clone() }).collect(),
+      indexes.iter().map(|e| { self.types[*e] }).collect(),
+    );
+    schema.labels = indexes.iter().map(|e| { self.labels[*e].clone() }).collect();
+    for (alias, index) in self.aliases() {
+      if let Some(n) = indexes.iter().position(|e| { *e == index }) {
+        if !schema.cmap.contains_key(&alias) {
+          schema.set_alias(alias, n);
+        }
+      }
+    }
+    schema
+  }
+  
+  // Produce a schema in which each pair of columns is merged into the
+  // first of the pair; the second column is removed. The merged column
+  // belongs to neither input, so it is named by the unqualified name of
+  // the first, and the names of both columns become aliases for it.
+  pub fn coalesce(&self, pairs: &[(usize, usize)]) -> Schema {
+    let indexes: Vec<usize> = (0..self.count()).filter(|e| { !pairs.iter().any(|p| { p.1 == *e }) }).collect();
+    let mut schema = self.select(&indexes);
+    for (keep, drop) in pairs {
+      if let (Some(n), Some(first), Some(second)) = (indexes.iter().position(|e| { e == keep }), self.keys.get(*keep), self.keys.get(*drop)) {
+        let key = QName::new_unscoped(first.name());
+        schema.cmap.insert(key.clone(), n);
+        schema.keys[n] = key;
+        schema.set_alias(first.clone(), n);
+        schema.set_alias(second.clone(), n);
+        for (alias, index) in self.aliases() {
+          if index == *drop {
+            schema.set_alias(alias, n);
+          }
+        }
+      }
+    }
+    schema
+  }
+  
+  // Select a column by an alias, which names it in an output header and
+  // by which it may also be referenced
+  pub fn set_label(&mut self, index: usize, label: &str) {
+    if let Some(l) = self.labels.get_mut(index) {
+      *l = Some(label.to_owned());
+      self.set_alias(QName::new_unscoped(label), index);
+    }
+  }
+  
+  // Refer to the column at the specified index by another name, which
+  // replaces any column the name referred to before
+  fn set_alias(&mut self, alias: QName, index: usize) {
+    self.cmap.insert(alias.clone(), index);
+    self.aliases.retain(|e| { e.0 != alias });
+    if self.keys.get(index) != Some(&alias) {
+      self.aliases.push((alias, index));
+    }
+  }
+  
+  // Names which refer to a column other than by its own name
+  fn aliases(&self) -> Vec<(QName, usize)> {
+    self.aliases.clone()
+  }
+  
+  // The type of the column at the specified index
+  pub fn column_type(&self, index: usize) -> value::Type {
+    match self.types.get(index) {
+      Some(t) => *t,
+      None => value::Type::String,
+    }
+  }
+  
+  pub fn set_type(&mut self, qname: &QName, typ: value::Type) -> Result<(), error::Error> {
+    match self.index(qname) {
+      Some(index) => {
+        self.set_column_type(index, typ);
+        Ok(())
+      },
+      None => Err(error::QueryError::new(&format!("Column not found: {} ({})", qname, self)).into()),
+    }
+  }
+  
+  pub fn set_column_type(&mut self, index: usize, typ: value::Type) {
+    if let Some(t) = self.types.get_mut(index) {
+      *t = typ;
     }
   }
   
-  pub fn _exclude(&self, col: &str) -> Schema {
-    Self::new_with_keys(self.keys.iter().filter(|e| { e.name() != col }).map(|e| { e.clone() }).collect())
+  // Convert a field in the column at the specified index to a typed value
+  pub fn value(&self, index: usize, text: &str) -> Result<value::Value, error::Error> {
+    self.normalized_value(index, &[], text)
+  }
+  
+  // Convert a field in the column at the specified index to a typed value
+  // after applying normalizers to it
+  pub fn normalized_value(&self, index: usize, norm: &[value::Normalizer], text: &str) -> Result<value::Value, error::Error> {
+    match value::Normalizer::normalize(norm, self.column_type(index), text) {
+      Ok(val) => Ok(val),
+      Err(err) => match self.keys.get(index) {
+        Some(key) => Err(error::FrameError::new(&format!("Column {}: {}", key, err)).into()),
+        None => Err(err),
+      },
+    }
   }
   
   pub fn count(&self) -> usize {
@@ -158,7 +300,7 @@ impl Schema {
   }
   
   pub fn empty_row(&self, adjust: i32) -> Vec<String> {
-    return Self::empty_vec((self.count() as i32 + cmp::max(-(self.count() as i32), adjust)) as usize);
+    Self::empty_vec((self.count() as i32 + cmp::max(-(self.count() as i32), adjust)) as usize)
   }
   
   pub fn _get<'a>(&'a self, name: &str) -> Option<&'a QName> {
@@ -170,37 +312,55 @@ impl Schema {
     None
   }
   
-  pub fn _columns<'a>(&'a self) -> Vec<&'a QName> {
+  pub fn columns<'a>(&'a self) -> Vec<&'a QName> {
     self.keys.iter().collect()
   }
   
-  pub fn record<'a>(&'a self) -> Vec<String> {
-    self.keys.iter().map(|e| { e.qname() }).collect()
+  // The names of columns as written to an output header in the specified
+  // style, which must not be ambiguous
+  pub fn header(&self, style: Header) -> Result<Vec<String>, error::Error> {
+    let hdrs: Vec<String> = self.keys.iter().zip(&self.labels).map(|(k, l)| {
+      match (style, l) {
+        (Header::Alias, Some(l)) | (Header::Bare, Some(l)) => l.to_owned(),
+        (Header::Bare, None) => k.name().to_owned(),
+        _ => k.qname(),
+      }
+    }).collect();
+    for (n, hdr) in hdrs.iter().enumerate() {
+      if let Some(m) = hdrs[..n].iter().position(|e| { e == hdr }) {
+        let hint = match style {
+          Header::Qualified => "",
+          _ => "; alias one of them differently or use qualified names",
+        };
+        return Err(error::QueryError::new(&format!("Ambiguous column name in header: {} ({} and {}){}", hdr, self.keys[m], self.keys[n], hint)).into());
+      }
+    }
+    Ok(hdrs)
   }
   
   pub fn description(&self, debug: bool) -> String {
     let mut dsc = String::new();
-    let mut n = 0;
-    for key in &self.keys {
+    for (n, key) in self.keys.iter().enumerate() {
       if n > 0 {
         dsc.push_str(", ");
       }
       if debug {
-        dsc.push_str(&format!("{}={}", &key, n));
+        dsc.push_str(&format!("{}={}:{}", &key, n, self.column_type(n)));
       }else{
-        dsc.push_str(&key.name());
+        dsc.push_str(key.name());
       }
-      n += 1;
     }
     dsc
   }
   
+  // The index of the column a name refers to. Unqualified names refer to
+  // a column by an alias, such as the label it is selected by, or else by
+  // its own name.
   pub fn index(&self, qname: &QName) -> Option<usize> {
-    if let Some(_) = qname.scope() {
-      return match self.cmap.get(qname) {
-        Some(index) => Some(*index),
-        None => None,
-      };
+    if qname.scope().is_some() {
+      return self.cmap.get(qname).copied();
+    }else if let Some(index) = self.cmap.get(qname) {
+      return Some(*index);
     }else{
       for (i, e) in self.keys.iter().enumerate() {
         if qname.matches(e) {
@@ -231,3 +391,49 @@ impl fmt::Debug for Schema {
     write!(f, "{}", self.description(true))
   }
 }
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+  
+  fn joined(labels: &[(usize, &str)]) -> Schema {
+    let mut schema = Schema::new("a", ["id", "name"].into_iter()).join(&Schema::new("b", ["id", "total"].into_iter()));
+    for (index, label) in labels {
+      schema.set_label(*index, label);
+    }
+    schema
+  }
+  
+  #[test]
+  fn header_styles() {
+    let schema = joined(&[(3, "sum")]);
+    assert_eq!(schema.header(Header::Alias).unwrap(), vec!["a.id", "a.name", "b.id", "sum"]);
+    assert_eq!(schema.header(Header::Qualified).unwrap(), vec!["a.id", "a.name", "b.id", "b.total"]);
+    assert_eq!(schema.header(Header::Bare).unwrap_err().to_string(), "Ambiguous column name in header: id (a.id and b.id); alias one of them differently or use qualified names");
+    
+    let schema = joined(&[(0, "a_id")]);
+    assert_eq!(schema.header(Header::Bare).unwrap(), vec!["a_id", "name", "id", "total"]);
+  }
+  
+  #[test]
+  fn header_ambiguous_aliases() {
+    let schema = joined(&[(1, "x"), (3, "x")]);
+    assert_eq!(schema.header(Header::Alias).unwrap_err().to_string(), "Ambiguous column name in header: x (a.name and b.total); alias one of them differently or use qualified names");
+    assert!(schema.header(Header::Bare).is_err());
+    assert_eq!(schema.header(Header::Qualified).unwrap(), vec!["a.id", "a.name", "b.id", "b.total"]);
+    
+    // an alias may also collide with the qualified name of another column
+    let schema = joined(&[(1, "b.id")]);
+    assert!(schema.header(Header::Alias).is_err());
+  }
+  
+  #[test]
+  fn index_labels() {
+    let schema = joined(&[(3, "sum")]);
+    assert_eq!(schema.index(&QName::parse("sum").unwrap()), Some(3));
+    assert_eq!(schema.index(&QName::parse("b.total").unwrap()), Some(3));
+    assert_eq!(schema.index(&QName::parse("name").unwrap()), Some(1));
+    assert_eq!(schema.index(&QName::parse("total").unwrap()), Some(3));
+    assert_eq!(schema.index(&QName::parse("a.sum").unwrap()), None);
+  }
+}
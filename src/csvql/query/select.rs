@@ -1,16 +1,25 @@
 use std::fmt;
 
 use csv;
+use regex;
 
+use crate::csvql::query::ast;
+use crate::csvql::query::expr;
 use crate::csvql::query::schema;
+use crate::csvql::query::parser;
 use crate::csvql::query::error;
 
 // A data selector
 pub trait Selector: fmt::Display + fmt::Debug {
+  fn schema(&self, source: &schema::Schema) -> schema::Schema;
   fn select(&self, row: &csv::StringRecord) -> Result<csv::StringRecord, error::Error>;
 }
 
 impl<S: Selector + ?Sized> Selector for Box<S> { // black magic
+  fn schema(&self, source: &schema::Schema) -> schema::Schema {
+    (**self).schema(source)
+  }
+  
   fn select(&self, row: &csv::StringRecord) -> Result<csv::StringRecord, error::Error> {
     (**self).select(row)
   }
@@ -20,20 +29,13 @@ impl<S: Selector + ?Sized> Selector for Box<S> { // black magic
 pub struct Columns {
   names: Vec<schema::QName>,
   indexes: Vec<usize>,
+  aliases: Vec<Option<String>>,
 }
 
+#[allow(clippy::redundant_field_names)]
 impl Columns {
-  pub fn parse(schema: &schema::Schema, text: &Vec<String>) -> Result<Columns, error::Error> {
-    let mut qnames: Vec<schema::QName> = Vec::new();
-    for t in text {
-      for e in t.split(",") {
-        qnames.push(schema::QName::parse(e)?);
-      }
-    }
-    Self::new(schema, &qnames)
-  }
-  
-  pub fn new(schema: &schema::Schema, qnames: &Vec<schema::QName>) -> Result<Columns, error::Error> {
+  // Select columns, each of which may be named by an alias
+  pub fn new(schema: &schema::Schema, qnames: &Vec<schema::QName>, aliases: Vec<Option<String>>) -> Result<Columns, error::Error> {
     let names: Vec<schema::QName> = qnames.clone();
     let mut indexes: Vec<usize> = Vec::new();
     for qname in qnames {
@@ -45,11 +47,22 @@ impl Columns {
     Ok(Columns{
       names: names,
       indexes: indexes,
+      aliases: aliases,
     })
   }
 }
 
 impl Selector for Columns {
+  fn schema(&self, source: &schema::Schema) -> schema::Schema {
+    let mut schema = source.select(&self.indexes);
+    for (n, alias) in self.aliases.iter().enumerate() {
+      if let Some(alias) = alias {
+        schema.set_label(n, alias);
+      }
+    }
+    schema
+  }
+  
   fn select(&self, row: &csv::StringRecord) -> Result<csv::StringRecord, error::Error> {
     let mut sel: Vec<String> = Vec::new();
     for index in &self.indexes {
@@ -74,45 +87,349 @@ impl fmt::Debug for Columns {
   }
 }
 
+// Parse lists of selected columns against a schema. Each item of a list
+// which names a column of the schema selects it, even if the name is not
+// valid in an expression, as in: h.order-id; other items are parsed as
+// wildcards or expressions, in which such names must be quoted, as in:
+// upper(h."order-id").
+pub fn parse(schema: &schema::Schema, texts: &[String]) -> Result<Vec<ast::Column>, error::Error> {
+  let mut cols: Vec<ast::Column> = Vec::new();
+  for text in texts {
+    // an item is complete once it names a column or parses, so commas
+    // within expressions are rejoined
+    let mut item = String::new();
+    for part in text.split(',') {
+      if !item.is_empty() {
+        item.push(',');
+      }
+      item.push_str(part);
+      if let Some(qname) = schema::QName::parse(item.trim()).ok().filter(|e| { schema.index(e).is_some() }) {
+        cols.push(ast::Column::Name(qname));
+        item.clear();
+      }else if let Ok(parsed) = parser::parse_selection(&item) {
+        cols.extend(parsed);
+        item.clear();
+      }
+    }
+    if !item.is_empty() {
+      parser::parse_selection(&item)?;
+    }
+  }
+  Ok(cols)
+}
+
+// Resolve selected columns against a schema into the projections which
+// compute them, in order. Wildcards are expanded into the columns they
+// match, in the order of the schema.
+pub fn resolve(schema: &schema::Schema, cols: &[ast::Column]) -> Result<Vec<ast::Projection>, error::Error> {
+  let mut projs: Vec<ast::Projection> = Vec::new();
+  for col in cols {
+    match col {
+      ast::Column::All(wildcard) => {
+        for index in expand(schema, wildcard)? {
+          projs.push(ast::Projection{
+            expr: ast::Expr::Column(schema.columns()[index].clone()),
+            alias: None,
+          });
+        }
+      },
+      ast::Column::Name(qname) => projs.push(ast::Projection{
+        expr: ast::Expr::Column(qname.clone()),
+        alias: None,
+      }),
+      ast::Column::Aggregate(agg, alias) => projs.push(ast::Projection{
+        expr: ast::Expr::Column(agg.qname()),
+        alias: alias.clone(),
+      }),
+      ast::Column::Expr(proj) => projs.push(proj.clone()),
+    }
+  }
+  Ok(projs)
+}
+
+// The indexes of the columns a wildcard selects
+fn expand(schema: &schema::Schema, wildcard: &ast::Wildcard) -> Result<Vec<usize>, error::Error> {
+  let mut indexes = matches(schema, &wildcard.pattern)?;
+  for exclusion in &wildcard.except {
+    let excluded = match exclusion {
+      ast::Exclusion::Pattern(pattern) => matches(schema, pattern)?,
+      ast::Exclusion::Name(qname) => {
+        let excluded: Vec<usize> = schema.columns().iter().enumerate().filter(|(_, e)| { qname.matches(e) }).map(|(i, _)| { i }).collect();
+        if excluded.is_empty() {
+          return Err(error::QueryError::new(&format!("Excluded column not found: {} ({})", qname, schema)).into());
+        }
+        excluded
+      },
+    };
+    indexes.retain(|e| { !excluded.contains(e) });
+  }
+  Ok(indexes)
+}
+
+// The indexes of the columns which match a pattern
+fn matches(schema: &schema::Schema, pattern: &ast::Pattern) -> Result<Vec<usize>, error::Error> {
+  let cols = schema.columns();
+  if let Some(scope) = pattern.scope() {
+    if !cols.iter().any(|e| { e.scope() == Some(scope) }) {
+      return Err(error::QueryError::new(&format!("No columns match: {} ({})", pattern, schema)).into());
+    }
+  }
+  let regex = match pattern {
+    ast::Pattern::Regex(_, regex) => match regex::Regex::new(regex) {
+      Ok(regex) => Some(regex),
+      Err(err) => return Err(error::QueryError::new(&format!("Invalid column pattern: {}: {}", pattern, err)).into()),
+    },
+    ast::Pattern::All(_) => None,
+  };
+  Ok(cols.iter().enumerate().filter(|(_, e)| {
+    pattern.scope().is_none_or(|s| { e.scope() == Some(s) }) && regex.as_ref().is_none_or(|r| { r.is_match(e.name()) })
+  }).map(|(i, _)| { i }).collect())
+}
+
+// Select the columns computed by projections: existing columns are picked
+// by index, and otherwise the projections are evaluated for each row
+pub fn projection(schema: &schema::Schema, projs: &[ast::Projection]) -> Result<Box<dyn Selector>, error::Error> {
+  let mut qnames: Vec<schema::QName> = Vec::new();
+  for proj in projs {
+    match &proj.expr {
+      ast::Expr::Column(qname) => qnames.push(qname.clone()),
+      _ => return Ok(Box::new(Projection::new(schema, projs)?)),
+    }
+  }
+  Ok(Box::new(Columns::new(schema, &qnames, projs.iter().map(|e| { e.alias.clone() }).collect())?))
+}
+
+// A selector which computes each of its columns from an expression, and
+// which produces its own schema
+#[derive(Clone)]
+pub struct Projection {
+  exprs: Vec<expr::Expr>,
+  schema: schema::Schema,
+}
+
+#[allow(clippy::redundant_field_names)]
+impl Projection {
+  pub fn new(schema: &schema::Schema, projs: &[ast::Projection]) -> Result<Projection, error::Error> {
+    let mut exprs: Vec<expr::Expr> = Vec::new();
+    let mut keys: Vec<schema::QName> = Vec::new();
+    for proj in projs {
+      let expr = expr::Expr::new(&proj.expr, schema)?;
+      keys.push(match &expr {
+        expr::Expr::Column(_, index, _) => schema.columns()[*index].clone(), // selected columns retain their qualified names
+        _ => proj.qname(),
+      });
+      exprs.push(expr);
+    }
+    let mut schema = schema::Schema::new_with_columns(keys, exprs.iter().map(|e| { e.result_type() }).collect());
+    for (n, proj) in projs.iter().enumerate() {
+      if let Some(alias) = &proj.alias {
+        schema.set_label(n, alias);
+      }
+    }
+    Ok(Projection{
+      exprs: exprs,
+      schema: schema,
+    })
+  }
+}
+
+impl Selector for Projection {
+  fn schema(&self, _source: &schema::Schema) -> schema::Schema {
+    self.schema.clone()
+  }
+  
+  fn select(&self, row: &csv::StringRecord) -> Result<csv::StringRecord, error::Error> {
+    let mut sel: Vec<String> = Vec::new();
+    for expr in &self.exprs {
+      sel.push(match expr {
+        expr::Expr::Column(_, index, _) => match row.get(*index) {
+          Some(col) => col.to_string(), // selected columns are copied verbatim
+          None => return Err(error::QueryError::new(&format!("Index not found in data: {} > {}", index, row.len() as i64 - 1)).into()),
+        },
+        expr => expr.eval(row)?.to_string(),
+      });
+    }
+    Ok(sel.into())
+  }
+}
+
+impl fmt::Display for Projection {
+  fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+    write!(f, "proj: [{}]", self.exprs.iter().zip(self.schema.columns()).map(|(e, n)| { format!("{} AS {}", e, n) }).collect::<Vec<String>>().join(", "))
+  }
+}
+
+impl fmt::Debug for Projection {
+  fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+    write!(f, "proj: {:?}", self.exprs)
+  }
+}
+
+// An input to join, with the keys on which it joins the inputs before
+// it: theirs and its own
+pub type JoinStep = (String, Vec<schema::QName>, Vec<schema::QName>);
+
+// The conditions which join inputs, as chains of columns which must be
+// equal, as in: a.id=b.id; a.(country,sku)=b.(country,sku); or pairwise
+// across several inputs, a.id=b.a_id, b.c_id=c.id
 #[derive(Clone)]
 pub struct Join {
-  on: Vec<schema::QName>,
+  chains: Vec<Vec<schema::QName>>,
 }
 
+#[allow(clippy::needless_lifetimes, clippy::redundant_field_names)]
 impl Join {
   pub fn parse(text: &str) -> Result<Join, error::Error> {
-    let mut on: Vec<schema::QName> = Vec::new();
-    for e in text.split("=") {
-      on.push(schema::QName::parse(e)?);
+    Self::new(parser::parse_join(text)?)
+  }
+  
+  pub fn new(chains: Vec<Vec<schema::QName>>) -> Result<Join, error::Error> {
+    for qname in chains.iter().flatten() {
+      if qname.scope().is_none() {
+        return Err(error::QueryError::new(&format!("Join column must be qualified by an input: {}", qname)).into());
+      }
     }
-    Ok(Self::new(on))
+    Ok(Join{
+      chains: chains,
+    })
   }
   
-  pub fn new(on: Vec<schema::QName>) -> Join {
-    Join{
-      on: on,
+  // The inputs referenced by these conditions
+  pub fn scopes<'a>(&'a self) -> impl Iterator<Item = &'a str> {
+    self.chains.iter().flatten().filter_map(|e| { e.scope() })
+  }
+  
+  // The keys on which an input joins the inputs already joined, which are
+  // listed in the order they were joined. Where a column of the input is
+  // equal to columns of several joined inputs, the most recently joined
+  // is preferred. Returns None if no condition relates them.
+  pub fn keys(&self, joined: &[&str], scope: &str) -> Option<(Vec<schema::QName>, Vec<schema::QName>)> {
+    let (mut left, mut right): (Vec<schema::QName>, Vec<schema::QName>) = (Vec::new(), Vec::new());
+    for chain in &self.chains {
+      let r = chain.iter().find(|e| { e.scope() == Some(scope) });
+      let l = chain.iter().filter_map(|e| {
+        joined.iter().position(|s| { e.scope() == Some(*s) }).map(|n| { (n, e) })
+      }).max_by_key(|e| { e.0 }).map(|e| { e.1 });
+      if let (Some(l), Some(r)) = (l, r) {
+        left.push(l.clone());
+        right.push(r.clone());
+      }
+    }
+    if right.is_empty() {
+      None
+    }else{
+      Some((left, right))
     }
   }
   
-  pub fn for_scope<'a>(&'a self, scope: &str) -> Option<&'a schema::QName> {
-    let check = Some(scope);
-    for e in &self.on {
-      if e.scope() == check {
-        return Some(&e);
+  // The order in which the named inputs are joined: the first, and then
+  // each of the rest once a condition relates it to an input already
+  // joined, with the keys on which it joins them
+  pub fn order(&self, names: &[&str]) -> Result<Vec<JoinStep>, error::Error> {
+    let mut joined: Vec<&str> = names.iter().take(1).copied().collect();
+    let mut rest: Vec<&str> = names.iter().skip(1).copied().collect();
+    let mut order: Vec<JoinStep> = Vec::new();
+    while !rest.is_empty() {
+      match rest.iter().enumerate().find_map(|(i, e)| { self.keys(&joined, e).map(|k| { (i, k) }) }) {
+        Some((index, (left_on, right_on))) => {
+          let name = rest.remove(index);
+          joined.push(name);
+          order.push((name.to_owned(), left_on, right_on));
+        },
+        None => return Err(error::QueryError::new(&format!("No join expression relates {} to {}", rest.join(", "), joined.join(", "))).into()),
       }
     }
-    None
+    Ok(order)
   }
 }
 
 impl fmt::Display for Join {
   fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
-    write!(f, "<{:?}>", &self.on)
+    write!(f, "<{}>", self.chains.iter().map(|e| { e.iter().map(|e| { e.to_string() }).collect::<Vec<String>>().join("=") }).collect::<Vec<String>>().join(", "))
   }
 }
 
 impl fmt::Debug for Join {
   fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
-    write!(f, "<{:?}>", &self.on)
+    write!(f, "{}", self)
+  }
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+  
+  fn order(join: &str, names: &[&str]) -> Vec<String> {
+    Join::parse(join).unwrap().order(names).unwrap().iter().map(|(name, left_on, right_on)| {
+      format!("{} on ({}) = ({})", name, left_on.iter().map(|e| { e.to_string() }).collect::<Vec<String>>().join(", "), right_on.iter().map(|e| { e.to_string() }).collect::<Vec<String>>().join(", "))
+    }).collect()
+  }
+  
+  fn resolved(text: &str) -> Result<Vec<String>, error::Error> {
+    let schema = schema::Schema::new("a", ["id", "name", "secret"].into_iter()).join(&schema::Schema::new("b", ["id", "amt_x", "amt_y", "note"].into_iter()));
+    Ok(resolve(&schema, &parser::parse_selection(text)?)?.iter().map(|e| { e.to_string() }).collect())
+  }
+  
+  #[test]
+  fn resolve_wildcards() {
+    assert_eq!(resolved("*").unwrap(), vec!["a.id", "a.name", "a.secret", "b.id", "b.amt_x", "b.amt_y", "b.note"]);
+    assert_eq!(resolved("a.*").unwrap(), vec!["a.id", "a.name", "a.secret"]);
+    assert_eq!(resolved("b.*, a.id").unwrap(), vec!["b.id", "b.amt_x", "b.amt_y", "b.note", "a.id"]);
+    assert_eq!(resolved("a.id, b.* except (b.note), a.name as n").unwrap(), vec!["a.id", "b.id", "b.amt_x", "b.amt_y", "a.name AS n"]);
+  }
+  
+  #[test]
+  fn resolve_patterns() {
+    assert_eq!(resolved("b./^amt_/").unwrap(), vec!["b.amt_x", "b.amt_y"]);
+    assert_eq!(resolved("/^amt_|^note$/").unwrap(), vec!["b.amt_x", "b.amt_y", "b.note"]);
+    assert_eq!(resolved("/_y$/, /_x$/").unwrap(), vec!["b.amt_y", "b.amt_x"]);
+    assert_eq!(resolved("a./^amt_/").unwrap(), Vec::<String>::new());
+    assert!(resolved("/[/").unwrap_err().to_string().starts_with("Invalid column pattern: /[/:"));
+    assert!(resolved("c.*").unwrap_err().to_string().starts_with("No columns match: c.*"));
+    assert!(resolved("c./x/").unwrap_err().to_string().starts_with("No columns match: c./x/"));
+  }
+  
+  #[test]
+  fn resolve_except() {
+    assert_eq!(resolved("* except (a.secret, b.note)").unwrap(), vec!["a.id", "a.name", "b.id", "b.amt_x", "b.amt_y"]);
+    // an unqualified name excludes the column from every input
+    assert_eq!(resolved("* except (id)").unwrap(), vec!["a.name", "a.secret", "b.amt_x", "b.amt_y", "b.note"]);
+    assert_eq!(resolved("* except (/^amt_/, a./^s/)").unwrap(), vec!["a.id", "a.name", "b.id", "b.note"]);
+    assert_eq!(resolved("a.* except (b.id)").unwrap(), vec!["a.id", "a.name", "a.secret"]);
+    assert!(resolved("* except (a.nope)").unwrap_err().to_string().starts_with("Excluded column not found: a.nope"));
+  }
+  
+  #[test]
+  fn parse_names() {
+    let schema = schema::Schema::new("h", ["order-id", "name"].into_iter());
+    let parse = |text: &str| -> Result<Vec<String>, error::Error> {
+      Ok(super::parse(&schema, &[text.to_owned()])?.iter().map(|e| { e.to_string() }).collect())
+    };
+    // names which are not valid in expressions select existing columns
+    assert_eq!(parse("h.order-id").unwrap(), vec!["h.order-id"]);
+    assert_eq!(parse("h.name, h.order-id").unwrap(), vec!["h.name", "h.order-id"]);
+    assert_eq!(parse(" order-id ").unwrap(), vec!["order-id"]);
+    // commas within expressions are not separators
+    assert_eq!(parse("concat(h.name, h.\"order-id\") as c, h.order-id").unwrap(), vec!["concat(h.name, h.order-id) AS c", "h.order-id"]);
+    assert_eq!(parse("* except (h.name, h.\"order-id\")").unwrap(), vec!["* EXCEPT (h.name, h.order-id)"]);
+    assert!(parse("h.order-id, upper(").is_err());
+  }
+  
+  #[test]
+  fn join_order() {
+    assert_eq!(order("a.id=b.a_id, b.c_id=c.id", &["a", "b", "c"]), vec!["b on (a.id) = (b.a_id)", "c on (b.c_id) = (c.id)"]);
+    // c is joined only once b, which relates it to a, has been
+    assert_eq!(order("a.id=b.a_id, b.c_id=c.id", &["a", "c", "b"]), vec!["b on (a.id) = (b.a_id)", "c on (b.c_id) = (c.id)"]);
+    // the most recently joined input is preferred
+    assert_eq!(order("a.id=b.id=c.id", &["a", "b", "c"]), vec!["b on (a.id) = (b.id)", "c on (b.id) = (c.id)"]);
+    assert_eq!(order("a.(x,y)=b.(x,y), b.z=c.z", &["a", "b", "c"]), vec!["b on (a.x, a.y) = (b.x, b.y)", "c on (b.z) = (c.z)"]);
+    assert_eq!(order("a.id=b.id", &["a"]), Vec::<String>::new());
+  }
+  
+  #[test]
+  fn join_order_unrelated() {
+    let join = Join::parse("a.id=b.id, c.id=d.id").unwrap();
+    assert_eq!(join.order(&["a", "b", "c", "d"]).unwrap_err().to_string(), "No join expression relates c, d to a, b");
+    assert_eq!(join.order(&["c", "a", "d", "b"]).unwrap_err().to_string(), "No join expression relates a, b to c, d");
   }
 }
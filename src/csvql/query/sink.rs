@@ -0,0 +1,225 @@
+use std::io;
+use std::io::Write;
+
+use csv;
+
+use crate::csvql::query::value;
+use crate::csvql::query::error;
+
+// The format output is written in
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Format {
+  Csv,
+  Json, // a single array of objects
+  Ndjson, // one object per line
+}
+
+impl Format {
+  pub fn parse(text: &str) -> Result<Format, error::Error> {
+    match text.trim().to_lowercase().as_ref() {
+      "csv" => Ok(Self::Csv),
+      "json" => Ok(Self::Json),
+      "ndjson" | "jsonl" => Ok(Self::Ndjson),
+      _ => Err(error::ParseError::new(&format!("Invalid output format, expected csv, json or ndjson: {}", text)).into()),
+    }
+  }
+}
+
+// A destination for the rows of a frame
+pub trait Sink {
+  fn write(&mut self, row: &csv::StringRecord) -> Result<(), error::Error>;
+  fn finish(&mut self) -> Result<(), error::Error>;
+}
+
+// A sink which writes CSV records, with a header if one is provided, and
+// optionally preceded by a UTF-8 byte order mark
+pub struct Csv<W: io::Write> {
+  data: csv::Writer<W>,
+}
+
+#[allow(clippy::redundant_field_names)]
+impl<W: io::Write> Csv<W> {
+  pub fn new(mut out: W, builder: &csv::WriterBuilder, header: Option<&[String]>, bom: bool) -> Result<Csv<W>, error::Error> {
+    if bom {
+      out.write_all("\u{feff}".as_bytes())?;
+    }
+    let mut data = builder.from_writer(out);
+    if let Some(header) = header {
+      data.write_record(header)?;
+    }
+    Ok(Csv{
+      data: data,
+    })
+  }
+}
+
+impl<W: io::Write> Sink for Csv<W> {
+  fn write(&mut self, row: &csv::StringRecord) -> Result<(), error::Error> {
+    self.data.write_record(row)?;
+    Ok(())
+  }
+  
+  fn finish(&mut self) -> Result<(), error::Error> {
+    self.data.flush()?;
+    Ok(())
+  }
+}
+
+// A sink which writes each row as a JSON object keyed by the names of
+// its columns, either in a single array or one per line. Fields are
+// typed according to their columns: empty fields in typed columns are
+// null, while in string columns they are empty strings. Rows do not
+// distinguish a missing value from an empty one, so this applies to the
+// fields an outer join pads unmatched rows with too; declare the type of
+// such a column for them to be null.
+pub struct Json<W: io::Write> {
+  data: io::BufWriter<W>,
+  keys: Vec<String>, // JSON encoded
+  types: Vec<value::Type>,
+  array: bool,
+  count: usize,
+}
+
+#[allow(clippy::redundant_field_names)]
+impl<W: io::Write> Json<W> {
+  pub fn new(data: W, names: &[String], types: &[value::Type], array: bool) -> Json<W> {
+    Json{
+      data: io::BufWriter::new(data),
+      keys: names.iter().map(|e| { quote(e) }).collect(),
+      types: types.to_vec(),
+      array: array,
+      count: 0,
+    }
+  }
+  
+  fn encode(typ: value::Type, text: &str) -> Result<String, error::Error> {
+    if typ == value::Type::String {
+      return Ok(quote(text));
+    }
+    let enc = match typ.value(text)? {
+      value::Value::Null => "null".to_owned(),
+      value::Value::Integer(v) => v.to_string(),
+      value::Value::Float(v) if v.is_finite() => v.to_string(),
+      value::Value::Float(_) => "null".to_owned(), // JSON has no infinities or NaN
+      value::Value::Decimal(v) => v.to_string(), // written exactly, rather than as a float
+      value::Value::Boolean(v) => v.to_string(),
+      v => quote(&v.to_string()),
+    };
+    Ok(enc)
+  }
+}
+
+impl<W: io::Write> Sink for Json<W> {
+  fn write(&mut self, row: &csv::StringRecord) -> Result<(), error::Error> {
+    let mut fields: Vec<String> = Vec::new();
+    for (i, (key, typ)) in self.keys.iter().zip(&self.types).enumerate() {
+      let field = row.get(i).unwrap_or_default();
+      let enc = match Self::encode(*typ, field) {
+        Ok(enc) => enc,
+        Err(err) => return Err(error::FrameError::new(&format!("Column {}: {}", key, err)).into()),
+      };
+      fields.push(format!("{}:{}", key, enc));
+    }
+    
+    let sep = match (self.array, self.count) {
+      (true, 0) => "[\n",
+      (true, _) => ",\n",
+      (false, _) => "",
+    };
+    write!(self.data, "{}{{{}}}", sep, fields.join(","))?;
+    if !self.array {
+      writeln!(self.data)?;
+    }
+    self.count += 1;
+    Ok(())
+  }
+  
+  fn finish(&mut self) -> Result<(), error::Error> {
+    if self.array {
+      writeln!(self.data, "{}]", if self.count == 0 { "[" } else { "\n" })?;
+    }
+    self.data.flush()?;
+    Ok(())
+  }
+}
+
+// Encode a string as JSON
+fn quote(text: &str) -> String {
+  serde_json::Value::from(text).to_string()
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+  
+  use crate::csvql::query::dialect;
+  
+  fn write_csv(settings: &str, quoting: dialect::Quoting, header: bool, bom: bool) -> String {
+    let dialect = dialect::Dialect::default().with_settings(settings).unwrap();
+    let names = ["id".to_owned(), "name".to_owned(), "note".to_owned()];
+    let mut dst = Csv::new(Vec::new(), &dialect.writer(quoting), if header { Some(&names[..]) } else { None }, bom).unwrap();
+    dst.write(&csv::StringRecord::from(vec!["1", "a,b", "say \"hi\""])).unwrap();
+    dst.write(&csv::StringRecord::from(vec!["2.5", "", "x"])).unwrap();
+    dst.finish().unwrap();
+    String::from_utf8(dst.data.into_inner().unwrap()).unwrap()
+  }
+  
+  fn write_json(array: bool, rows: &[&[&str]]) -> String {
+    let names = ["a.id".to_owned(), "a.name".to_owned(), "b.total".to_owned(), "b.note".to_owned()];
+    let types = [value::Type::Integer, value::Type::String, value::Type::Decimal, value::Type::String];
+    let mut dst = Json::new(Vec::new(), &names, &types, array);
+    for row in rows {
+      dst.write(&csv::StringRecord::from(row.to_vec())).unwrap();
+    }
+    dst.finish().unwrap();
+    String::from_utf8(dst.data.into_inner().unwrap()).unwrap()
+  }
+  
+  #[test]
+  fn json_types() {
+    let out = write_json(false, &[&["1", "x \"y\"", "2.50", "n"], &["", "", "", ""]]);
+    assert_eq!(out, "{\"a.id\":1,\"a.name\":\"x \\\"y\\\"\",\"b.total\":2.50,\"b.note\":\"n\"}\n{\"a.id\":null,\"a.name\":\"\",\"b.total\":null,\"b.note\":\"\"}\n");
+  }
+  
+  #[test]
+  fn json_array() {
+    assert_eq!(write_json(true, &[]), "[]\n");
+    assert_eq!(write_json(true, &[&["1", "a", "", ""], &["2", "b", "3", ""]]), "[\n{\"a.id\":1,\"a.name\":\"a\",\"b.total\":null,\"b.note\":\"\"},\n{\"a.id\":2,\"a.name\":\"b\",\"b.total\":3,\"b.note\":\"\"}\n]\n");
+  }
+  
+  #[test]
+  fn json_invalid() {
+    let names = ["a.id".to_owned()];
+    let mut dst = Json::new(Vec::new(), &names, &[value::Type::Integer], false);
+    let err = dst.write(&csv::StringRecord::from(vec!["x"])).unwrap_err();
+    assert!(err.to_string().starts_with("Column \"a.id\": "));
+  }
+  
+  #[test]
+  fn csv_quoting() {
+    assert_eq!(write_csv("", dialect::Quoting::Necessary, false, false), "1,\"a,b\",\"say \"\"hi\"\"\"\n2.5,,x\n");
+    assert_eq!(write_csv("", dialect::Quoting::Always, false, false), "\"1\",\"a,b\",\"say \"\"hi\"\"\"\n\"2.5\",\"\",\"x\"\n");
+    assert_eq!(write_csv("", dialect::Quoting::NonNumeric, false, false), "1,\"a,b\",\"say \"\"hi\"\"\"\n2.5,\"\",\"x\"\n");
+    assert_eq!(write_csv("", dialect::Quoting::Never, false, false), "1,a,b,say \"hi\"\n2.5,,x\n");
+    // quoting is never applied if the dialect disables it
+    assert_eq!(write_csv("quote=none", dialect::Quoting::Always, false, false), "1,a,b,say \"hi\"\n2.5,,x\n");
+    assert_eq!(write_csv("escape=backslash", dialect::Quoting::Necessary, false, false), "1,\"a,b\",\"say \\\"hi\\\"\"\n2.5,,x\n");
+    assert_eq!(write_csv("delim=;:quote='", dialect::Quoting::Necessary, false, false), "1;a,b;say \"hi\"\n2.5;;x\n");
+  }
+  
+  #[test]
+  fn csv_terminators() {
+    assert_eq!(write_csv("delim=tab:terminator=crlf", dialect::Quoting::Necessary, false, false), "1\ta,b\t\"say \"\"hi\"\"\"\r\n2.5\t\tx\r\n");
+    assert_eq!(write_csv("terminator=|", dialect::Quoting::Never, true, false), "id,name,note|1,a,b,say \"hi\"|2.5,,x|");
+    assert_eq!(write_csv("terminator=any", dialect::Quoting::Necessary, true, false), "id,name,note\n1,\"a,b\",\"say \"\"hi\"\"\"\n2.5,,x\n");
+  }
+  
+  #[test]
+  fn csv_bom() {
+    let out = write_csv("", dialect::Quoting::Necessary, true, true);
+    assert!(out.starts_with("\u{feff}id,name,note\n1,"));
+    assert_eq!(out.matches('\u{feff}').count(), 1);
+    assert!(write_csv("", dialect::Quoting::Necessary, false, true).starts_with("\u{feff}1,"));
+    assert!(write_csv("", dialect::Quoting::Necessary, true, false).starts_with("id,"));
+  }
+}
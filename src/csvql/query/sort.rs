@@ -0,0 +1,174 @@
+use std::fs;
+use std::io;
+use std::cmp;
+use std::path;
+use std::process;
+use std::collections::BinaryHeap;
+use std::sync::atomic;
+
+use csv;
+
+use crate::csvql::query::ast;
+use crate::csvql::query::schema;
+use crate::csvql::query::value;
+use crate::csvql::query::error;
+
+static RUN_COUNTER: atomic::AtomicUsize = atomic::AtomicUsize::new(0);
+
+// Options that control how frames are sorted
+#[derive(Debug, Clone)]
+pub struct SortOptions {
+  pub memory: Option<usize>, // the approximate number of bytes of rows to hold in memory before spilling to disk
+  pub temp_dir: path::PathBuf, // where sorted runs are spilled
+}
+
+impl Default for SortOptions {
+  fn default() -> SortOptions {
+    SortOptions{
+      memory: None,
+      temp_dir: std::env::temp_dir(),
+    }
+  }
+}
+
+// Estimate the number of bytes of memory a sorted row occupies
+pub fn footprint(row: &csv::StringRecord, on: &[value::Value]) -> usize {
+  row.as_slice().len() + row.len() * 16 + on.len() * 48 + 64
+}
+
+// Extract the sort key of a row, applying normalizers to each field
+pub fn key(schema: &schema::Schema, indexes: &[usize], norm: &[value::Normalizer], row: &csv::StringRecord) -> Result<Vec<value::Value>, error::Error> {
+  let mut on: Vec<value::Value> = Vec::new();
+  for index in indexes {
+    on.push(match row.get(*index) {
+      Some(field) => schema.normalized_value(*index, norm, field)?,
+      None => return Err(error::FrameError::new(&format!("Index column not found: {}", index)).into()),
+    });
+  }
+  Ok(on)
+}
+
+// A sorted run of rows spilled to a temporary file, which is removed
+// when the run is dropped
+#[derive(Debug)]
+pub struct Run {
+  path: path::PathBuf,
+}
+
+#[allow(clippy::redundant_field_names)]
+impl Run {
+  pub fn write<'a>(dir: &path::Path, rows: impl Iterator<Item=&'a csv::StringRecord>) -> Result<Run, error::Error> {
+    // The file is created only if it does not already exist, so neither
+    // another process nor a link planted in a shared directory is written
+    // through; names which are taken are skipped
+    let (run, file) = loop {
+      let n = RUN_COUNTER.fetch_add(1, atomic::Ordering::SeqCst);
+      let path = dir.join(format!("csvql-sort-{}-{}.csv", process::id(), n));
+      match fs::OpenOptions::new().write(true).create_new(true).open(&path) {
+        Ok(file) => break (Run{ path: path }, file),
+        Err(err) if err.kind() == io::ErrorKind::AlreadyExists => continue,
+        Err(err) => return Err(err.into()),
+      }
+    };
+    let mut dst = csv::WriterBuilder::new().has_headers(false).from_writer(file);
+    for row in rows {
+      dst.write_record(row)?;
+    }
+    dst.flush()?;
+    Ok(run)
+  }
+  
+  fn reader(&self) -> Result<csv::Reader<fs::File>, error::Error> {
+    Ok(csv::ReaderBuilder::new().has_headers(false).from_path(&self.path)?)
+  }
+}
+
+impl Drop for Run {
+  fn drop(&mut self) {
+    let _ = fs::remove_file(&self.path);
+  }
+}
+
+// The current row of a run being merged
+struct Head<'a> {
+  order: &'a [ast::Order],
+  on: Vec<value::Value>,
+  run: usize,
+  data: csv::StringRecord,
+}
+
+impl<'a> Ord for Head<'a> {
+  fn cmp(&self, other: &Self) -> cmp::Ordering {
+    // reversed, so the binary heap yields the least row first; ties are
+    // broken by run, which preserves the source order of equal rows
+    ast::Order::compare_all(self.order, &self.on, &other.on).then(self.run.cmp(&other.run)).reverse()
+  }
+}
+
+impl<'a> PartialOrd for Head<'a> {
+  fn partial_cmp(&self, other: &Self) -> Option<cmp::Ordering> {
+    Some(self.cmp(other))
+  }
+}
+
+impl<'a> PartialEq for Head<'a> {
+  fn eq(&self, other: &Self) -> bool {
+    self.cmp(other) == cmp::Ordering::Equal
+  }
+}
+
+impl<'a> Eq for Head<'a> {}
+
+// A k-way merge of sorted runs, which reads lazily from each run
+pub struct Merge<'a> {
+  schema: &'a schema::Schema,
+  indexes: &'a [usize],
+  norm: &'a [value::Normalizer],
+  order: &'a [ast::Order],
+  readers: Vec<csv::StringRecordsIntoIter<fs::File>>,
+  heap: BinaryHeap<Head<'a>>,
+}
+
+#[allow(clippy::redundant_field_names)]
+impl<'a> Merge<'a> {
+  pub fn new(schema: &'a schema::Schema, indexes: &'a [usize], norm: &'a [value::Normalizer], order: &'a [ast::Order], runs: &[Run]) -> Result<Merge<'a>, error::Error> {
+    let mut merge = Merge{
+      schema: schema,
+      indexes: indexes,
+      norm: norm,
+      order: order,
+      readers: Vec::new(),
+      heap: BinaryHeap::new(),
+    };
+    for (i, run) in runs.iter().enumerate() {
+      merge.readers.push(run.reader()?.into_records());
+      merge.advance(i)?;
+    }
+    Ok(merge)
+  }
+  
+  fn advance(&mut self, run: usize) -> Result<(), error::Error> {
+    if let Some(row) = self.readers[run].next() {
+      let row = row?;
+      self.heap.push(Head{
+        order: self.order,
+        on: key(self.schema, self.indexes, self.norm, &row)?,
+        run: run,
+        data: row,
+      });
+    }
+    Ok(())
+  }
+}
+
+impl<'a> Iterator for Merge<'a> {
+  type Item = Result<csv::StringRecord, error::Error>;
+  
+  fn next(&mut self) -> Option<Self::Item> {
+    let head = self.heap.pop()?;
+    if let Err(err) = self.advance(head.run) {
+      return Some(Err(err));
+    }
+    Some(Ok(head.data))
+  }
+}
@@ -0,0 +1,729 @@
+use std::fmt;
+use std::cmp;
+use std::iter;
+use std::hash;
+
+use chrono;
+use unicode_normalization::UnicodeNormalization;
+
+use crate::csvql::query::error;
+
+const DATE_FORMAT: &str = "%Y-%m-%d";
+const TIMESTAMP_FORMATS: &[&str] = &["%Y-%m-%dT%H:%M:%S%.f", "%Y-%m-%d %H:%M:%S%.f"];
+
+// The type of a column
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum Type {
+  String,
+  Integer,
+  Float,
+  Decimal,
+  Boolean,
+  Date,
+  Timestamp,
+}
+
+impl Type {
+  pub fn parse(text: &str) -> Result<Type, error::Error> {
+    match text.trim().to_lowercase().as_ref() {
+      "string" | "text" => Ok(Self::String),
+      "integer" | "int" => Ok(Self::Integer),
+      "float" | "double" => Ok(Self::Float),
+      "decimal" | "numeric" => Ok(Self::Decimal),
+      "boolean" | "bool" => Ok(Self::Boolean),
+      "date" => Ok(Self::Date),
+      "timestamp" | "datetime" => Ok(Self::Timestamp),
+      _ => Err(error::ParseError::new(&format!("Invalid type: {}", text)).into()),
+    }
+  }
+  
+  // Infer the narrowest type which can represent every one of the
+  // provided fields. Empty fields are null and are compatible with
+  // every type; if no field has a value the column is a string.
+  pub fn infer<'a>(fields: impl iter::Iterator<Item=&'a str>) -> Type {
+    const CANDIDATES: &[Type] = &[Type::Integer, Type::Float, Type::Boolean, Type::Date, Type::Timestamp];
+    let mut viable: Vec<Type> = CANDIDATES.to_vec();
+    let mut any = false;
+    for field in fields {
+      if field.is_empty() {
+        continue;
+      }
+      any = true;
+      viable.retain(|e| { e.infers(field) });
+      if viable.is_empty() {
+        break;
+      }
+    }
+    match viable.first() {
+      Some(typ) if any => *typ,
+      _ => Type::String,
+    }
+  }
+  
+  // Determine whether values of this type compare with those of another
+  // by their value: numbers compare with each other, as do dates and
+  // timestamps, but otherwise only values of the same type compare.
+  pub fn compares_with(&self, other: &Type) -> bool {
+    let numeric = |e: &Type| { matches!(e, Self::Integer | Self::Float | Self::Decimal) };
+    let temporal = |e: &Type| { matches!(e, Self::Date | Self::Timestamp) };
+    self == other || (numeric(self) && numeric(other)) || (temporal(self) && temporal(other))
+  }
+  
+  // Determine whether a field is evidence of this type when inferring.
+  // Floats are parsed leniently, so words like nan and infinity, which
+  // are far more likely to be text, are not taken as floats.
+  fn infers(&self, field: &str) -> bool {
+    match (self, self.value(field)) {
+      (Self::Float, Ok(Value::Float(v))) => v.is_finite() && !field.chars().any(|e| { e.is_alphabetic() && e != 'e' && e != 'E' }),
+      (_, res) => res.is_ok(),
+    }
+  }
+  
+  // Convert the textual representation of a field to a value of this
+  // type. Empty fields are null regardless of type.
+  pub fn value(&self, text: &str) -> Result<Value, error::Error> {
+    if text.is_empty() {
+      return Ok(Value::Null);
+    }
+    let val = match self {
+      Self::String => Some(Value::String(text.to_owned())),
+      Self::Integer => text.trim().parse::<i64>().ok().map(Value::Integer),
+      Self::Float => text.trim().parse::<f64>().ok().map(Value::Float),
+      Self::Decimal => Decimal::parse(text.trim()).map(Value::Decimal),
+      Self::Boolean => parse_bool(text.trim()).map(Value::Boolean),
+      Self::Date => chrono::NaiveDate::parse_from_str(text.trim(), DATE_FORMAT).ok().map(Value::Date),
+      Self::Timestamp => parse_timestamp(text.trim()).map(Value::Timestamp),
+    };
+    match val {
+      Some(val) => Ok(val),
+      None => Err(error::ParseError::new(&format!("Invalid {} value: {:?}", self, text)).into()),
+    }
+  }
+}
+
+impl fmt::Display for Type {
+  fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+    match self {
+      Self::String => write!(f, "string"),
+      Self::Integer => write!(f, "integer"),
+      Self::Float => write!(f, "float"),
+      Self::Decimal => write!(f, "decimal"),
+      Self::Boolean => write!(f, "boolean"),
+      Self::Date => write!(f, "date"),
+      Self::Timestamp => write!(f, "timestamp"),
+    }
+  }
+}
+
+fn parse_bool(text: &str) -> Option<bool> {
+  match text.to_lowercase().as_ref() {
+    "true" | "t" | "yes" | "y" | "1" => Some(true),
+    "false" | "f" | "no" | "n" | "0" => Some(false),
+    _ => None,
+  }
+}
+
+fn parse_timestamp(text: &str) -> Option<chrono::NaiveDateTime> {
+  if let Ok(v) = chrono::DateTime::parse_from_rfc3339(text) {
+    return Some(v.naive_utc());
+  }
+  for f in TIMESTAMP_FORMATS {
+    if let Ok(v) = chrono::NaiveDateTime::parse_from_str(text, f) {
+      return Some(v);
+    }
+  }
+  match chrono::NaiveDate::parse_from_str(text, DATE_FORMAT) {
+    Ok(v) => Some(v.and_time(chrono::NaiveTime::MIN)),
+    Err(_) => None,
+  }
+}
+
+// A fixed-point decimal number
+#[derive(Debug, Clone, Copy)]
+pub struct Decimal {
+  mantissa: i128,
+  scale: u32,
+}
+
+#[allow(clippy::redundant_field_names)]
+impl Decimal {
+  pub fn parse(text: &str) -> Option<Decimal> {
+    let (int, frac) = match text.split_once('.') {
+      Some((int, frac)) => (int, frac),
+      None => (text, ""),
+    };
+    if int.trim_start_matches(['-', '+']).is_empty() && frac.is_empty() {
+      return None;
+    }
+    if !frac.chars().all(|e| { e.is_ascii_digit() }) {
+      return None;
+    }
+    let mantissa = format!("{}{}", int, frac).parse::<i128>().ok()?;
+    Some(Decimal{
+      mantissa: mantissa,
+      scale: frac.len() as u32,
+    })
+  }
+  
+  pub fn to_f64(self) -> f64 {
+    self.mantissa as f64 / 10f64.powi(self.scale as i32)
+  }
+  
+  fn rescale(&self, scale: u32) -> Option<i128> {
+    self.mantissa.checked_mul(10i128.checked_pow(scale - self.scale)?)
+  }
+  
+  pub fn checked_add(&self, other: &Decimal) -> Option<Decimal> {
+    let scale = cmp::max(self.scale, other.scale);
+    Some(Decimal{
+      mantissa: self.rescale(scale)?.checked_add(other.rescale(scale)?)?,
+      scale: scale,
+    })
+  }
+  
+  pub fn checked_mul(&self, other: &Decimal) -> Option<Decimal> {
+    Some(Decimal{
+      mantissa: self.mantissa.checked_mul(other.mantissa)?,
+      scale: self.scale.checked_add(other.scale)?,
+    })
+  }
+  
+  pub fn checked_neg(&self) -> Option<Decimal> {
+    Some(Decimal{
+      mantissa: self.mantissa.checked_neg()?,
+      scale: self.scale,
+    })
+  }
+  
+  pub fn checked_abs(&self) -> Option<Decimal> {
+    Some(Decimal{
+      mantissa: self.mantissa.checked_abs()?,
+      scale: self.scale,
+    })
+  }
+  
+  pub fn from_integer(v: i64) -> Decimal {
+    Decimal{
+      mantissa: v as i128,
+      scale: 0,
+    }
+  }
+  
+  // The integer this decimal represents, if it has no fractional digits
+  // and is in range
+  pub fn to_integer(self) -> Option<i64> {
+    match self.scale {
+      0 => i64::try_from(self.mantissa).ok(),
+      _ => None,
+    }
+  }
+  
+  // Round to the specified number of fractional digits, with halves
+  // rounded away from zero; a negative number of digits rounds to tens,
+  // hundreds and so on
+  pub fn round(&self, digits: i64) -> Decimal {
+    let scale = digits.clamp(0, u32::MAX as i64) as u32;
+    let drop = self.scale as i64 - digits;
+    if drop <= 0 {
+      return *self;
+    }
+    let factor = match u32::try_from(drop).ok().and_then(|e| { 10i128.checked_pow(e) }) {
+      Some(factor) => factor,
+      None => return Decimal{mantissa: 0, scale: scale},
+    };
+    let (quot, rem) = (self.mantissa / factor, self.mantissa % factor);
+    let quot = if rem.unsigned_abs() * 2 >= factor.unsigned_abs() { quot + self.mantissa.signum() } else { quot };
+    // digits dropped from the integer part are restored as zeros
+    let shift = 10i128.checked_pow((-digits).clamp(0, u32::MAX as i64) as u32);
+    Decimal{
+      mantissa: shift.and_then(|e| { quot.checked_mul(e) }).unwrap_or(0),
+      scale: scale,
+    }
+  }
+}
+
+impl Ord for Decimal {
+  fn cmp(&self, other: &Self) -> cmp::Ordering {
+    let scale = cmp::max(self.scale, other.scale);
+    match (self.rescale(scale), other.rescale(scale)) {
+      (Some(a), Some(b)) => a.cmp(&b),
+      _ => self.to_f64().total_cmp(&other.to_f64()),
+    }
+  }
+}
+
+impl PartialOrd for Decimal {
+  fn partial_cmp(&self, other: &Self) -> Option<cmp::Ordering> {
+    Some(self.cmp(other))
+  }
+}
+
+impl PartialEq for Decimal {
+  fn eq(&self, other: &Self) -> bool {
+    self.cmp(other) == cmp::Ordering::Equal
+  }
+}
+
+impl Eq for Decimal {}
+
+impl fmt::Display for Decimal {
+  fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+    if self.scale == 0 {
+      return write!(f, "{}", self.mantissa);
+    }
+    let digits = format!("{:0width$}", self.mantissa.unsigned_abs(), width = self.scale as usize + 1);
+    let (int, frac) = digits.split_at(digits.len() - self.scale as usize);
+    write!(f, "{}{}.{}", if self.mantissa < 0 { "-" } else { "" }, int, frac)
+  }
+}
+
+// A typed value
+#[derive(Debug, Clone)]
+pub enum Value {
+  Null,
+  Integer(i64),
+  Float(f64),
+  Decimal(Decimal),
+  Boolean(bool),
+  Date(chrono::NaiveDate),
+  Timestamp(chrono::NaiveDateTime),
+  String(String),
+}
+
+impl Value {
+  // Infer the value of a literal that is not associated with a column
+  pub fn infer(text: &str) -> Value {
+    if let Ok(v) = text.parse::<i64>() {
+      Value::Integer(v)
+    }else if let Some(v) = Decimal::parse(text) {
+      Value::Decimal(v)
+    }else{
+      Value::String(text.to_owned())
+    }
+  }
+  
+  pub fn is_null(&self) -> bool {
+    matches!(self, Self::Null)
+  }
+  
+  // The order of types relative to each other; numeric types are
+  // compared by value, so they share a rank.
+  fn rank(&self) -> u8 {
+    match self {
+      Self::Null => 0,
+      Self::Boolean(_) => 1,
+      Self::Integer(_) | Self::Float(_) | Self::Decimal(_) => 2,
+      Self::Date(_) | Self::Timestamp(_) => 3,
+      Self::String(_) => 4,
+    }
+  }
+  
+  pub fn to_f64(&self) -> Option<f64> {
+    match self {
+      Self::Integer(v) => Some(*v as f64),
+      Self::Float(v) => Some(*v),
+      Self::Decimal(v) => Some(v.to_f64()),
+      _ => None,
+    }
+  }
+  
+  // Interpret this value as a number, if possible. Strings are parsed,
+  // since columns which have not been typed are strings, but words like
+  // nan and inf are not numbers, as when inferring types.
+  pub fn numeric(&self) -> Option<Value> {
+    match self {
+      Self::Integer(_) | Self::Float(_) | Self::Decimal(_) => Some(self.clone()),
+      Self::String(v) => match Value::infer(v.trim()) {
+        Value::String(_) if Type::Float.infers(v.trim()) => v.trim().parse::<f64>().ok().map(Value::Float),
+        Value::String(_) => None,
+        v => Some(v),
+      },
+      _ => None,
+    }
+  }
+  
+  // Add two numeric values, promoting to the wider of their types
+  pub fn add(&self, other: &Value) -> Option<Value> {
+    match (self, other) {
+      (Self::Integer(a), Self::Integer(b)) => match a.checked_add(*b) {
+        Some(v) => Some(Value::Integer(v)),
+        None => Some(Value::Decimal(Decimal{mantissa: *a as i128, scale: 0}.checked_add(&Decimal{mantissa: *b as i128, scale: 0})?)),
+      },
+      (Self::Decimal(a), Self::Decimal(b)) => Some(Value::Decimal(a.checked_add(b)?)),
+      (Self::Integer(a), Self::Decimal(b)) | (Self::Decimal(b), Self::Integer(a)) => Some(Value::Decimal(Decimal{mantissa: *a as i128, scale: 0}.checked_add(b)?)),
+      (a, b) => Some(Value::Float(a.to_f64()? + b.to_f64()?)),
+    }
+  }
+  
+  // Negate a numeric value
+  pub fn neg(&self) -> Option<Value> {
+    match self {
+      Self::Integer(v) => match v.checked_neg() {
+        Some(v) => Some(Value::Integer(v)),
+        None => Some(Value::Decimal(Decimal{mantissa: *v as i128, scale: 0}.checked_neg()?)),
+      },
+      Self::Float(v) => Some(Value::Float(-v)),
+      Self::Decimal(v) => Some(Value::Decimal(v.checked_neg()?)),
+      _ => None,
+    }
+  }
+  
+  // Subtract a numeric value from another, promoting to the wider of their types
+  pub fn sub(&self, other: &Value) -> Option<Value> {
+    self.add(&other.neg()?)
+  }
+  
+  // Multiply two numeric values, promoting to the wider of their types
+  pub fn mul(&self, other: &Value) -> Option<Value> {
+    match (self, other) {
+      (Self::Integer(a), Self::Integer(b)) => match a.checked_mul(*b) {
+        Some(v) => Some(Value::Integer(v)),
+        None => Some(Value::Decimal(Decimal{mantissa: *a as i128, scale: 0}.checked_mul(&Decimal{mantissa: *b as i128, scale: 0})?)),
+      },
+      (Self::Decimal(a), Self::Decimal(b)) => Some(Value::Decimal(a.checked_mul(b)?)),
+      (Self::Integer(a), Self::Decimal(b)) | (Self::Decimal(b), Self::Integer(a)) => Some(Value::Decimal(Decimal{mantissa: *a as i128, scale: 0}.checked_mul(b)?)),
+      (a, b) => Some(Value::Float(a.to_f64()? * b.to_f64()?)),
+    }
+  }
+  
+  // Divide a numeric value by another, producing a float; division by
+  // zero produces null
+  pub fn div(&self, other: &Value) -> Option<Value> {
+    let (a, b) = (self.to_f64()?, other.to_f64()?);
+    if b == 0.0 {
+      Some(Value::Null)
+    }else{
+      Some(Value::Float(a / b))
+    }
+  }
+  
+  // The remainder of dividing a numeric value by another, which is an
+  // integer if both are; division by zero produces null
+  pub fn rem(&self, other: &Value) -> Option<Value> {
+    match (self, other) {
+      (Self::Integer(_), Self::Integer(0)) => Some(Value::Null),
+      (Self::Integer(a), Self::Integer(b)) => Some(Value::Integer(a.wrapping_rem(*b))),
+      (a, b) => {
+        let (a, b) = (a.to_f64()?, b.to_f64()?);
+        if b == 0.0 { Some(Value::Null) } else { Some(Value::Float(a % b)) }
+      },
+    }
+  }
+}
+
+impl Ord for Value {
+  fn cmp(&self, other: &Self) -> cmp::Ordering {
+    match (self, other) {
+      (Self::Null, Self::Null) => cmp::Ordering::Equal,
+      (Self::Integer(a), Self::Integer(b)) => a.cmp(b),
+      (Self::Decimal(a), Self::Decimal(b)) => a.cmp(b),
+      (Self::Integer(a), Self::Decimal(b)) => Decimal{mantissa: *a as i128, scale: 0}.cmp(b),
+      (Self::Decimal(a), Self::Integer(b)) => a.cmp(&Decimal{mantissa: *b as i128, scale: 0}),
+      (Self::Boolean(a), Self::Boolean(b)) => a.cmp(b),
+      (Self::Date(a), Self::Date(b)) => a.cmp(b),
+      (Self::Timestamp(a), Self::Timestamp(b)) => a.cmp(b),
+      (Self::Date(a), Self::Timestamp(b)) => a.and_time(chrono::NaiveTime::MIN).cmp(b),
+      (Self::Timestamp(a), Self::Date(b)) => a.cmp(&b.and_time(chrono::NaiveTime::MIN)),
+      (Self::String(a), Self::String(b)) => a.cmp(b),
+      (a, b) => match (a.to_f64(), b.to_f64()) {
+        (Some(a), Some(b)) => a.total_cmp(&b),
+        _ => a.rank().cmp(&b.rank()),
+      },
+    }
+  }
+}
+
+impl PartialOrd for Value {
+  fn partial_cmp(&self, other: &Self) -> Option<cmp::Ordering> {
+    Some(self.cmp(other))
+  }
+}
+
+impl PartialEq for Value {
+  fn eq(&self, other: &Self) -> bool {
+    self.cmp(other) == cmp::Ordering::Equal
+  }
+}
+
+impl Eq for Value {}
+
+impl hash::Hash for Value {
+  fn hash<H: hash::Hasher>(&self, state: &mut H) {
+    // values which compare equal must hash equally, so numeric values
+    // are hashed by their magnitude and dates by their timestamp
+    self.rank().hash(state);
+    match self {
+      Self::Null => {},
+      Self::Boolean(v) => v.hash(state),
+      Self::Integer(_) | Self::Float(_) | Self::Decimal(_) => if let Some(v) = self.to_f64() {
+        v.to_bits().hash(state);
+      },
+      Self::Date(v) => v.and_time(chrono::NaiveTime::MIN).hash(state),
+      Self::Timestamp(v) => v.hash(state),
+      Self::String(v) => v.hash(state),
+    }
+  }
+}
+
+impl fmt::Display for Value {
+  fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+    match self {
+      Self::Null => Ok(()),
+      Self::Integer(v) => write!(f, "{}", v),
+      Self::Float(v) => write!(f, "{}", v),
+      Self::Decimal(v) => write!(f, "{}", v),
+      Self::Boolean(v) => write!(f, "{}", v),
+      Self::Date(v) => write!(f, "{}", v.format(DATE_FORMAT)),
+      Self::Timestamp(v) => write!(f, "{}", v.format("%Y-%m-%dT%H:%M:%S%.f")),
+      Self::String(v) => write!(f, "{}", v),
+    }
+  }
+}
+
+// A normalization applied to a field before it is compared, so that keys
+// which differ only in form, such as in case or leading zeros, match
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Normalizer {
+  Lower,
+  Trim,
+  Zeros,
+  Number,
+  Nfc,
+}
+
+impl Normalizer {
+  pub fn parse(text: &str) -> Result<Normalizer, error::Error> {
+    match text.trim().to_lowercase().as_ref() {
+      "lower" | "lowercase" => Ok(Self::Lower),
+      "trim" => Ok(Self::Trim),
+      "zeros" | "strip-zeros" => Ok(Self::Zeros),
+      "number" | "numeric" => Ok(Self::Number),
+      "nfc" => Ok(Self::Nfc),
+      _ => Err(error::ParseError::new(&format!("Invalid normalizer, expected one of lower, trim, zeros, number or nfc: {}", text)).into()),
+    }
+  }
+  
+  // Parse a list of normalizers, as in: trim, lower
+  pub fn parse_list(text: &str) -> Result<Vec<Normalizer>, error::Error> {
+    text.split(',').map(Self::parse).collect()
+  }
+  
+  // Normalize the text of a field. Numeric normalization is applied to
+  // the typed value instead; see normalize.
+  fn text(&self, text: &str) -> String {
+    match self {
+      Self::Lower => text.to_lowercase(),
+      Self::Trim => text.trim().to_owned(),
+      Self::Zeros => {
+        // strip zeros which are followed by another digit after any sign,
+        // so 007 becomes 7 and -007 becomes -7 but 0 and 0.5 are unchanged;
+        // a plus sign is dropped, as is a minus sign on zero
+        let (negative, mut text) = match text.chars().next() {
+          Some(c) if (c == '-' || c == '+') && text[1..].starts_with(|c: char| { c.is_ascii_digit() }) => (c == '-', &text[1..]),
+          _ => (false, text),
+        };
+        while text.starts_with('0') && text[1..].starts_with(|c: char| { c.is_ascii_digit() }) {
+          text = &text[1..];
+        }
+        if negative && text != "0" {
+          format!("-{}", text)
+        }else{
+          text.to_owned()
+        }
+      },
+      Self::Nfc => text.nfc().collect(),
+      Self::Number => text.to_owned(),
+    }
+  }
+  
+  // Apply normalizers to a field and convert it to a value of the provided
+  // type. Text normalizers are applied in order; if numeric normalization
+  // is requested, the value is then converted to a number if possible.
+  pub fn normalize(norm: &[Normalizer], typ: Type, text: &str) -> Result<Value, error::Error> {
+    if norm.is_empty() {
+      return typ.value(text);
+    }
+    let mut text = text.to_owned();
+    for n in norm {
+      text = n.text(&text);
+    }
+    let val = typ.value(&text)?;
+    if norm.contains(&Self::Number) {
+      if let Some(num) = val.numeric() {
+        return Ok(num);
+      }
+    }
+    Ok(val)
+  }
+}
+
+impl fmt::Display for Normalizer {
+  fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+    match self {
+      Self::Lower => write!(f, "lower"),
+      Self::Trim => write!(f, "trim"),
+      Self::Zeros => write!(f, "zeros"),
+      Self::Number => write!(f, "number"),
+      Self::Nfc => write!(f, "nfc"),
+    }
+  }
+}
+
+// The greatest distance by which one value may exceed another, as for the
+// keys of an as-of join: either a number, or a duration for dates and
+// timestamps, as in: 30s, 5m, 2h, 1d, 1w
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Tolerance {
+  Number(f64),
+  Duration(chrono::Duration),
+}
+
+impl Tolerance {
+  pub fn parse(text: &str) -> Result<Tolerance, error::Error> {
+    let text = text.trim();
+    let split = text.len() - text.chars().last().filter(|e| { e.is_ascii_alphabetic() }).map_or(0, |e| { e.len_utf8() });
+    let tolerance = match (text[..split].parse::<i64>(), &text[split..]) {
+      (Ok(n), unit @ ("s" | "m" | "h" | "d" | "w")) => match Self::duration(n, unit) {
+        Some(d) => Some(Self::Duration(d)),
+        None => return Err(error::ParseError::new(&format!("Tolerance is out of range: {}", text)).into()),
+      },
+      (_, "") => text.parse::<f64>().ok().filter(|e| { e.is_finite() }).map(Self::Number),
+      _ => None,
+    };
+    match tolerance {
+      Some(Self::Number(n)) if n < 0.0 => Err(error::ParseError::new(&format!("Tolerance must not be negative: {}", text)).into()),
+      Some(Self::Duration(d)) if d < chrono::Duration::zero() => Err(error::ParseError::new(&format!("Tolerance must not be negative: {}", text)).into()),
+      Some(tolerance) => Ok(tolerance),
+      None => Err(error::ParseError::new(&format!("Invalid tolerance, expected a number or a duration in s, m, h, d or w: {}", text)).into()),
+    }
+  }
+  
+  fn duration(n: i64, unit: &str) -> Option<chrono::Duration> {
+    match unit {
+      "s" => chrono::Duration::try_seconds(n),
+      "m" => chrono::Duration::try_minutes(n),
+      "h" => chrono::Duration::try_hours(n),
+      "d" => chrono::Duration::try_days(n),
+      "w" => chrono::Duration::try_weeks(n),
+      _ => None,
+    }
+  }
+  
+  // Whether the distance by which the upper value exceeds the lower value
+  // is within this tolerance
+  pub fn within(&self, upper: &Value, lower: &Value) -> Result<bool, error::Error> {
+    let within = match self {
+      Self::Number(n) => match (upper.to_f64(), lower.to_f64()) {
+        (Some(u), Some(l)) => Some(u - l <= *n),
+        _ => None,
+      },
+      Self::Duration(d) => match (Self::timestamp(upper), Self::timestamp(lower)) {
+        (Some(u), Some(l)) => Some(u - l <= *d),
+        _ => None,
+      },
+    };
+    match within {
+      Some(within) => Ok(within),
+      None => Err(error::QueryError::new(&format!("Tolerance {} cannot be applied to values: {} and {}", self, upper, lower)).into()),
+    }
+  }
+  
+  fn timestamp(val: &Value) -> Option<chrono::NaiveDateTime> {
+    match val {
+      Value::Date(v) => Some(v.and_time(chrono::NaiveTime::MIN)),
+      Value::Timestamp(v) => Some(*v),
+      _ => None,
+    }
+  }
+}
+
+impl fmt::Display for Tolerance {
+  fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+    match self {
+      Self::Number(n) => write!(f, "{}", n),
+      Self::Duration(d) => write!(f, "{}s", d.num_seconds()),
+    }
+  }
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+  
+  fn infer(fields: &[&str]) -> Type {
+    Type::infer(fields.iter().copied())
+  }
+  
+  #[test]
+  fn infer_types() {
+    assert_eq!(infer(&["1", "-2", ""]), Type::Integer);
+    assert_eq!(infer(&["1", "2.5", "1e3"]), Type::Float);
+    assert_eq!(infer(&["true", "no", ""]), Type::Boolean);
+    assert_eq!(infer(&["2024-01-02", ""]), Type::Date);
+    assert_eq!(infer(&["2024-01-02T10:00:00Z", "2024-01-02 10:00:00"]), Type::Timestamp);
+    assert_eq!(infer(&["1", "x"]), Type::String);
+    assert_eq!(infer(&["", ""]), Type::String);
+    assert_eq!(infer(&[]), Type::String);
+  }
+  
+  #[test]
+  fn infer_rejects_float_words() {
+    assert_eq!(infer(&["nan"]), Type::String);
+    assert_eq!(infer(&["inf", "1.5"]), Type::String);
+    assert_eq!(infer(&["Infinity", "-infinity"]), Type::String);
+    assert_eq!(infer(&["1.5", "NaN"]), Type::String);
+    assert_eq!(infer(&["1e400"]), Type::String); // overflows to infinity
+  }
+  
+  #[test]
+  fn tolerance() {
+    assert_eq!(Tolerance::parse("2.5").unwrap(), Tolerance::Number(2.5));
+    assert_eq!(Tolerance::parse("90s").unwrap(), Tolerance::Duration(chrono::Duration::try_seconds(90).unwrap()));
+    assert_eq!(Tolerance::parse(" 2w ").unwrap(), Tolerance::Duration(chrono::Duration::try_days(14).unwrap()));
+    assert_eq!(Tolerance::parse("-1h").unwrap_err().to_string(), "Tolerance must not be negative: -1h");
+    assert_eq!(Tolerance::parse("99999999999999999w").unwrap_err().to_string(), "Tolerance is out of range: 99999999999999999w");
+    assert_eq!(Tolerance::parse("9223372036854775807s").unwrap_err().to_string(), "Tolerance is out of range: 9223372036854775807s");
+    assert!(Tolerance::parse("5y").is_err());
+    assert!(Tolerance::parse("inf").is_err());
+  }
+  
+  #[test]
+  fn numeric_text() {
+    let text = |e: &str| { Value::String(e.to_owned()).numeric() };
+    assert_eq!(text(" 42 "), Some(Value::Integer(42)));
+    assert_eq!(text("1.50"), Some(Value::Decimal(Decimal::parse("1.5").unwrap())));
+    assert_eq!(text("1e3"), Some(Value::Float(1000.0)));
+    assert_eq!(text("inf"), None);
+    assert_eq!(text("-Infinity"), None);
+    assert_eq!(text("NaN"), None);
+    assert_eq!(text("1e400"), None);
+    assert_eq!(text("x"), None);
+    assert_eq!(normalize("number", Type::String, "nan"), Value::String("nan".to_owned()));
+  }
+  
+  fn normalize(norm: &str, typ: Type, text: &str) -> Value {
+    Normalizer::normalize(&Normalizer::parse_list(norm).unwrap(), typ, text).unwrap()
+  }
+  
+  #[test]
+  fn normalizers() {
+    let text = |e: &str| { Value::String(e.to_owned()) };
+    assert_eq!(normalize("zeros", Type::String, "007"), text("7"));
+    assert_eq!(normalize("zeros", Type::String, "-007"), text("-7"));
+    assert_eq!(normalize("zeros", Type::String, "+007"), text("7"));
+    assert_eq!(normalize("zeros", Type::String, "-000"), text("0"));
+    assert_eq!(normalize("zeros", Type::String, "0"), text("0"));
+    assert_eq!(normalize("zeros", Type::String, "00.5"), text("0.5"));
+    assert_eq!(normalize("zeros", Type::String, "-x"), text("-x"));
+    assert_eq!(normalize("zeros", Type::String, "0x"), text("0x"));
+    assert_eq!(normalize("lower", Type::String, "ABC"), text("abc"));
+    assert_eq!(normalize("trim, zeros", Type::String, " 042 "), text("42"));
+    assert_eq!(normalize("zeros, trim", Type::String, " 042 "), text("042")); // in order
+    assert_eq!(normalize("nfc", Type::String, "e\u{301}"), text("\u{e9}"));
+    assert_eq!(normalize("number", Type::String, "42"), Value::Integer(42));
+    assert_eq!(normalize("number", Type::String, "x"), text("x"));
+    assert_eq!(normalize("trim", Type::Integer, " 42 "), Value::Integer(42));
+    assert_eq!(normalize("trim", Type::String, ""), Value::Null);
+    assert!(Normalizer::parse("upper").is_err());
+  }
+}
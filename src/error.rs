@@ -1,8 +1,6 @@
 use std::io;
 use std::fmt;
 
-use csv;
-
 use crate::csvql::query;
 
 #[derive(Debug)]
@@ -25,6 +23,7 @@ impl fmt::Display for ArgumentError {
 }
 
 #[derive(Debug)]
+#[allow(clippy::enum_variant_names)]
 pub enum Error {
   IOError(io::Error),
   CsvError(csv::Error),
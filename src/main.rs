@@ -1,9 +1,11 @@
+
 mod csvql;
 mod error;
 
 use std::io;
 use std::fs;
 use std::process;
+use std::collections::HashMap;
 
 use clap::Parser;
 
@@ -11,8 +13,12 @@ use csvql::query;
 use csvql::query::frame;
 use csvql::query::frame::Frame;
 use csvql::query::select;
-use csvql::query::select::Selector;
 use csvql::query::schema;
+use csvql::query::sink;
+use csvql::query::sort;
+use csvql::query::value;
+use csvql::query::definition;
+use csvql::query::dialect;
 
 #[derive(Parser, Debug, Clone)]
 #[clap(author, version, about, long_about = None)]
@@ -21,15 +27,65 @@ pub struct Options {
   pub debug: bool,
   #[clap(long, help="Enable verbose output")]
   pub verbose: bool,
-  #[clap(long, help="Join inputs on the specified column")]
+  #[clap(long, help="Join inputs on the specified column. Joining columns of types whose values do not compare, such as an integer and a string, are compared as text")]
   pub join: Option<String>,
-  #[clap(long="sort:read", help="Sort input on the specified column")]
-  pub sort_read: Option<String>,
-  #[clap(long="sort:write", help="Sort output data on the specified column")]
+  #[clap(long="join:type", help="Join inputs with this kind of join: inner, left, right, full, semi or anti [default: full]")]
+  pub join_type: Option<String>,
+  #[clap(long="join:using", help="Merge the joining columns of each pair of joined inputs into a single column, which holds whichever value is present")]
+  pub join_using: bool,
+  #[clap(long="join:normalize", help="Normalize joining values before they are compared, with a comma-separated list of: lower, trim, zeros, number or nfc")]
+  pub join_normalize: Option<String>,
+  #[clap(long="join:asof", help="As-of join each pair of inputs: the last condition relating them pairs each left row with the right row whose value is the greatest at or below the left value")]
+  pub join_asof: bool,
+  #[clap(long="join:tolerance", help="Limit an as-of join to right values no further below the left value than this number, or duration in s, m, h, d or w")]
+  pub join_tolerance: Option<String>,
+  #[clap(long="join:strategy", default_value="auto", help="Join inputs with this strategy: merge, which sorts both inputs; hash, which indexes the smaller input in memory; or auto, which chooses based on the size of the inputs")]
+  pub join_strategy: String,
+  #[clap(long="join:hash-limit", help="Index an input in memory when the join strategy is auto only if its data is no larger than this many bytes as CSV text, as in: 256M. Indexed rows occupy several times as much memory [default: 64M]")]
+  pub join_hash_limit: Option<String>,
+  #[clap(long="where", help="Report only rows which match the specified predicate")]
+  pub filter: Option<String>,
+  #[clap(long, help="Group rows on the specified columns")]
+  pub group: Option<String>,
+  #[clap(long, help="Compute aggregates over each group, as in: count(*), sum(<column>)")]
+  pub aggregate: Option<String>,
+  #[clap(long="sort:write", help="Sort output data on the specified columns, as in: <column> [asc|desc] [nulls first|last], ...")]
   pub sort_write: Option<String>,
-  #[clap(long, help="Select columns to report")]
+  #[clap(long="type", help="Declare the type of a column, as in: <column>=<type>")]
+  pub types: Vec<String>,
+  #[clap(long, help="Declare the columns of an input with a TOML or JSON schema file, as in: <input>=<path>")]
+  pub schema: Vec<String>,
+  #[clap(long, help="Read CSV inputs in this dialect, which an input may override by appending settings to its path, as in: orders=orders.tsv:delim=tab. Settings are separated by colons: delim, quote, escape, doublequote, comment and terminator")]
+  pub dialect: Option<String>,
+  #[clap(long="no-header", help="Read an input which has no header row, naming its columns c1, c2, ... or as supplied, as in: <input> or <input>=<name>,<name>,...")]
+  pub no_header: Vec<String>,
+  #[clap(long, help="Infer column types by sampling input rows")]
+  pub infer: bool,
+  #[clap(long="infer:rows", default_value="1000", help="Sample this many rows when inferring column types")]
+  pub infer_rows: usize,
+  #[clap(long, help="Describe the schema of each input, with inferred column types, and exit")]
+  pub describe: bool,
+  #[clap(long="sort:memory", help="Spill sorted rows to disk when they exceed this many bytes of memory, as in: 512M")]
+  pub sort_memory: Option<String>,
+  #[clap(long="sort:temp", help="Spill sorted rows to this directory")]
+  pub sort_temp: Option<String>,
+  #[clap(long, help="Select columns to report, or compute them from expressions, as in: a.qty * a.price as total, upper(b.name) as name; or match columns with wildcards and patterns, as in: a.*, * except (a.secret), b./^amt_/. Names which are not valid in expressions are quoted within them, as in: upper(a.\"order-id\")")]
   pub select: Vec<String>,
-  #[clap(help="Document to open")]
+  #[clap(long="header:style", default_value="alias", help="Name output columns in this style: alias, which uses the alias of a column if it has one and otherwise its qualified name; bare, which uses the alias or otherwise the name without its input; or qualified, which always uses the qualified name")]
+  pub header_style: String,
+  #[clap(long, default_value="csv", help="Write output in this format: csv; json, an array of objects keyed by column; or ndjson, one object per line. JSON values are typed by their columns; empty fields are null in typed columns, but empty strings in string columns, including those of rows an outer join leaves unmatched")]
+  pub format: String,
+  #[clap(long="output:dialect", help="Write output in this CSV dialect, with settings separated by colons, as in: delim=tab:terminator=crlf")]
+  pub output_dialect: Option<String>,
+  #[clap(long="output:quoting", default_value="necessary", help="Quote output fields in this style: always; necessary, which quotes only fields that contain a delimiter, quote or terminator; nonnumeric; or never")]
+  pub output_quoting: String,
+  #[clap(long="output:no-header", help="Omit the header row from output")]
+  pub output_no_header: bool,
+  #[clap(long="output:bom", help="Begin output with a UTF-8 byte order mark, as some spreadsheets expect")]
+  pub output_bom: bool,
+  #[clap(long, short='q', help="Evaluate a query against the input documents")]
+  pub query: Option<String>,
+  #[clap(help="Document to open, which is read as JSON if its extension is .json, .ndjson or .jsonl, and otherwise as CSV. JSON documents are read into memory whole")]
   pub docs: Vec<String>,
 }
 
@@ -43,92 +99,301 @@ fn main() {
   };
 }
 
+#[allow(clippy::redundant_field_names)]
 fn cmd() -> Result<(), error::Error> {
   let opts = Options::parse();
   
+  let mut types: Vec<(schema::QName, value::Type)> = Vec::new();
+  for s in &opts.types {
+    types.push(parse_type(s)?);
+  }
+  
+  let mut sort_opts = sort::SortOptions::default();
+  if let Some(size) = &opts.sort_memory {
+    sort_opts.memory = Some(parse_size(size)?);
+  }
+  if let Some(dir) = &opts.sort_temp {
+    sort_opts.temp_dir = dir.into();
+  }
+  
+  let header_style = schema::Header::parse(&opts.header_style)?;
+  
+  let plan_opts = query::plan::Options{
+    sort: sort_opts.clone(),
+    strategy: query::plan::Strategy::parse(&opts.join_strategy)?,
+    hash_limit: match &opts.join_hash_limit {
+      Some(size) => parse_size(size)? as u64,
+      None => query::plan::HASH_LIMIT,
+    },
+  };
+  
+  let mut defs: HashMap<String, definition::Definition> = HashMap::new();
+  for s in &opts.schema {
+    let (alias, path) = parse_source(s);
+    defs.insert(alias.to_owned(), definition::Definition::load(path)?);
+  }
+  
+  let mut hdrs: HashMap<String, frame::Headers> = HashMap::new();
+  for s in &opts.no_header {
+    let (alias, hdr) = match s.split_once('=') {
+      Some((alias, names)) => (alias, frame::Headers::Names(names.split(',').map(|e| { e.trim().to_owned() }).collect())),
+      None => (s.as_str(), frame::Headers::Positional),
+    };
+    hdrs.insert(alias.to_owned(), hdr);
+  }
+  
+  let csv_opts = frame::CsvOptions{
+    infer: if opts.infer || opts.describe { Some(opts.infer_rows) } else { None },
+    headers: frame::Headers::Read,
+    definition: None,
+    types: types.clone(),
+    dialect: match &opts.dialect {
+      Some(text) => dialect::Dialect::default().with_settings(text)?,
+      None => dialect::Dialect::default(),
+    },
+    size: None,
+  };
+  
   let mut frms: Vec<Box<dyn Frame>> = Vec::new();
   for s in &opts.docs {
-    let (alias, path) = parse_source(&s);
-    let (name, input): (&str, Box<dyn io::Read>) = if path == "-" {
-      (alias, Box::new(io::stdin()))
+    let (s, settings) = dialect::Dialect::split(s);
+    let (alias, path) = parse_source(s);
+    let (name, input, size): (&str, Box<dyn io::Read>, Option<u64>) = if path == "-" {
+      (alias, Box::new(io::stdin()), None)
     }else{
-      (alias, Box::new(fs::File::open(path)?))
+      let file = fs::File::open(path)?;
+      let size = file.metadata()?.len();
+      (alias, Box::new(file), Some(size))
+    };
+    let headers = hdrs.remove(name);
+    if is_json(path) && (headers.is_some() || settings.is_some()) {
+      // JSON inputs name their columns by their keys
+      return Err(error::ArgumentError::new(&format!("A JSON input cannot be read with --no-header or dialect settings: {}", name)).into());
+    }
+    let csv_opts = frame::CsvOptions{
+      headers: headers.unwrap_or_default(),
+      definition: defs.remove(name),
+      dialect: match settings {
+        Some(text) => csv_opts.dialect.with_settings(text)?,
+        None => csv_opts.dialect,
+      },
+      size: size,
+      ..csv_opts.clone()
     };
-    // let mut raw = query::frame::Csv::new(&name, input)?;
-    // let frm: Box<dyn Frame> = if let Some(on) = &opts.sort_read {
-    //   Box::new(frame::Sorted::new(&mut raw, on)?)
-    // }else{
-    //   Box::new(raw)
-    // };
-    // frms.push(frm);
-    frms.push(Box::new(query::frame::Csv::new(&name, input)?));
+    if is_json(path) {
+      frms.push(Box::new(query::frame::Json::new(name, input, &csv_opts)?));
+    }else{
+      frms.push(Box::new(query::frame::Csv::new(name, input, &csv_opts)?));
+    }
+  }
+  
+  if !defs.is_empty() {
+    let mut unknown: Vec<&str> = defs.keys().map(|e| { e.as_str() }).collect();
+    unknown.sort();
+    return Err(error::ArgumentError::new(&format!("Schema declared for an unknown input: {}", unknown.join(", "))).into());
+  }
+  if !hdrs.is_empty() {
+    let mut unknown: Vec<&str> = hdrs.keys().map(|e| { e.as_str() }).collect();
+    unknown.sort();
+    return Err(error::ArgumentError::new(&format!("Unknown input declared without a header: {} (--no-header)", unknown.join(", "))).into());
+  }
+  
+  for (qname, _) in &types {
+    match qname.scope() {
+      // an input rejects a type for a column it does not have as it is read
+      Some(scope) => if !frms.iter().any(|e| { e.name() == scope }) {
+        return Err(error::ArgumentError::new(&format!("Declared type for a column of an unknown input: {}", qname)).into());
+      },
+      None => if !frms.iter().any(|e| { e.schema().index(qname).is_some() }) {
+        return Err(error::ArgumentError::new(&format!("Declared type for a column that no input has: {}", qname)).into());
+      },
+    }
+  }
+  
+  if opts.describe {
+    return describe(&opts, &frms);
+  }
+  
+  if let Some(text) = &opts.query {
+    let join = opts.join.is_some() || opts.join_type.is_some() || opts.join_using || opts.join_normalize.is_some() || opts.join_asof || opts.join_tolerance.is_some();
+    if join || opts.filter.is_some() || opts.group.is_some() || opts.aggregate.is_some() || opts.sort_write.is_some() || !opts.select.is_empty() {
+      return Err(error::ArgumentError::new("A query cannot be combined with --join or its options, --where, --group, --aggregate, --sort:write or --select").into());
+    }
+    let query = query::parser::parse(text)?;
+    if opts.verbose {
+      eprintln!(">>> {}", query);
+    }
+    return output(&opts, header_style, query::plan::plan(&query, frms, &plan_opts)?);
   }
   
   let frms = if let Some(on) = &opts.join {
     let join = select::Join::parse(on)?;
-    let (mut base, mut base_on): (Option<Box<dyn Frame>>, Option<&schema::QName>) = (None, None);
-    for mut frm in frms.into_iter() {
-      let on = match join.for_scope(frm.name()) {
-        Some(on) => on,
-        None => return Err(error::ArgumentError::new(&format!("No join expression matches input frame: {}", frm.name())).into()),
-      };
-      if let (Some(curr), Some(curr_on)) = (base, base_on) {
-        base = Some(Box::new(frame::OuterJoin::new(curr, curr_on, frame::Sorted::new(&mut frm, on)?, on)?));
-      }else{
-        base = Some(Box::new(frame::Sorted::new(&mut frm, on)?));
+    let join_opts = query::plan::JoinOptions{
+      kind: match &opts.join_type {
+        Some(kind) => query::ast::JoinKind::parse(kind)?,
+        None => query::ast::JoinKind::Full,
+      },
+      coalesce: opts.join_using,
+      normalize: match &opts.join_normalize {
+        Some(norm) => query::value::Normalizer::parse_list(norm)?,
+        None => Vec::new(),
+      },
+      asof: match (opts.join_asof, &opts.join_tolerance) {
+        (true, tolerance) => Some(query::ast::AsOf{
+          tolerance: tolerance.as_deref().map(query::value::Tolerance::parse).transpose()?,
+        }),
+        (false, Some(_)) => return Err(error::ArgumentError::new("A tolerance requires an as-of join: --join:asof").into()),
+        (false, None) => None,
+      },
+    };
+    for scope in join.scopes() {
+      if !frms.iter().any(|e| { e.name() == scope }) {
+        return Err(error::ArgumentError::new(&format!("Join expression references an unknown input: {}", scope)).into());
       }
-      base_on = Some(on);
     }
-    if let Some(base) = base {
-      vec![base]
+    
+    // inputs are joined in order, except that each is joined only once a
+    // condition relates it to an input already joined
+    let mut frms = frms;
+    if frms.is_empty() {
+      frms
     }else{
-      Vec::new()
+      let names: Vec<String> = frms.iter().map(|e| { e.name().to_owned() }).collect();
+      let order = join.order(&names.iter().map(|e| { e.as_str() }).collect::<Vec<&str>>())?;
+      let mut base = frms.remove(0);
+      for (name, left_on, right_on) in order {
+        let frm = match frms.iter().position(|e| { e.name() == name }) {
+          Some(index) => frms.remove(index),
+          None => return Err(error::ArgumentError::new(&format!("Input not found: {}", name)).into()),
+        };
+        base = query::plan::join(base, &left_on, frm, &right_on, &join_opts, &plan_opts)?;
+      }
+      vec![base]
     }
   }else{
     frms
   };
   
-  for mut frm in frms.into_iter() {
-    let frm: Box<dyn Frame> = if let Some(on) = &opts.sort_write {
-      Box::new(frame::Sorted::new(&mut frm, &schema::QName::parse(on)?)?)
+  let filter = match &opts.filter {
+    Some(text) => Some(query::parser::parse_expr(text)?),
+    None => None,
+  };
+  
+  let group = match &opts.group {
+    Some(text) => query::parser::parse_columns(text)?,
+    None => Vec::new(),
+  };
+  let aggs = match &opts.aggregate {
+    Some(text) => query::parser::parse_aggregates(text)?,
+    None => Vec::new(),
+  };
+  for frm in frms.into_iter() {
+    let frm: Box<dyn Frame> = if let Some(filter) = &filter {
+      Box::new(frame::Where::new(frm, filter)?)
     }else{
       frm
     };
     
-    let sel = if opts.select.len() > 0 {
-      Some(select::Columns::parse(frm.schema(), &opts.select)?)
+    let mut frm: Box<dyn Frame> = if !group.is_empty() || !aggs.is_empty() {
+      Box::new(frame::Aggregate::new(frm, &group, &aggs)?)
     }else{
-      None
+      frm
     };
     
-    let mut frm: Box<dyn Frame> = if let Some(sel) = &sel {
-      Box::new(frame::Filter::new(frm, sel.clone())?)
+    let frm: Box<dyn Frame> = if let Some(on) = &opts.sort_write {
+      Box::new(frame::Sorted::new_with_order(&mut frm, &query::parser::parse_order(on)?, &sort_opts)?)
     }else{
       frm
     };
     
-    if opts.verbose {
-      eprintln!(">>> {}", frm);
-    }
-    
-    let mut dst = csv::Writer::from_writer(io::stdout());
-    if let Some(sel) = &sel {
-      dst.write_record(&sel.select(&csv::StringRecord::from(frm.schema().record()))?)?;
+    let frm: Box<dyn Frame> = if !opts.select.is_empty() {
+      let cols = select::parse(frm.schema(), &opts.select)?;
+      let sel = select::projection(frm.schema(), &select::resolve(frm.schema(), &cols)?)?;
+      Box::new(frame::Filter::new(frm, sel)?)
     }else{
-      dst.write_record(frm.schema().record())?;
-    }
+      frm
+    };
     
-    for row in frm.rows() {
-      let row = row?;
-      dst.write_record(&row)?;
+    output(&opts, header_style, frm)?;
+  }
+  
+  Ok(())
+}
+
+fn describe(opts: &Options, frms: &[Box<dyn Frame>]) -> Result<(), error::Error> {
+  let mut dst = sink(opts, &["column".to_owned(), "type".to_owned()], &[value::Type::String, value::Type::String])?;
+  for frm in frms {
+    let schema = frm.schema();
+    for (i, col) in schema.columns().iter().enumerate() {
+      dst.write(&csv::StringRecord::from(vec![col.qname(), schema.column_type(i).to_string()]))?;
     }
-    
-    dst.flush()?;
+  }
+  dst.finish()?;
+  Ok(())
+}
+
+fn output(opts: &Options, header_style: schema::Header, mut frm: Box<dyn Frame>) -> Result<(), error::Error> {
+  if opts.verbose {
+    eprintln!(">>> {}", frm);
+  }
+  
+  let schema = frm.schema();
+  let header = schema.header(header_style)?;
+  let types: Vec<value::Type> = (0..schema.count()).map(|e| { schema.column_type(e) }).collect();
+  let mut dst = sink(opts, &header, &types)?;
+  
+  for row in frm.rows() {
+    let row = row?;
+    dst.write(&row)?;
   }
   
+  dst.finish()?;
   Ok(())
 }
 
+// The sink output is written to, which is provided the names and types
+// of the columns it is written
+fn sink(opts: &Options, names: &[String], types: &[value::Type]) -> Result<Box<dyn sink::Sink>, error::Error> {
+  let format = sink::Format::parse(&opts.format)?;
+  if format != sink::Format::Csv {
+    return Ok(Box::new(sink::Json::new(io::stdout(), names, types, format == sink::Format::Json)));
+  }
+  
+  let dialect = match &opts.output_dialect {
+    Some(text) => dialect::Dialect::default().with_settings(text)?,
+    None => dialect::Dialect::default(),
+  };
+  let builder = dialect.writer(dialect::Quoting::parse(&opts.output_quoting)?);
+  Ok(Box::new(sink::Csv::new(io::stdout(), &builder, if opts.output_no_header { None } else { Some(names) }, opts.output_bom)?))
+}
+
+fn parse_size(f: &str) -> Result<usize, error::Error> {
+  let f = f.trim();
+  let (num, mult) = match f.char_indices().last() {
+    Some((i, 'k')) | Some((i, 'K')) => (&f[..i], 1 << 10),
+    Some((i, 'm')) | Some((i, 'M')) => (&f[..i], 1 << 20),
+    Some((i, 'g')) | Some((i, 'G')) => (&f[..i], 1 << 30),
+    _ => (f, 1),
+  };
+  match num.trim().parse::<usize>().map(|e| { e.checked_mul(mult) }) {
+    Ok(Some(num)) => Ok(num),
+    Ok(None) => Err(error::ArgumentError::new(&format!("Invalid size, too large: {}", f)).into()),
+    Err(_) => Err(error::ArgumentError::new(&format!("Invalid size, expected a number of bytes with an optional K, M or G suffix: {}", f)).into()),
+  }
+}
+
+fn parse_type(f: &str) -> Result<(schema::QName, value::Type), error::Error> {
+  match f.split_once('=') {
+    Some((qname, typ)) => Ok((schema::QName::parse(qname.trim())?, value::Type::parse(typ)?)),
+    None => Err(error::ArgumentError::new(&format!("Invalid type declaration, expected <column>=<type>: {}", f)).into()),
+  }
+}
+
+fn is_json(path: &str) -> bool {
+  let ext = std::path::Path::new(path).extension().and_then(|e| { e.to_str() }).map(|e| { e.to_lowercase() });
+  matches!(ext.as_deref(), Some("json") | Some("ndjson") | Some("jsonl"))
+}
+
 fn parse_source<'a>(f: &'a str) -> (&'a str, &'a str) {
   let split: Vec<&'a str> = f.splitn(2, "=").collect();
   match split.len() {